@@ -0,0 +1,88 @@
+//! Benchmarks of the tokenization of a file into the words to probe, which runs over every
+//! byte of every crawled file.
+//!
+//! Run with `cargo bench --bench tokenize`, and with `--features tree-sitter` to compare
+//! `tokenize` with the grammars to `get_words`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use universal_lsif::{
+    configs::language_configs,
+    crawler::{get_words, tokenize},
+    lsp::PositionEncoding,
+};
+
+/// Source files of this crate, from a few hundred lines to a few tens of thousands.
+fn sources() -> Vec<(&'static str, String)> {
+    let medium = include_str!("../src/crawler.rs");
+    vec![
+        ("small", include_str!("../src/uri.rs").to_string()),
+        ("medium", medium.to_string()),
+        ("large", medium.repeat(20)),
+    ]
+}
+
+/// A long line of words with characters outside of the ASCII range, where the UTF-16
+/// column of each word differs from its byte offset.
+fn non_ascii_line(words: usize) -> String {
+    ["naïve", "日本語", "café", "emoji_🦀", "x"]
+        .iter()
+        .cycle()
+        .take(words)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn bench_get_words(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_words");
+    for (name, text) in sources() {
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16] {
+            group.bench_with_input(
+                BenchmarkId::new(encoding.as_str(), name),
+                &text,
+                |b, text| b.iter(|| get_words(text.clone(), encoding)),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_tokenize(c: &mut Criterion) {
+    let configs = language_configs();
+    let config = &configs["rust"];
+    let mut group = c.benchmark_group("tokenize");
+    for (name, text) in sources() {
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        group.bench_with_input(BenchmarkId::new("rust", name), &text, |b, text| {
+            b.iter(|| tokenize(text.clone(), config, PositionEncoding::Utf16))
+        });
+    }
+    group.finish();
+}
+
+/// The columns of the words of a single line. Computing each column from the start of the
+/// line is quadratic in its length, which shows as the time per word growing with it.
+fn bench_utf16_columns(c: &mut Criterion) {
+    let mut group = c.benchmark_group("utf16_columns");
+    for words in [10, 100, 1000] {
+        let line = non_ascii_line(words);
+        group.throughput(Throughput::Elements(words as u64));
+        for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16] {
+            group.bench_with_input(
+                BenchmarkId::new(encoding.as_str(), words),
+                &line,
+                |b, line| b.iter(|| get_words(line.clone(), encoding)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_get_words,
+    bench_tokenize,
+    bench_utf16_columns
+);
+criterion_main!(benches);
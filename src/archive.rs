@@ -0,0 +1,97 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use tempfile::TempDir;
+
+use crate::Result;
+
+/// A `.tar.gz` snapshot of a project given with `--from-archive`, extracted to a temporary
+/// directory that is removed when this is dropped.
+pub struct ExtractedArchive {
+    dir: TempDir,
+}
+
+impl ExtractedArchive {
+    pub fn extract(archive: &Path) -> Result<Self> {
+        let file = File::open(archive)
+            .with_context(|| format!("Could not open the archive `{}`", archive.display()))?;
+        let dir = tempfile::Builder::new()
+            .prefix("universal-lsif-")
+            .tempdir()
+            .context("Could not create a directory to extract the archive to")?;
+        tar::Archive::new(GzDecoder::new(file))
+            .unpack(dir.path())
+            .with_context(|| format!("Could not extract the archive `{}`", archive.display()))?;
+        Ok(Self { dir })
+    }
+
+    /// Returns the root of the project: the only directory at the top of the archive, as in
+    /// `git archive --prefix` snapshots, or the top of the archive itself.
+    pub fn project_root(&self) -> PathBuf {
+        let entries = std::fs::read_dir(self.dir.path())
+            .map(|entries| entries.filter_map(|e| e.ok()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        match &entries[..] {
+            [entry] if entry.path().is_dir() => entry.path(),
+            _ => self.dir.path().to_path_buf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::ExtractedArchive;
+
+    /// Writes a `.tar.gz` archive with the given files in `dir` and returns its path.
+    fn archive(dir: &Path, name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let path = dir.join(name);
+        let encoder = GzEncoder::new(std::fs::File::create(&path).unwrap(), Compression::fast());
+        let mut builder = tar::Builder::new(encoder);
+        for (file, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, file, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_project_root_of_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = archive(
+            dir.path(),
+            "prefixed.tar.gz",
+            &[("repo-abc123/src/main.py", "x = 1\n")],
+        );
+        let extracted = ExtractedArchive::extract(&path).unwrap();
+        let root = extracted.project_root();
+        assert!(root.ends_with("repo-abc123"));
+        assert!(root.join("src/main.py").is_file());
+
+        let path = archive(
+            dir.path(),
+            "flat.tar.gz",
+            &[("main.py", "x = 1\n"), ("lib.py", "y = 2\n")],
+        );
+        let extracted = ExtractedArchive::extract(&path).unwrap();
+        let root = extracted.project_root();
+        assert!(root.join("main.py").is_file());
+
+        drop(extracted);
+        assert!(!root.exists());
+        assert!(ExtractedArchive::extract(Path::new("/nonexistent.tar.gz")).is_err());
+    }
+}
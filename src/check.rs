@@ -0,0 +1,111 @@
+use std::path::Path;
+
+use crate::{
+    dump::reader::read_dump,
+    protocol::types::{Edge, Element, Entry, Item, Vertex},
+    ret_error, Result,
+};
+
+/// Counts of the elements that `--check` requires to be present in a dump.
+#[derive(Debug, Default, PartialEq)]
+pub struct DumpSummary {
+    pub definitions: usize,
+    pub references: usize,
+    pub contains: usize,
+}
+
+impl DumpSummary {
+    fn add(&mut self, entry: &Entry) {
+        match &entry.data {
+            Element::Vertex(Vertex::DefinitionResult(_)) => self.definitions += 1,
+            Element::Edge(Edge::Item(Item::Reference(_))) => self.references += 1,
+            Element::Edge(Edge::Contains(_)) => self.contains += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Reads the dump in the given files, e.g. the shards of a dump in order, and returns an
+/// error unless it contains at least one definition, one reference and one contains edge.
+pub fn check_dump<P: AsRef<Path>>(paths: &[P]) -> Result<DumpSummary> {
+    let mut summary = DumpSummary::default();
+    for_each_entry(paths, |entry| summary.add(entry))?;
+
+    if summary.definitions == 0 {
+        ret_error!("The dump does not contain any definitions");
+    }
+    if summary.references == 0 {
+        ret_error!("The dump does not contain any references");
+    }
+    if summary.contains == 0 {
+        ret_error!("The dump does not contain any contains edges");
+    }
+
+    Ok(summary)
+}
+
+/// Calls `f` with each entry of the dump in the given files, in order. Each file is either
+/// JSON lines or a JSON array, as written with `--output-format`.
+pub fn for_each_entry<P: AsRef<Path>>(paths: &[P], mut f: impl FnMut(&Entry)) -> Result<()> {
+    for entry in read_dump(paths) {
+        f(&entry?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        path::{Path, PathBuf},
+    };
+
+    use crate::protocol::types::{DefinitionResult, Edge, Element, Entry, NumberOrString};
+
+    use super::{check_dump, DumpSummary};
+
+    fn write_dump(dir: &Path, name: &str, elements: Vec<Element>) -> PathBuf {
+        let path = dir.join(name);
+        let mut file = std::fs::File::create(&path).unwrap();
+        for (id, data) in elements.into_iter().enumerate() {
+            let entry = Entry {
+                id: NumberOrString::Number(id as u64 + 1),
+                data,
+            };
+            writeln!(file, "{}", serde_json::to_string(&entry).unwrap()).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn test_check_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let complete = write_dump(
+            dir.path(),
+            "complete.json",
+            vec![
+                Element::Vertex(DefinitionResult {}.into()),
+                Element::Edge(Edge::ref_item(1, vec![2], 3)),
+                Element::Edge(Edge::contains(3, vec![2])),
+            ],
+        );
+        assert_eq!(
+            check_dump(&[&complete]).unwrap(),
+            DumpSummary {
+                definitions: 1,
+                references: 1,
+                contains: 1
+            }
+        );
+
+        let missing_refs = write_dump(
+            dir.path(),
+            "missing-refs.json",
+            vec![
+                Element::Vertex(DefinitionResult {}.into()),
+                Element::Edge(Edge::contains(3, vec![2])),
+            ],
+        );
+        assert!(check_dump(&[&missing_refs]).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    crawler::{Definition, DocumentExtras, Reference},
+    Result,
+};
+
+/// The progress of a crawl, saved periodically so that an interrupted run can be resumed
+/// with `--resume` without querying the language server for the files it already crawled.
+///
+/// The checkpoint file has a line of JSON for each crawled file. A save only appends the
+/// files crawled since the previous one, so that saving does not get slower as the run goes
+/// on. A checkpoint that is dropped with unsaved files, e.g. when the run fails, saves them.
+///
+/// The dump itself is regenerated from the recorded definitions and references when the
+/// crawl finishes, so the checkpoint does not need to contain any emitted IDs.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    pub processed_files: HashSet<PathBuf>,
+    /// The files crawled since the last save
+    unsaved: Vec<CheckpointEntry>,
+    /// Whether the checkpoint file is of another run and is replaced by the first save
+    replace: bool,
+}
+
+/// A crawled file and what was found in it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CheckpointEntry {
+    pub file: PathBuf,
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+    pub extras: Option<DocumentExtras>,
+}
+
+impl Checkpoint {
+    /// Starts a new checkpoint at the given path.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            processed_files: HashSet::new(),
+            unsaved: Vec::new(),
+            replace: true,
+        }
+    }
+
+    /// Continues the checkpoint at the given path, and returns the files that it records.
+    /// A last line that was only partly written, e.g. by a crash while saving, is dropped.
+    pub fn load(path: PathBuf) -> Result<(Self, Vec<CheckpointEntry>)> {
+        let file = File::open(&path)
+            .with_context(|| format!("Could not open the checkpoint `{}`", path.display()))?;
+        let mut entries = Vec::new();
+        let mut valid_len = 0;
+        let mut lines = BufReader::new(file).split(b'\n').peekable();
+        while let Some(line) = lines.next() {
+            let line = line.context("Could not read the checkpoint")?;
+            match serde_json::from_slice::<CheckpointEntry>(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) if lines.peek().is_none() => break,
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("Could not read the checkpoint `{}`", path.display())
+                    })
+                }
+            }
+            valid_len += line.len() as u64 + 1;
+        }
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_len(valid_len))
+            .context("Could not repair the checkpoint file")?;
+
+        let checkpoint = Self {
+            processed_files: entries.iter().map(|entry| entry.file.clone()).collect(),
+            path,
+            unsaved: Vec::new(),
+            replace: false,
+        };
+        Ok((checkpoint, entries))
+    }
+
+    /// Records a crawled file, to be written by the next save.
+    pub fn record(&mut self, entry: CheckpointEntry) {
+        self.processed_files.insert(entry.file.clone());
+        self.unsaved.push(entry);
+    }
+
+    /// Returns the number of files recorded since the last save.
+    pub fn unsaved(&self) -> usize {
+        self.unsaved.len()
+    }
+
+    /// Appends the files crawled since the last save to the checkpoint file, and syncs it
+    /// so that they are not lost in a crash.
+    pub fn save(&mut self) -> Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(!self.replace)
+            .write(true)
+            .truncate(self.replace)
+            .open(&self.path)
+            .context("Could not open the checkpoint file")?;
+        let mut writer = BufWriter::new(file);
+        for entry in &self.unsaved {
+            serde_json::to_writer(&mut writer, entry)
+                .context("Could not write the checkpoint file")?;
+            writer.write_all(b"\n")?;
+        }
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())
+            .and_then(|file| file.sync_data())
+            .context("Could not write the checkpoint file")?;
+        self.unsaved.clear();
+        self.replace = false;
+        Ok(())
+    }
+
+    /// Removes the checkpoint file, once the run does not need to be resumed.
+    pub fn remove(mut self) -> Result<()> {
+        self.unsaved.clear();
+        if self.path.exists() {
+            std::fs::remove_file(&self.path).context("Could not remove the checkpoint file")?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Checkpoint {
+    fn drop(&mut self) {
+        if !self.unsaved.is_empty() {
+            if let Err(err) = self.save() {
+                log::warn!("Could not save the checkpoint: {:#}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::PathBuf};
+
+    use super::{Checkpoint, CheckpointEntry};
+    use crate::crawler::{Definition, Location, Range};
+
+    fn entry(file: &str) -> CheckpointEntry {
+        CheckpointEntry {
+            file: PathBuf::from(file),
+            definitions: vec![Definition {
+                location: Location {
+                    file_path: format!("file://{}", file),
+                    range: Range::new(Default::default()),
+                },
+                node_name: "main".into(),
+                comment: Some("fn main()".into()),
+                symbol: None,
+                resolved_range: None,
+                declaration: None,
+                exported: false,
+                renameable: None,
+            }],
+            ..CheckpointEntry::default()
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.lsif.checkpoint");
+        std::fs::write(&path, "of another run\n").unwrap();
+
+        let mut checkpoint = Checkpoint::new(path.clone());
+        checkpoint.record(entry("/repo/src/main.rs"));
+        checkpoint.save().unwrap();
+        checkpoint.record(entry("/repo/src/lib.rs"));
+        assert_eq!(checkpoint.unsaved(), 1);
+        // Saved when dropped
+        drop(checkpoint);
+
+        let (checkpoint, entries) = Checkpoint::load(path.clone()).unwrap();
+        assert_eq!(checkpoint.processed_files.len(), 2);
+        let files = entries
+            .iter()
+            .map(|e| e.file.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec!["/repo/src/main.rs", "/repo/src/lib.rs"]);
+        assert_eq!(
+            entries[1].definitions[0].comment.as_deref(),
+            Some("fn main()")
+        );
+        assert!(entries[1].references.is_empty());
+        checkpoint.remove().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_checkpoint_drops_a_partly_written_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.lsif.checkpoint");
+        let mut checkpoint = Checkpoint::new(path.clone());
+        checkpoint.record(entry("/repo/src/main.rs"));
+        checkpoint.save().unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(br#"{"file": "/repo/src/li"#).unwrap();
+
+        let (mut checkpoint, entries) = Checkpoint::load(path.clone()).unwrap();
+        assert_eq!(entries.len(), 1);
+        checkpoint.record(entry("/repo/src/lib.rs"));
+        checkpoint.save().unwrap();
+        let (_, entries) = Checkpoint::load(path).unwrap();
+        assert_eq!(entries.len(), 2);
+    }
+}
@@ -1,8 +1,23 @@
-use std::path::{Component, Path, PathBuf};
+use std::{
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
 
+use anyhow::Context;
+use languageserver_types::{ClientCapabilities, MarkupKind, Position, Range, TraceOption};
+use serde_json::Value;
 use structopt::StructOpt;
 
-use crate::protocol::types::Language;
+use crate::{
+    emitter::{
+        emit_order::EmitOrder,
+        file_emitter::{FlushPolicy, OutputFormat, Shards},
+    },
+    error,
+    overlay::Overlay,
+    protocol::types::{Language, MonikerUnique},
+    ret_error, Result,
+};
 
 /// Represents the command-line arguments.
 #[derive(Clone, Debug, StructOpt)]
@@ -11,46 +26,751 @@ use crate::protocol::types::Language;
     about = "An LSIF indexer for every language (use --langs to see supported language)"
 )]
 pub struct Args {
-    /// Command for starting the language server.
-    /// This client and the server communicate over stdin.
-    pub init_server_command: String,
     /// Specifies the language
     pub language: String,
-    /// Optional arguments for running the language server.
+    /// Command for starting the language server, or the language's default command if not
+    /// present. This client and the server communicate over stdin.
+    #[structopt(short = "c", long = "server-command")]
+    pub init_server_command: Option<String>,
+    /// Optional arguments for running the language server, or the language's default
+    /// arguments if not present.
     #[structopt(short, long)]
     pub server_args: Option<String>,
+    /// Talk to a language server that is already running over this process' stdin and
+    /// stdout, instead of spawning one. It is initialized, but never shut down.
+    #[structopt(long, conflicts_with_all = &["server-command", "attach-socket", "check"])]
+    pub attach_stdio: bool,
+    /// Connect to a language server that is already running and listening on the given TCP
+    /// address, e.g. `127.0.0.1:9257`, instead of spawning one.
+    #[structopt(long, conflicts_with = "server-command")]
+    pub attach_socket: Option<String>,
     /// Path to the root of the project, or the current directory if not present.
     #[structopt(parse(from_os_str))]
     pub project_root: Option<PathBuf>,
-    /// The output file, `dump.json` if not present.
+    /// Index the project in the given `.tar.gz` archive instead of `project-root`. It is
+    /// extracted to a temporary directory, which is the project root of the dump and is
+    /// removed afterwards. The output is written to the current directory by default.
+    #[structopt(long, parse(from_os_str), conflicts_with = "project-root")]
+    pub from_archive: Option<PathBuf>,
+    /// Follow symlinks when looking for files to index. Symlink loops are skipped.
+    #[structopt(long)]
+    pub follow_symlinks: bool,
+    /// Also index the files without one of the language's extensions whose first line is a
+    /// shebang of one of its interpreters, e.g. `#!/usr/bin/env python3`. Reads the start of
+    /// every other file of the project.
+    #[structopt(long)]
+    pub detect_shebangs: bool,
+    /// Also index the files ignored by git, e.g. generated files in an ignored build
+    /// directory. The `.lsifignore` files still apply.
+    #[structopt(long)]
+    pub no_gitignore: bool,
+    /// Index only the given file of the project instead of every file in it. The files that
+    /// its references point to are not crawled, so those references are linked to `import`
+    /// monikers, as for the definitions outside of the project.
+    #[structopt(long, parse(from_os_str))]
+    pub file: Option<PathBuf>,
+    /// Index only N files of the project picked at random, e.g. to try a server setup
+    /// before a long run. The same `--seed` picks the same files.
+    #[structopt(long, value_name = "N", conflicts_with = "file")]
+    pub sample: Option<usize>,
+    /// The seed of the random choice of files with `--sample`.
+    #[structopt(long, default_value = "0")]
+    pub seed: u64,
+    /// A name for the project, recorded in the dump's metadata.
+    #[structopt(long)]
+    pub project_name: Option<String>,
+    /// Record how the dump was made in its metadata: the arguments and version of the
+    /// indexer, the server command, the time and the OS. The values of `--server-env` and
+    /// `--initialization-options` are left out.
+    #[structopt(long)]
+    pub record_invocation: bool,
+    /// The output file, relative to the current directory, or `dump.json` in the project
+    /// root if not present.
     #[structopt(short, long, parse(from_os_str))]
     pub output: Option<PathBuf>,
+    /// The format of the output: `jsonl`, one entry per line, or `json-array`, a single JSON
+    /// array.
+    #[structopt(long, default_value = "jsonl", parse(try_from_str = parse_output_format))]
+    pub output_format: OutputFormat,
+    /// The order of the ranges and their result sets in the output: `as-emitted`, the order in
+    /// which they are indexed, `spec-default`, the result sets of each document before its
+    /// ranges as in the LSIF specification, or `ranges-first`, the ranges of each document
+    /// before their result sets. The last two hold each document back until its `contains`
+    /// edge, which is emitted at the end, so they keep most of the dump in memory. See
+    /// `EmitOrder` for the consumers that need each.
+    #[structopt(long, default_value = "as-emitted", parse(try_from_str = parse_emit_order))]
+    pub emit_order: EmitOrder,
+    /// Split the output into `dump.000.json`, `dump.001.json`, ... The only supported value
+    /// is `size`, for shards of at most `--shard-size` megabytes.
+    #[structopt(long, parse(try_from_str = parse_shard_by))]
+    pub shard_by: Option<ShardBy>,
+    /// The maximum size of a shard with `--shard-by size`, in megabytes.
+    #[structopt(long, default_value = "1024")]
+    pub shard_size: u64,
+    /// The maximum number of `textDocument/definition` requests to keep in flight at once.
+    #[structopt(long, default_value = "1")]
+    pub concurrency_limit: usize,
+    /// Send at most N `textDocument/definition` requests per second, for servers that do not
+    /// cope with a flood of requests. Unlimited if not given.
+    #[structopt(long, parse(try_from_str = parse_max_requests_per_second))]
+    pub max_requests_per_second: Option<u64>,
+    /// The number of threads of the thread pool used for parallel work. Uses one per CPU if 0.
+    #[structopt(long, default_value = "0")]
+    pub threads: usize,
+    /// The maximum number of definitions and references to queue for the indexer before the
+    /// crawler waits for it to catch up.
+    #[structopt(long, default_value = "4096")]
+    pub channel_capacity: usize,
+    /// A word to skip in addition to the language's keywords. Can be repeated.
+    #[structopt(long = "skip-word", number_of_values = 1)]
+    pub skip_words: Vec<String>,
+    /// A file containing words to skip in addition to the language's keywords, separated
+    /// by whitespace. Lines starting with `#` are ignored.
+    #[structopt(long, parse(from_os_str))]
+    pub skip_words_file: Option<PathBuf>,
+    /// Index the bundled `src/tests/test_data/<language>` project (unless a project root is
+    /// given) and verify that the dump contains definitions, references and contains edges.
+    #[structopt(long)]
+    pub check: bool,
+    /// A workspace folder to send to the language server, for projects with multiple roots.
+    /// Can be repeated. Only the project root is sent if not present.
+    #[structopt(long = "workspace-folder", parse(from_os_str), number_of_values = 1)]
+    pub workspace_folders: Vec<PathBuf>,
+    /// Save a checkpoint next to the output file every N files, so that an interrupted run
+    /// can be continued with `--resume`. The files crawled since the last save are also saved
+    /// if the run fails. Disabled if 0.
+    #[structopt(long, default_value = "0")]
+    pub checkpoint_every: usize,
+    /// Send at most N definition requests per file, so that a huge generated file does not
+    /// dominate the run. The remaining words of the file are skipped. Unlimited if 0.
+    #[structopt(long, default_value = "0")]
+    pub max_requests_per_file: usize,
+    /// Continue from the checkpoint saved by a previous run with `--checkpoint-every` or
+    /// `--resume`. The checkpoint is kept up to date as with `--checkpoint-every`, but is only
+    /// saved when the run fails or stops, if that is not given.
+    #[structopt(long)]
+    pub resume: bool,
+    /// Only probe the words that the language server's semantic tokens classify as
+    /// identifiers, if it supports `textDocument/semanticTokens`.
+    #[structopt(long)]
+    pub semantic_tokens: bool,
+    /// The language id of the files with an extension, as `EXT=ID`, e.g. `tsx=typescriptreact`.
+    /// Overrides the defaults of the language. Can be repeated.
+    #[structopt(long = "language-id-map", parse(try_from_str = parse_key_value), number_of_values = 1)]
+    pub language_id_map: Vec<(String, String)>,
+    /// Only index the files with these extensions of the language, e.g. `ts,tsx`.
+    #[structopt(long, use_delimiter = true)]
+    pub only_extensions: Vec<String>,
+    /// Only emit definitions, without references. Definitions are taken from
+    /// `textDocument/documentSymbol` if the language server supports it, without probing
+    /// every word.
+    #[structopt(long)]
+    pub defs_only: bool,
+    /// How references are found: `probe` sends a `textDocument/definition` request for every
+    /// word, and `references` takes the definitions from `textDocument/documentSymbol` and
+    /// sends a `textDocument/references` request for each. `references` sends far fewer
+    /// requests, but misses the definitions that are not document symbols, e.g. local
+    /// variables with some servers, and the references to them. `workspace-symbols` is the
+    /// same as `references`, with the definitions of all files from a single `workspace/symbol`
+    /// request, so that the files without symbols are not opened. The files whose symbols the
+    /// server may have cut fall back to `textDocument/documentSymbol`.
+    #[structopt(long, default_value = "probe", parse(try_from_str = parse_strategy))]
+    pub strategy: Strategy,
+    /// The number of symbols at which the language server cuts a `workspace/symbol` response,
+    /// with `--strategy workspace-symbols`. A response with that many symbols is taken as
+    /// incomplete. Defaults to the known limit of the default server of the language, if any.
+    #[structopt(long)]
+    pub workspace_symbol_limit: Option<usize>,
+    /// Moves the positions of the definition and references requests by `LINES:COLUMNS`, e.g.
+    /// `0:1`. A workaround for servers that only resolve a word one column after its start.
+    /// The ranges of the responses are kept as they are, unless
+    /// `--position-adjust-responses` is given.
+    #[structopt(long, parse(try_from_str = parse_position_adjust), allow_hyphen_values = true)]
+    pub position_adjust: Option<PositionAdjust>,
+    /// Also moves the ranges of the definition and references responses back by
+    /// `--position-adjust`, for servers that count positions from 1 against the protocol,
+    /// with `--position-adjust 1:1`.
+    #[structopt(long, requires = "position-adjust")]
+    pub position_adjust_responses: bool,
+    /// Also emit the folding ranges and document links of each document, and whether each
+    /// definition can be renamed, if the language server supports them.
+    #[structopt(long)]
+    pub extras: bool,
+    /// Also emit the diagnostics that the language server publishes for each document.
+    #[structopt(long)]
+    pub include_diagnostics: bool,
+    /// Also emit the byte offsets of each range in its document, as `offsets` next to its
+    /// `start` and `end` positions.
+    #[structopt(long)]
+    pub emit_offsets: bool,
+    /// How long to wait for more diagnostics after the last ones published for a file, with
+    /// `--include-diagnostics`.
+    #[structopt(long, default_value = "300")]
+    pub diagnostics_settle_ms: u64,
+    /// How long to wait after opening a file before querying it, for servers that return
+    /// nothing until they have parsed it.
+    #[structopt(long, default_value = "0")]
+    pub open_settle_ms: u64,
+    /// Wait up to MS milliseconds after opening a file for the server to publish its
+    /// diagnostics, which shows that it has parsed the file, before querying it.
+    #[structopt(long, value_name = "MS")]
+    pub wait_for_diagnostics: Option<u64>,
+    /// Flush the output file and sync it to disk every N seconds, so that a crash loses at
+    /// most N seconds of data. Only flushed at the end if 0.
+    #[structopt(long, default_value = "0")]
+    pub flush_interval: u64,
+    /// Flush the output file and sync it to disk every N written entries, along with
+    /// `--flush-interval` if both are given. Disabled if 0.
+    #[structopt(long, default_value = "0")]
+    pub flush_every: u64,
+    /// Stop crawling after N seconds and write the dump for the files crawled so far. The
+    /// checkpoint is kept if `--checkpoint-every` or `--resume` is given. Disabled if 0.
+    #[structopt(long, default_value = "0")]
+    pub timeout_total: u64,
+    /// Write metrics about the run to the given file as JSON.
+    #[structopt(long, parse(from_os_str))]
+    pub stats_json: Option<PathBuf>,
+    /// Print a plain line every few files instead of a progress bar. This is the default
+    /// when stderr is not a terminal.
+    #[structopt(short, long)]
+    pub quiet: bool,
+    /// Write a detailed log to the given file instead of warnings to stderr, together with
+    /// the stderr of the language server. The progress is still shown on the terminal.
+    #[structopt(long, parse(from_os_str))]
+    pub log_file: Option<PathBuf>,
+    /// An environment variable to set for the language server, as `KEY=VALUE`. Can be
+    /// repeated.
+    #[structopt(long = "server-env", parse(try_from_str = parse_key_value), number_of_values = 1)]
+    pub server_env: Vec<(String, String)>,
+    /// Index the given file with the contents of another one, as `PATH=CONTENTS_FILE`, e.g.
+    /// to index the unsaved buffer of an editor. Can be repeated.
+    #[structopt(long = "overlay", parse(try_from_str = parse_key_value), number_of_values = 1)]
+    pub overlay_files: Vec<(String, String)>,
+    /// The contents read from `--overlay`, or set by a caller of the library.
+    #[structopt(skip)]
+    pub overlay: Overlay,
+    /// The trace level to ask the language server for: `off`, `messages` or `verbose`.
+    #[structopt(long, default_value = "off", parse(try_from_str = parse_trace))]
+    pub server_trace: TraceOption,
+    /// Restart the language server up to N times if it stops, crawling the file it stopped
+    /// at again.
+    #[structopt(long, default_value = "0")]
+    pub max_respawns: u64,
+    /// Shut the language server down and start it again every N files, to free the memory
+    /// that some servers leak over a long run. Never if 0.
+    #[structopt(
+        long,
+        default_value = "0",
+        conflicts_with_all = &["attach-stdio", "attach-socket"]
+    )]
+    pub server_restart_every: u64,
+    /// The working directory of the language server, or the current directory if not
+    /// present.
+    #[structopt(long, parse(from_os_str))]
+    pub server_cwd: Option<PathBuf>,
+    /// Wait until the language server resolves the definition at `FILE:LINE:COLUMN` (from 1,
+    /// relative to the project root) instead of a fixed time before crawling. For servers
+    /// that do not report their indexing progress.
+    #[structopt(long, parse(try_from_str = parse_ready_probe))]
+    pub server_ready_command: Option<ReadyProbe>,
+    /// Same as `--server-ready-command`, given as a file relative to the project root and
+    /// `--probe-position`. A small file whose definitions only resolve once the server has
+    /// loaded the project works best, e.g. for gopls.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "probe-position",
+        conflicts_with = "server-ready-command"
+    )]
+    pub probe_file: Option<PathBuf>,
+    /// The position to probe in `--probe-file`, as `LINE:COLUMN` (from 1).
+    #[structopt(long, parse(try_from_str = parse_line_column), requires = "probe-file")]
+    pub probe_position: Option<Position>,
+    /// How long to wait for the language server to start, in seconds: for its response to
+    /// `initialize`, then for `--server-ready-command` to succeed. Fails if it takes longer.
+    #[structopt(long, default_value = "120", alias = "server-ready-timeout")]
+    pub server_startup_timeout: u64,
+    /// A command to run once the dump is complete, e.g. `--after 'src lsif upload -file=$DUMP'`,
+    /// where `$DUMP` is the path of the dump. Words with `$DUMP` are repeated for each shard.
+    /// Not run if the crawl stopped at `--timeout-total`. Exits with the status of the command.
+    #[structopt(long)]
+    pub after: Option<String>,
+    /// Stop with an error once the server returned errors for `--fail-fast-after` definition
+    /// requests in a row, which usually means that it is misconfigured.
+    #[structopt(long)]
+    pub fail_fast: bool,
+    /// Keep crawling whatever errors the server returns. This is the default.
+    #[structopt(long, conflicts_with = "fail-fast")]
+    pub keep_going: bool,
+    /// The number of definition requests in a row that must fail for `--fail-fast` to stop.
+    #[structopt(long, default_value = "100")]
+    pub fail_fast_after: u64,
+    /// A JSON file with the `ClientCapabilities` to send to the language server, instead of
+    /// the built-in ones (markdown hovers, hierarchical document symbols, all symbol kinds).
+    #[structopt(long, parse(from_os_str))]
+    pub capabilities_file: Option<PathBuf>,
+    /// The `initializationOptions` to send to the language server, as JSON. Merged over the
+    /// options of `--preset`, if given.
+    #[structopt(long, parse(try_from_str = serde_json::from_str))]
+    pub initialization_options: Option<Value>,
+    /// A preset of `initializationOptions` of the language's default server, e.g. `full` for
+    /// rust-analyzer. See `presets` in `language_config.toml` for those of each language.
+    #[structopt(long)]
+    pub preset: Option<String>,
+    /// Which definitions get a moniker: `all`, with the kind `export` for the exported
+    /// symbols and `local` for the others, or `exports-only`.
+    #[structopt(long, default_value = "all", parse(try_from_str = parse_monikers))]
+    pub monikers: Monikers,
+    /// The uniqueness of the `export` and `import` monikers: `scheme`, or `global` for
+    /// resolvers that only match global monikers across repositories. `local` monikers are
+    /// unique in their document.
+    #[structopt(long, default_value = "scheme", parse(try_from_str = parse_moniker_unique))]
+    pub moniker_unique: MonikerUnique,
+    /// The format of the hover contents to ask the server for: `markdown`, rendered by
+    /// Sourcegraph, or `plaintext`, shown as is. Replaces the hover formats of
+    /// `--capabilities-file`.
+    #[structopt(long, default_value = "markdown", parse(try_from_str = parse_hover_format))]
+    pub hover_format: HoverFormat,
+    /// Do not request the hover of the definitions of a kind in `--lazy-hover-skip-kinds`,
+    /// which rarely have documentation. The kind is that of the definition's document
+    /// symbol, or else of its semantic token with `--semantic-tokens`; the definitions of
+    /// an unknown kind always get their hover.
+    #[structopt(long)]
+    pub lazy_hover: bool,
+    /// The kinds of definitions without a hover with `--lazy-hover`, as the camelCase names
+    /// of symbol kinds or semantic token types, e.g. `variable,parameter,field`.
+    #[structopt(long, use_delimiter = true, default_value = "variable,parameter")]
+    pub lazy_hover_skip_kinds: Vec<String>,
 }
 
 impl Args {
-    pub fn canonicalize_paths(&mut self) {
-        self.project_root = Some(
-            self.project_root
-                .clone()
-                .unwrap_or(PathBuf::from("."))
-                .canonicalize()
-                .unwrap(),
-        );
-        self.output = Some(
-            self.output.as_ref().map_or(
-                normalize_path(
-                    &self
-                        .project_root
-                        .clone()
-                        .unwrap()
-                        .join(PathBuf::from("dump.json")),
-                ),
-                |p| normalize_path(p),
-            ),
-        );
+    /// Makes the paths absolute. Fails if the project root is not a directory, if a file or
+    /// workspace folder does not exist, or if `--file` is outside of the project root.
+    pub fn canonicalize_paths(&mut self) -> Result<()> {
+        let default_root = if self.check {
+            PathBuf::from("src/tests/test_data").join(&self.language)
+        } else {
+            PathBuf::from(".")
+        };
+        let project_root = self.project_root.clone().unwrap_or(default_root);
+        if !project_root.is_dir() {
+            ret_error!(
+                "The project root `{}` does not exist or is not a directory",
+                project_root.display()
+            );
+        }
+        self.project_root = Some(canonicalize(&project_root)?);
+        self.file = self.file.as_deref().map(canonicalize).transpose()?;
+        if let Some(file) = &self.file {
+            if !file.starts_with(self.project_root.as_ref().unwrap()) {
+                ret_error!(
+                    "The file `{}` is not in the project root `{}`",
+                    file.display(),
+                    project_root.display()
+                );
+            }
+        }
+        for (path, contents_file) in &self.overlay_files {
+            let contents = std::fs::read_to_string(contents_file)
+                .with_context(|| format!("Could not read `{}`", contents_file))?;
+            self.overlay
+                .insert(canonicalize(Path::new(path))?, contents);
+        }
+        if let (Some(file), Some(position)) = (self.probe_file.take(), self.probe_position) {
+            self.server_ready_command = Some(ReadyProbe { file, position });
+        }
+        if let Some(probe) = &mut self.server_ready_command {
+            probe.file = normalize_path(&self.project_root.as_ref().unwrap().join(&probe.file));
+        }
+        self.workspace_folders = self
+            .workspace_folders
+            .iter()
+            .map(|p| canonicalize(p))
+            .collect::<Result<_>>()?;
+        if self.check && self.output.is_none() {
+            self.output = Some(std::env::temp_dir().join("universal-lsif-check.json"));
+        }
+        // The project root of an archive is removed once it has been indexed
+        let output_dir = match &self.from_archive {
+            Some(_) => std::env::current_dir().unwrap(),
+            None => self.project_root.clone().unwrap(),
+        };
+        // A given output is relative to the current directory, like any other argument
+        self.output = Some(self.output.as_ref().map_or(
+            normalize_path(&output_dir.join(PathBuf::from("dump.json"))),
+            |p| normalize_path(&std::env::current_dir().unwrap().join(p)),
+        ));
+        Ok(())
+    }
+
+    /// Returns the words given with `--skip-word` and those in `--skip-words-file`.
+    pub fn skip_words(&self) -> Result<Vec<String>> {
+        let mut words = self.skip_words.clone();
+        if let Some(path) = &self.skip_words_file {
+            let src = std::fs::read_to_string(path)
+                .with_context(|| format!("Could not read `{}`", path.display()))?;
+            words.extend(parse_skip_words(&src));
+        }
+        Ok(words)
+    }
+
+    /// Returns the capabilities in `--capabilities-file`, or `None` to use the built-in ones.
+    pub fn client_capabilities(&self) -> Result<Option<Value>> {
+        let path = match &self.capabilities_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let src = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read `{}`", path.display()))?;
+        let capabilities: Value = serde_json::from_str(&src)
+            .with_context(|| format!("Invalid JSON in `{}`", path.display()))?;
+        serde_json::from_value::<ClientCapabilities>(capabilities.clone())
+            .with_context(|| format!("Invalid client capabilities in `{}`", path.display()))?;
+        Ok(Some(capabilities))
+    }
+
+    /// Returns when to flush the output file, as given with `--flush-interval` and
+    /// `--flush-every`.
+    pub fn flush_policy(&self) -> FlushPolicy {
+        FlushPolicy {
+            every_entries: Some(self.flush_every).filter(|&n| n > 0),
+            interval: Some(self.flush_interval)
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+        }
+    }
+
+    /// Returns the budget given with `--timeout-total`, or `None` if disabled.
+    pub fn timeout_total(&self) -> Option<Duration> {
+        if self.timeout_total == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(self.timeout_total))
+        }
+    }
+
+    /// Returns the number of server errors in a row to stop at with `--fail-fast`, or `None`
+    /// if the crawl keeps going whatever the errors.
+    pub fn fail_fast_after(&self) -> Option<u64> {
+        if self.fail_fast && !self.keep_going {
+            Some(self.fail_fast_after)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the shards to split the output into, or `None` if `--shard-by` is not given.
+    pub fn shards(&self) -> Option<Shards> {
+        self.shard_by.map(|ShardBy::Size| {
+            Shards::new(self.output.clone().unwrap(), self.shard_size * 1024 * 1024)
+        })
+    }
+
+    /// Returns whether the crawled files are recorded in a checkpoint.
+    pub fn checkpointing(&self) -> bool {
+        self.checkpoint_every > 0 || self.resume
+    }
+
+    /// Returns the path of the checkpoint file for the output file.
+    pub fn checkpoint_path(&self) -> PathBuf {
+        let mut path = self.output.clone().unwrap().into_os_string();
+        path.push(".checkpoint");
+        PathBuf::from(path)
+    }
+}
+
+/// The options whose values may hold secrets, e.g. tokens, and are left out of the arguments
+/// recorded in the dump with `--record-invocation`.
+const REDACTED_OPTIONS: &[&str] = &["--server-env", "--initialization-options"];
+
+/// Returns the given command-line arguments with the values of `REDACTED_OPTIONS` replaced.
+/// The name of a `--server-env` variable is kept.
+pub fn redact_args<I: IntoIterator<Item = String>>(args: I) -> Vec<String> {
+    let redact = |option: &str, value: &str| match (option, value.split_once('=')) {
+        ("--server-env", Some((key, _))) => format!("{}=<redacted>", key),
+        _ => "<redacted>".to_string(),
+    };
+    let mut redacted = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.split_once('=') {
+            Some((option, value)) if REDACTED_OPTIONS.contains(&option) => {
+                redacted.push(format!("{}={}", option, redact(option, value)));
+            }
+            None if REDACTED_OPTIONS.contains(&arg.as_str()) => {
+                let value = args.next().map(|value| redact(&arg, &value));
+                redacted.push(arg);
+                redacted.extend(value);
+            }
+            _ => redacted.push(arg),
+        }
+    }
+    redacted
+}
+
+/// Returns the program and arguments of the `--after` command for the dump in the given
+/// files.
+pub fn after_argv(command: &str, dump_paths: &[PathBuf]) -> Result<(String, Vec<String>)> {
+    let mut argv = Vec::new();
+    for word in shell_words::split(command)
+        .with_context(|| format!("Could not parse the `--after` command `{}`", command))?
+    {
+        if word.contains("$DUMP") {
+            argv.extend(
+                dump_paths
+                    .iter()
+                    .map(|path| word.replace("$DUMP", &path.to_string_lossy())),
+            );
+        } else {
+            argv.push(word);
+        }
+    }
+    if argv.is_empty() {
+        ret_error!("The `--after` command is empty");
+    }
+    let program = argv.remove(0);
+    Ok((program, argv))
+}
+
+/// Parses the contents of a skip-words file.
+fn parse_skip_words(src: &str) -> Vec<String> {
+    src.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// The command-line arguments of `universal-lsif verify-dump`.
+#[derive(Clone, Debug, StructOpt)]
+#[structopt(
+    name = "universal-lsif verify-dump",
+    about = "Checks the integrity of the graph of an existing dump"
+)]
+pub struct VerifyArgs {
+    /// The dump, or its shards in order
+    #[structopt(parse(from_os_str), required = true)]
+    pub dumps: Vec<PathBuf>,
+}
+
+/// How to split the output with `--shard-by`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShardBy {
+    /// Start a new shard before one gets bigger than `--shard-size`.
+    Size,
+}
+
+/// How definitions and references are found, with `--strategy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Probe every word with a definition request.
+    Probe,
+    /// Ask for the references to each document symbol.
+    References,
+    /// Ask for the references to each workspace symbol.
+    WorkspaceSymbols,
+}
+
+/// Which definitions get a moniker, with `--monikers`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Monikers {
+    All,
+    /// Only the symbols that the language's export rule finds exported.
+    ExportsOnly,
+}
+
+/// The format of the hover contents, with `--hover-format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HoverFormat {
+    Markdown,
+    Plaintext,
+}
+
+impl HoverFormat {
+    pub fn markup_kind(self) -> MarkupKind {
+        match self {
+            HoverFormat::Markdown => MarkupKind::Markdown,
+            HoverFormat::Plaintext => MarkupKind::PlainText,
+        }
+    }
+}
+
+/// A position to send definition requests to with `--server-ready-command`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadyProbe {
+    pub file: PathBuf,
+    /// The position in the file, from 0 as in the protocol.
+    pub position: Position,
+}
+
+/// The offset added to the positions of the requests with `--position-adjust`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionAdjust {
+    pub lines: i64,
+    pub columns: i64,
+}
+
+impl PositionAdjust {
+    /// Returns the given position moved by the offset, not before the first line or column.
+    pub fn apply(self, position: Position) -> Position {
+        Position::new(
+            position.line.saturating_add_signed(self.lines),
+            position.character.saturating_add_signed(self.columns),
+        )
+    }
+
+    /// Returns the given range moved back by the offset, the inverse of `apply`.
+    pub fn revert(self, range: Range) -> Range {
+        let back = PositionAdjust {
+            lines: -self.lines,
+            columns: -self.columns,
+        };
+        Range::new(back.apply(range.start), back.apply(range.end))
+    }
+}
+
+/// Parses the value of `--position-adjust`, e.g. `0:1` or `-1:0`.
+fn parse_position_adjust(s: &str) -> Result<PositionAdjust> {
+    let (lines, columns) = match s.split_once(':') {
+        Some(it) => it,
+        None => ret_error!("Expected `LINES:COLUMNS`, found `{}`", s),
+    };
+    let number = |n: &str| {
+        n.parse::<i64>()
+            .map_err(|_| error!("Expected a number, found `{}` in `{}`", n, s))
+    };
+    Ok(PositionAdjust {
+        lines: number(lines)?,
+        columns: number(columns)?,
+    })
+}
+
+/// Parses the value of `--server-ready-command`, e.g. `src/main.go:12:6`.
+fn parse_ready_probe(s: &str) -> Result<ReadyProbe> {
+    let file = match s.rsplitn(3, ':').nth(2) {
+        Some(file) if !file.is_empty() => file,
+        _ => ret_error!("Expected `FILE:LINE:COLUMN`, found `{}`", s),
+    };
+    Ok(ReadyProbe {
+        file: PathBuf::from(file),
+        position: parse_line_column(&s[file.len() + 1..])?,
+    })
+}
+
+/// Parses a `LINE:COLUMN` position, from 1, e.g. the value of `--probe-position`.
+fn parse_line_column(s: &str) -> Result<Position> {
+    let (line, column) = match s.split_once(':') {
+        Some(it) => it,
+        None => ret_error!("Expected `LINE:COLUMN`, found `{}`", s),
+    };
+    let number = |n: &str| match n.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n - 1),
+        _ => Err(error!("Expected a number from 1, found `{}` in `{}`", n, s)),
+    };
+    Ok(Position::new(number(line)?, number(column)?))
+}
+
+/// Parses the value of `--max-requests-per-second`.
+fn parse_max_requests_per_second(s: &str) -> Result<u64> {
+    match s.parse::<u64>() {
+        Ok(max) if max > 0 => Ok(max),
+        _ => ret_error!("Expected a number from 1, found `{}`", s),
     }
 }
 
+/// Parses the value of `--output-format`.
+fn parse_output_format(s: &str) -> Result<OutputFormat> {
+    match s {
+        "jsonl" => Ok(OutputFormat::JsonLines),
+        "json-array" => Ok(OutputFormat::JsonArray),
+        _ => ret_error!("Expected `jsonl` or `json-array`, found `{}`", s),
+    }
+}
+
+/// Parses the value of `--emit-order`.
+fn parse_emit_order(s: &str) -> Result<EmitOrder> {
+    match s {
+        "as-emitted" => Ok(EmitOrder::AsEmitted),
+        "spec-default" => Ok(EmitOrder::SpecDefault),
+        "ranges-first" => Ok(EmitOrder::RangesFirst),
+        _ => ret_error!(
+            "Expected `as-emitted`, `spec-default` or `ranges-first`, found `{}`",
+            s
+        ),
+    }
+}
+
+/// Parses the value of `--shard-by`.
+fn parse_shard_by(s: &str) -> Result<ShardBy> {
+    match s {
+        "size" => Ok(ShardBy::Size),
+        // The documents are emitted before any of their ranges, so a shard per group of
+        // documents would not hold their ranges
+        "documents" => ret_error!("Sharding by documents is not supported, use `size`"),
+        _ => ret_error!("Expected `size`, found `{}`", s),
+    }
+}
+
+/// Parses the value of `--strategy`.
+fn parse_strategy(s: &str) -> Result<Strategy> {
+    match s {
+        "probe" => Ok(Strategy::Probe),
+        "references" => Ok(Strategy::References),
+        "workspace-symbols" => Ok(Strategy::WorkspaceSymbols),
+        _ => ret_error!(
+            "Expected `probe`, `references` or `workspace-symbols`, found `{}`",
+            s
+        ),
+    }
+}
+
+/// Parses the value of `--moniker-unique`.
+fn parse_moniker_unique(s: &str) -> Result<MonikerUnique> {
+    match s {
+        "scheme" => Ok(MonikerUnique::Scheme),
+        "global" => Ok(MonikerUnique::Global),
+        _ => ret_error!("Expected `scheme` or `global`, found `{}`", s),
+    }
+}
+
+/// Parses the value of `--monikers`.
+fn parse_monikers(s: &str) -> Result<Monikers> {
+    match s {
+        "all" => Ok(Monikers::All),
+        "exports-only" => Ok(Monikers::ExportsOnly),
+        _ => ret_error!("Expected `all` or `exports-only`, found `{}`", s),
+    }
+}
+
+/// Parses the value of `--hover-format`.
+fn parse_hover_format(s: &str) -> Result<HoverFormat> {
+    match s {
+        "markdown" => Ok(HoverFormat::Markdown),
+        "plaintext" => Ok(HoverFormat::Plaintext),
+        _ => ret_error!("Expected `markdown` or `plaintext`, found `{}`", s),
+    }
+}
+
+/// Parses a trace level.
+fn parse_trace(s: &str) -> Result<TraceOption> {
+    serde_json::from_value(serde_json::Value::String(s.to_string()))
+        .map_err(|_| error!("Expected `off`, `messages` or `verbose`, found `{}`", s))
+}
+
+/// Parses a `KEY=VALUE` pair, e.g. an environment variable.
+fn parse_key_value(s: &str) -> Result<(String, String)> {
+    match s.find('=') {
+        Some(0) | None => ret_error!("Expected `KEY=VALUE`, found `{}`", s),
+        Some(i) => Ok((s[..i].to_string(), s[i + 1..].to_string())),
+    }
+}
+
+/// Returns the absolute path of the given file or directory, which must exist.
+fn canonicalize(path: &Path) -> Result<PathBuf> {
+    path.canonicalize()
+        .with_context(|| format!("Could not find `{}`", path.display()))
+}
+
 /// Same as `std::path::Path::canonicalize`, but does not require that the given path exists.
 pub fn normalize_path(path: &Path) -> PathBuf {
     let mut components = path.components().peekable();
@@ -78,3 +798,263 @@ pub fn normalize_path(path: &Path) -> PathBuf {
     }
     ret
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use languageserver_types::{Position, Range, TraceOption};
+    use structopt::StructOpt;
+
+    use super::{
+        after_argv, parse_key_value, parse_line_column, parse_max_requests_per_second,
+        parse_position_adjust, parse_ready_probe, parse_shard_by, parse_skip_words, parse_trace,
+        redact_args, Args, PositionAdjust, ReadyProbe, ShardBy,
+    };
+
+    #[test]
+    fn test_parse_skip_words() {
+        let src = "# generated accessors\nget_value set_value\n\n  unwrap\n";
+        assert_eq!(
+            parse_skip_words(src),
+            vec!["get_value", "set_value", "unwrap"]
+        );
+    }
+
+    #[test]
+    fn test_parse_key_value() {
+        assert_eq!(
+            parse_key_value("GOFLAGS=-mod=vendor").unwrap(),
+            ("GOFLAGS".to_string(), "-mod=vendor".to_string())
+        );
+        assert_eq!(
+            parse_key_value("RUST_LOG=").unwrap(),
+            ("RUST_LOG".to_string(), String::new())
+        );
+        assert!(parse_key_value("RUST_LOG").is_err());
+        assert!(parse_key_value("=info").is_err());
+    }
+
+    #[test]
+    fn test_parse_ready_probe() {
+        assert_eq!(
+            parse_ready_probe(r"C:\src\main.go:12:6").unwrap(),
+            ReadyProbe {
+                file: PathBuf::from(r"C:\src\main.go"),
+                position: Position::new(11, 5),
+            }
+        );
+        assert!(parse_ready_probe("main.go:12").is_err());
+        assert!(parse_ready_probe("main.go:0:1").is_err());
+        assert!(parse_ready_probe(":1:1").is_err());
+        assert!(parse_line_column("12").is_err());
+    }
+
+    #[test]
+    fn test_parse_position_adjust() {
+        let adjust = parse_position_adjust("0:1").unwrap();
+        assert_eq!(adjust.apply(Position::new(3, 4)), Position::new(3, 5));
+        let adjust = parse_position_adjust("-1:-2").unwrap();
+        assert_eq!(adjust.apply(Position::new(3, 4)), Position::new(2, 2));
+        assert_eq!(adjust.apply(Position::new(0, 1)), Position::new(0, 0));
+        let range = Range::new(Position::new(3, 4), Position::new(3, 6));
+        assert_eq!(
+            adjust.revert(range),
+            Range::new(Position::new(4, 6), Position::new(4, 8))
+        );
+        assert!(parse_position_adjust("1").is_err());
+        assert!(parse_position_adjust("1:x").is_err());
+
+        let args = Args::from_iter_safe(&["universal-lsif", "rust", "--position-adjust", "-1:0"]);
+        assert_eq!(
+            args.unwrap().position_adjust,
+            Some(PositionAdjust {
+                lines: -1,
+                columns: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_probe_file() {
+        let mut args = Args::from_iter(vec![
+            "universal-lsif",
+            "go",
+            "src/tests/test_data/go",
+            "--probe-file",
+            "main.go",
+            "--probe-position",
+            "3:6",
+        ]);
+        args.canonicalize_paths().unwrap();
+        let probe = args.server_ready_command.unwrap();
+        assert!(probe.file.ends_with("src/tests/test_data/go/main.go"));
+        assert_eq!(probe.position, Position::new(2, 5));
+
+        assert!(
+            Args::from_iter_safe(vec!["universal-lsif", "go", "--probe-file", "main.go"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_missing_project_root() {
+        let mut args = Args::from_iter(vec!["universal-lsif", "go", "src/tests/test_data/nope"]);
+        let err = args.canonicalize_paths().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The project root `src/tests/test_data/nope` does not exist or is not a directory"
+        );
+
+        let mut args = Args::from_iter(vec!["universal-lsif", "go", "Cargo.toml"]);
+        assert!(args.canonicalize_paths().is_err());
+    }
+
+    #[test]
+    fn test_file_outside_of_project_root() {
+        let mut args = Args::from_iter(vec![
+            "universal-lsif",
+            "go",
+            "src/tests/test_data/go",
+            "--file",
+            "src/tests/test_data/rust/src/main.rs",
+        ]);
+        let err = args.canonicalize_paths().unwrap_err();
+        assert!(
+            err.to_string().contains("is not in the project root"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_overlay_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = dir.path().join("buffer.go");
+        std::fs::write(&buffer, "package main // unsaved").unwrap();
+        let main = "src/tests/test_data/go/main.go";
+        let mut args = Args::from_iter(vec![
+            "universal-lsif",
+            "go",
+            "src/tests/test_data/go",
+            "--overlay",
+            &format!("{}={}", main, buffer.display()),
+        ]);
+        args.canonicalize_paths().unwrap();
+        let main = std::path::Path::new(main).canonicalize().unwrap();
+        assert_eq!(
+            args.overlay.read_to_string(&main).unwrap(),
+            "package main // unsaved"
+        );
+
+        let mut args = Args::from_iter(vec![
+            "universal-lsif",
+            "go",
+            "src/tests/test_data/go",
+            "--overlay",
+            "missing.go=buffer.go",
+        ]);
+        assert!(args.canonicalize_paths().is_err());
+    }
+
+    #[test]
+    fn test_output_paths() {
+        let cwd = std::env::current_dir().unwrap();
+        let project = "src/tests/test_data/go";
+
+        let mut args = Args::from_iter(vec!["universal-lsif", "go", project]);
+        args.canonicalize_paths().unwrap();
+        assert_eq!(
+            args.output.unwrap(),
+            cwd.join(project).canonicalize().unwrap().join("dump.json")
+        );
+
+        let mut args =
+            Args::from_iter(vec!["universal-lsif", "go", project, "-o", "out/./go.json"]);
+        args.canonicalize_paths().unwrap();
+        assert_eq!(args.output.unwrap(), cwd.join("out/go.json"));
+
+        let mut args = Args::from_iter(vec!["universal-lsif", "go", project, "-o", "/tmp/go.json"]);
+        args.canonicalize_paths().unwrap();
+        assert_eq!(args.output.unwrap(), PathBuf::from("/tmp/go.json"));
+    }
+
+    #[test]
+    fn test_from_archive_conflicts_with_project_root() {
+        let args = |extra: &[&str]| {
+            Args::from_iter_safe(
+                ["universal-lsif", "python", "--from-archive", "repo.tar.gz"]
+                    .iter()
+                    .chain(extra),
+            )
+        };
+        assert!(args(&[]).is_ok());
+        assert!(args(&["."]).is_err());
+    }
+
+    #[test]
+    fn test_redact_args() {
+        let args = [
+            "rust",
+            "--server-env",
+            "GITHUB_TOKEN=secret",
+            "--server-env=NPM_TOKEN=secret",
+            "--initialization-options",
+            r#"{"token": "secret"}"#,
+            "--initialization-options={}",
+            "--server-args=--stdio",
+        ];
+        assert_eq!(
+            redact_args(args.iter().map(ToString::to_string)),
+            vec![
+                "rust",
+                "--server-env",
+                "GITHUB_TOKEN=<redacted>",
+                "--server-env=NPM_TOKEN=<redacted>",
+                "--initialization-options",
+                "<redacted>",
+                "--initialization-options=<redacted>",
+                "--server-args=--stdio",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_after_argv() {
+        let (program, args) = after_argv(
+            "src lsif upload -file=$DUMP",
+            &[PathBuf::from("/tmp/my dump.json")],
+        )
+        .unwrap();
+        assert_eq!(program, "src");
+        assert_eq!(args, vec!["lsif", "upload", "-file=/tmp/my dump.json"]);
+
+        let shards = [
+            PathBuf::from("dump.000.json"),
+            PathBuf::from("dump.001.json"),
+        ];
+        let (program, args) = after_argv("gzip $DUMP", &shards).unwrap();
+        assert_eq!(program, "gzip");
+        assert_eq!(args, vec!["dump.000.json", "dump.001.json"]);
+
+        assert!(after_argv("", &shards).is_err());
+    }
+
+    #[test]
+    fn test_parse_shard_by() {
+        assert_eq!(parse_shard_by("size").unwrap(), ShardBy::Size);
+        assert!(parse_shard_by("documents").is_err());
+    }
+
+    #[test]
+    fn test_parse_max_requests_per_second() {
+        assert_eq!(parse_max_requests_per_second("20").unwrap(), 20);
+        assert!(parse_max_requests_per_second("0").is_err());
+        assert!(parse_max_requests_per_second("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_trace() {
+        assert_eq!(parse_trace("messages").unwrap(), TraceOption::Messages);
+        assert!(parse_trace("loud").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use toml::Value;
 
@@ -18,3 +18,219 @@ pub fn language_configs() -> HashMap<String, LSConfig> {
         _ => unreachable!("`languge_config.toml` is not valid"),
     }
 }
+
+/// The extensions that are of more than one language, with those languages in alphabetical
+/// order.
+pub type AmbiguousExtensions = Vec<(String, Vec<String>)>;
+
+/// Maps each extension to a single language, for runs over several languages. An extension
+/// that more than one language has goes to the one that lists it in `preferred_extensions`.
+/// If none or several of them do, it goes to the first of them in alphabetical order and is
+/// also returned as ambiguous.
+pub fn extension_languages(
+    configs: &HashMap<String, LSConfig>,
+) -> (HashMap<String, String>, AmbiguousExtensions) {
+    let mut candidates: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (language, config) in configs {
+        for ext in &config.extensions {
+            candidates.entry(ext).or_default().push(language);
+        }
+    }
+
+    let mut languages = HashMap::new();
+    let mut ambiguous = Vec::new();
+    for (ext, mut candidates) in candidates {
+        candidates.sort_unstable();
+        let preferred = candidates
+            .iter()
+            .filter(|language| {
+                configs[**language]
+                    .preferred_extensions
+                    .iter()
+                    .any(|e| e == ext)
+            })
+            .collect::<Vec<_>>();
+        let language = match preferred[..] {
+            [language] => language,
+            _ => {
+                if candidates.len() > 1 {
+                    let candidates = candidates.iter().map(ToString::to_string).collect();
+                    ambiguous.push((ext.to_string(), candidates));
+                }
+                &candidates[0]
+            }
+        };
+        languages.insert(ext.to_string(), language.to_string());
+    }
+    (languages, ambiguous)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path};
+
+    use serde_json::json;
+    use structopt::StructOpt;
+
+    use super::{extension_languages, language_configs};
+    use crate::cli::Args;
+    use crate::lsp::LSConfig;
+
+    #[test]
+    fn test_server_command_precedence() {
+        let config = language_configs()["typescript"].clone();
+
+        let args = Args::from_iter(vec!["universal-lsif", "typescript"]);
+        assert_eq!(
+            config.server_command(&args).unwrap(),
+            (
+                "typescript-language-server".to_string(),
+                Some("--stdio".to_string())
+            )
+        );
+
+        let args = Args::from_iter(vec![
+            "universal-lsif",
+            "typescript",
+            "--server-command",
+            "tsserver-lsp",
+            "--server-args=--verbose",
+        ]);
+        assert_eq!(
+            config.server_command(&args).unwrap(),
+            ("tsserver-lsp".to_string(), Some("--verbose".to_string()))
+        );
+
+        let config = language_configs()["java"].clone();
+        let args = Args::from_iter(vec!["universal-lsif", "java"]);
+        assert!(config.server_command(&args).is_err());
+    }
+
+    #[test]
+    fn test_language_id_per_extension() {
+        let config = language_configs()["typescript"].clone();
+        assert_eq!(
+            config.language_id(Path::new("src/app.tsx"), "typescript"),
+            "typescriptreact"
+        );
+        assert_eq!(
+            config.language_id(Path::new("src/app.ts"), "typescript"),
+            "typescript"
+        );
+    }
+
+    #[test]
+    fn test_restrict_extensions() {
+        let mut config = language_configs()["typescript"].clone();
+        assert!(config.restrict_extensions(&[]).is_empty());
+        assert_eq!(config.extensions, vec!["ts", "tsx"]);
+
+        let unknown = config.restrict_extensions(&[".ts".to_string(), "js".to_string()]);
+        assert_eq!(unknown, vec!["js"]);
+        assert_eq!(config.extensions, vec!["ts"]);
+    }
+
+    #[test]
+    fn test_extension_languages() {
+        let config = |extensions: &str, preferred: &str| -> LSConfig {
+            toml::from_str(&format!(
+                "extensions = {}\npreferred_extensions = {}\nkeywords = []",
+                extensions, preferred
+            ))
+            .unwrap()
+        };
+        let mut configs = HashMap::new();
+        configs.insert("c".to_string(), config(r#"["c", "h"]"#, "[]"));
+        configs.insert("cpp".to_string(), config(r#"["cpp", "h"]"#, r#"["h"]"#));
+        configs.insert("objc".to_string(), config(r#"["m", "h", "mm"]"#, "[]"));
+        configs.insert("objcpp".to_string(), config(r#"["mm"]"#, "[]"));
+
+        let (languages, ambiguous) = extension_languages(&configs);
+        assert_eq!(languages["h"], "cpp");
+        assert_eq!(languages["c"], "c");
+        assert_eq!(languages["mm"], "objc");
+        assert_eq!(
+            ambiguous,
+            vec![(
+                "mm".to_string(),
+                vec!["objc".to_string(), "objcpp".to_string()]
+            )]
+        );
+
+        // The built-in languages do not share extensions
+        assert_eq!(extension_languages(&language_configs()).1, vec![]);
+    }
+
+    #[test]
+    fn test_is_exported() {
+        let go = language_configs()["go"].clone();
+        assert!(go.is_exported("func ", "Serve"));
+        assert!(go.is_exported("type ", "Config"));
+        assert!(go.is_exported("func (s *Server) ", "Serve"));
+        assert!(!go.is_exported("func ", "serve"));
+        // Parameters and locals
+        assert!(!go.is_exported("func Serve(", "Port"));
+        assert!(!go.is_exported("func Serve(port int, ", "Host"));
+        assert!(!go.is_exported("\t", "Config"));
+        assert!(!go.is_exported("\tvar ", "Config"));
+
+        let rust = language_configs()["rust"].clone();
+        assert!(rust.is_exported("pub fn ", "serve"));
+        assert!(rust.is_exported("    pub ", "name"));
+        assert!(!rust.is_exported("fn ", "serve"));
+        assert!(!rust.is_exported("pub(crate) struct ", "Config"));
+        assert!(!rust.is_exported("fn republish(", "x"));
+        assert!(rust.is_exported("pub async unsafe fn ", "serve"));
+        // Parameters and locals
+        assert!(!rust.is_exported("pub fn serve(", "port"));
+        assert!(!rust.is_exported("pub fn serve(host: &str, ", "port"));
+        assert!(!rust.is_exported("pub fn serve() { let ", "port"));
+        assert!(!rust.is_exported("pub fn serve() { let mut ", "port"));
+
+        let typescript = language_configs()["typescript"].clone();
+        assert!(typescript.is_exported("export default function ", "serve"));
+        assert!(!typescript.is_exported("const ", "Serve"));
+        assert!(!typescript.is_exported("export function serve(", "port"));
+        assert!(!typescript.is_exported("export const serve = (", "port"));
+    }
+
+    #[test]
+    fn test_matches_shebang() {
+        let python = language_configs()["python"].clone();
+        assert!(python.matches_shebang("#!/usr/bin/python"));
+        assert!(python.matches_shebang("#!/usr/bin/env python3"));
+        assert!(python.matches_shebang("#! /usr/bin/env -S python3.11 -u"));
+        assert!(!python.matches_shebang("#!/bin/sh"));
+        assert!(!python.matches_shebang("#!/usr/bin/env pythonic"));
+        assert!(!python.matches_shebang("# python"));
+
+        let rust = language_configs()["rust"].clone();
+        assert!(!rust.matches_shebang("#!/usr/bin/env rust"));
+    }
+
+    #[test]
+    fn test_initialization_options_preset() {
+        let config = language_configs()["rust"].clone();
+        let args = Args::from_iter(vec!["universal-lsif", "rust"]);
+        assert_eq!(config.initialization_options(&args).unwrap(), None);
+
+        let args = Args::from_iter(vec![
+            "universal-lsif",
+            "rust",
+            "--preset",
+            "full",
+            "--initialization-options",
+            r#"{"cargo": {"features": "all"}, "procMacro": {"enable": false}}"#,
+        ]);
+        assert_eq!(
+            config.initialization_options(&args).unwrap(),
+            Some(json!({
+                "cargo": {"buildScripts": {"enable": true}, "features": "all"},
+                "procMacro": {"enable": false},
+            }))
+        );
+
+        let args = Args::from_iter(vec!["universal-lsif", "rust", "--preset", "fast"]);
+        assert!(config.initialization_options(&args).is_err());
+    }
+}
@@ -1,145 +1,1353 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs::File,
     hash::Hasher,
+    io::{IsTerminal, Read},
     path::{Path, PathBuf},
-    sync::{mpsc::channel, Arc, Mutex},
+    sync::{
+        mpsc::{channel, sync_channel, RecvError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use ignore::{DirEntry, Walk};
+use ignore::{DirEntry, WalkBuilder};
 use indicatif::ProgressBar;
 use languageserver_types::{
-    request::GotoDefinitionResponse, Position, Range as LspRange, TextDocumentIdentifier,
-    TextDocumentPositionParams, Url,
+    request::GotoDefinitionResponse, DocumentSymbol, DocumentSymbolResponse,
+    Location as LspLocation, Position, Range as LspRange, SymbolInformation, SymbolKind,
+    TextDocumentIdentifier, TextDocumentPositionParams, Url,
 };
 use lazy_static::lazy_static;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    cli::Args,
-    emitter::file_emitter::FileEmitter,
-    indexer::Indexer,
-    lsp::{LSClient, LSConfig},
-    protocol, Result,
+    checkpoint::{Checkpoint, CheckpointEntry},
+    cli::{Args, PositionAdjust, Strategy},
+    emitter::{emitter::Emitter, file_emitter::FileEmitter, graph_emitter::GraphEmitter},
+    graph::Graph,
+    indexer::{IndexStats, Indexer, PhaseDurations},
+    lsp::{
+        hover_to_string, is_server_error, LSClient, LSConfig, PositionEncoding, SemanticToken,
+        WriteError, SEMANTIC_TOKEN_TYPES,
+    },
+    protocol::{
+        self,
+        types::{ByteOffsets, DefinitionTag, RangeTag, RangeVertex, ReferenceTag},
+    },
+    resolution::{Classification, DefinitionProbeStrategy, ResolutionStrategy},
+    ret_error,
+    uri::{file_uri, normalize_uri, path_to_uri},
+    Error, Result,
 };
 
-pub fn traverse(args: Args, mut client: LSClient, config: LSConfig) -> Result<()> {
-    let (def_tx, def_rx) = channel();
-    let (ref_tx, ref_rx) = channel();
+/// How long to wait for the language server to accept the `shutdown` request when it is
+/// restarted with `--server-restart-every`.
+const SERVER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
-    let (file_emitter, flush_signal) = FileEmitter::new(get_output_file(&args)?);
+/// Crawls the project and writes its dump to the output file.
+pub fn traverse(args: Args, client: LSClient, config: LSConfig) -> Result<IndexStats> {
+    let (file_emitter, flush_signal) = FileEmitter::new(
+        get_output_file(&args)?,
+        args.output_format,
+        args.flush_policy(),
+        args.shards(),
+        args.emit_order,
+    );
+    let (stats, _file_emitter) = crawl(args, client, config, file_emitter, || {
+        Ok(flush_signal.recv()?)
+    })?;
+    Ok(stats)
+}
+
+/// Crawls the project and builds its graph in memory, to query it without writing a dump.
+pub fn traverse_in_memory(
+    args: Args,
+    client: LSClient,
+    config: LSConfig,
+) -> Result<(IndexStats, Graph)> {
+    let (stats, emitter) = crawl(args, client, config, GraphEmitter::default(), || Ok(()))?;
+    Ok((stats, emitter.into_graph()))
+}
+
+/// Crawls the project and indexes it with the given emitter. `wait_written` is called once
+/// the emitter has ended, and must block until all the data is written.
+fn crawl<E: Emitter + Send + 'static>(
+    args: Args,
+    mut client: LSClient,
+    config: LSConfig,
+    emitter: E,
+    wait_written: impl FnOnce() -> Result<()>,
+) -> Result<(IndexStats, E)> {
+    match args.strategy {
+        Strategy::Probe if !client.supports_definition() => {
+            ret_error!("The language server does not support `textDocument/definition`")
+        }
+        Strategy::References
+            if !client.supports_document_symbol() || !client.supports_references() =>
+        {
+            ret_error!(
+                "`--strategy references` needs a language server that supports \
+                 `textDocument/documentSymbol` and `textDocument/references`"
+            )
+        }
+        Strategy::WorkspaceSymbols
+            if !client.supports_workspace_symbol() || !client.supports_references() =>
+        {
+            ret_error!(
+                "`--strategy workspace-symbols` needs a language server that supports \
+                 `workspace/symbol` and `textDocument/references`"
+            )
+        }
+        _ => {}
+    }
+    if let Some(adjust) = args.position_adjust {
+        log::warn!(
+            "Moving the positions of the requests by {} lines and {} columns{}",
+            adjust.lines,
+            adjust.columns,
+            if args.position_adjust_responses {
+                ", and those of the responses back"
+            } else {
+                ""
+            }
+        );
+    }
+    client.set_max_requests_per_second(args.max_requests_per_second);
+    client.set_keep_diagnostics(args.include_diagnostics);
+
+    // The indexer drains definitions and references while the crawler is running, so the
+    // crawler blocks once it gets `channel_capacity` of them ahead. Extras are only read
+    // after all definitions and references, so bounding them would deadlock.
+    let (occurrence_tx, occurrence_rx) = sync_channel(args.channel_capacity);
+    let (extras_tx, extras_rx) = channel();
 
     let a = args.clone();
     let c = config.clone();
-    let indexer_proc = std::thread::spawn(move || -> Result<()> {
-        Indexer::index(a, c, file_emitter, def_rx, ref_rx)
+    let encoding = client.position_encoding();
+    let indexer_proc = std::thread::spawn(move || -> Result<(IndexStats, E)> {
+        Indexer::index(a, c, encoding, emitter, occurrence_rx, extras_rx)
     });
 
-    let pb = ProgressBar::new(
-        paths(&args.project_root.clone().unwrap(), config.extensions.clone()).len() as u64,
+    let mut pb = Progress::new(
+        paths(&args, &config).len() as u64,
+        args.quiet || !std::io::stderr().is_terminal(),
     );
-    pb.set_message("Waiting for the language server to finish indexing");
 
-    for p in paths(&args.project_root.clone().unwrap(), config.extensions.clone()) {
-        let text = std::fs::read_to_string(&p).unwrap();
+    let mut checkpoint = if args.resume {
+        let (checkpoint, entries) = Checkpoint::load(args.checkpoint_path())?;
+        for entry in entries {
+            for def in entry.definitions {
+                occurrence_tx.send(Occurrence::Definition(def))?;
+            }
+            for r in entry.references {
+                occurrence_tx.send(Occurrence::Reference(r))?;
+            }
+            if let Some(extras) = entry.extras {
+                extras_tx.send(extras)?;
+            }
+        }
+        checkpoint
+    } else {
+        Checkpoint::new(args.checkpoint_path())
+    };
 
-        client.set_document(&p, text.clone());
+    let workspace_symbols = match args.strategy {
+        Strategy::WorkspaceSymbols => Some(get_workspace_symbols(
+            &mut client,
+            args.workspace_symbol_limit
+                .or(config.workspace_symbol_limit),
+        )?),
+        _ => None,
+    };
 
-        get_words(text)
-            .into_iter()
-            .try_for_each(|(word, range)| -> Result<()> {
-                if config.keywords.get(&word).is_some() {
-                    return Ok(());
+    let crawl_start = Instant::now();
+    let mut crawl_stats = IndexStats::default();
+    let deadline = args.timeout_total().map(|timeout| Instant::now() + timeout);
+    let mut timed_out = false;
+    let mut error_streak = 0;
+    let mut hovered_definitions = HashSet::new();
+    let mut token_cache = TokenCache::default();
+    let mut files_since_restart = 0;
+    for p in paths(&args, &config) {
+        if checkpoint.processed_files.contains(&p) {
+            crawl_stats.files_resumed += 1;
+            pb.inc();
+            continue;
+        }
+        if let Some(symbols) = &workspace_symbols {
+            if symbols.has_no_symbols(&p) && !args.extras && !args.include_diagnostics {
+                log::debug!("Skipping `{}`, it has no workspace symbols", p.display());
+                pb.inc();
+                continue;
+            }
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            pb.println(format!(
+                "Stopped after {} seconds, indexed {}/{} files",
+                args.timeout_total, pb.position, pb.total
+            ));
+            timed_out = true;
+            break;
+        }
+        if args.server_restart_every > 0 && files_since_restart == args.server_restart_every {
+            log::debug!(
+                "Restarting the language server after {} files",
+                files_since_restart
+            );
+            client.restart(SERVER_SHUTDOWN_TIMEOUT)?;
+            files_since_restart = 0;
+        }
+        files_since_restart += 1;
+        let crawled = loop {
+            match crawl_file(
+                &p,
+                &args,
+                &config,
+                &mut client,
+                &DefinitionProbeStrategy,
+                workspace_symbols
+                    .as_ref()
+                    .map(|symbols| symbols.of_file(&p)),
+                &mut error_streak,
+                &mut hovered_definitions,
+                &mut token_cache,
+            ) {
+                Err(err) if is_fatal(&err) && client.respawns() < args.max_respawns => {
+                    pb.println(format!(
+                        "The language server stopped while crawling `{}`, restarting it",
+                        p.display()
+                    ));
+                    client.respawn()?;
+                }
+                crawled => break crawled,
+            }
+        };
+        match crawled {
+            Ok(mut crawled) => {
+                log::debug!(
+                    "Crawled `{}`: {} definitions, {} references, {} failed requests",
+                    p.display(),
+                    crawled.definitions.len(),
+                    crawled.references.len(),
+                    crawled.failed_requests
+                );
+                if args.include_diagnostics {
+                    let settle = Duration::from_millis(args.diagnostics_settle_ms);
+                    let diagnostics = client
+                        .wait_for_diagnostics(&p, settle)
+                        .filter(|v| v.as_array().is_some_and(|a| !a.is_empty()));
+                    if diagnostics.is_some() {
+                        crawled
+                            .extras
+                            .get_or_insert_with(|| DocumentExtras {
+                                file_path: file_uri(&p),
+                                ..DocumentExtras::default()
+                            })
+                            .diagnostics = diagnostics;
+                    }
                 }
+                crawl_stats.files_crawled += 1;
+                crawl_stats.failed_definition_requests += crawled.failed_requests;
+                crawl_stats.empty_definition_responses += crawled.empty_responses;
+                if crawled.skipped_words > 0 {
+                    crawl_stats.files_truncated += 1;
+                    pb.println(format!(
+                        "Skipped the last {} words of `{}`, it has more than {}",
+                        crawled.skipped_words,
+                        p.display(),
+                        args.max_requests_per_file
+                    ));
+                }
+                // Only the files that were crawled are recorded, so that `--resume` crawls
+                // those that failed again
+                if args.checkpointing() {
+                    checkpoint.record(CheckpointEntry {
+                        file: p.clone(),
+                        definitions: crawled.definitions.clone(),
+                        references: crawled.references.clone(),
+                        extras: crawled.extras.clone(),
+                    });
+                }
+                for def in crawled.definitions {
+                    occurrence_tx.send(Occurrence::Definition(def))?;
+                }
+                for r in crawled.references {
+                    occurrence_tx.send(Occurrence::Reference(r))?;
+                }
+                if let Some(extras) = crawled.extras {
+                    extras_tx.send(extras)?;
+                }
+            }
+            Err(err) if is_fatal(&err) => {
+                return Err(err.context(format!("Failed to crawl `{}`", p.display())));
+            }
+            Err(err) => {
+                crawl_stats.files_failed += 1;
+                pb.println(format!("Skipping `{}`: {:#}", p.display(), err));
+            }
+        }
+        if args
+            .fail_fast_after()
+            .is_some_and(|max_errors| error_streak >= max_errors)
+        {
+            ret_error!(
+                "The server returned errors for the last {} requests, check your server setup",
+                error_streak
+            );
+        }
+
+        if args.checkpoint_every > 0 && checkpoint.unsaved() >= args.checkpoint_every {
+            checkpoint.save()?;
+        }
+
+        pb.inc();
+    }
+
+    let crawl_ms = crawl_start.elapsed().as_millis() as u64;
+    drop(occurrence_tx);
+    drop(extras_tx);
+    let (stats, emitter) = indexer_proc.join().unwrap()?;
+    wait_written()?;
+
+    if timed_out && args.checkpointing() {
+        // Keep the checkpoint so that the run can be continued with `--resume`
+        checkpoint.save()?;
+    } else {
+        checkpoint.remove()?;
+    }
 
-                let (start, _end) = (range.start, range.end);
-
-                match client.get_definition(TextDocumentPositionParams {
-                    text_document: TextDocumentIdentifier {
-                        uri: Url::from_file_path(&p).unwrap(),
-                    },
-                    position: start,
-                }) {
-                    Ok(resp) => {
-                        let def_location = match resp {
-                            GotoDefinitionResponse::Scalar(it) => Some(it),
-                            GotoDefinitionResponse::Array(it) => it.get(0).map(Clone::clone),
-                            GotoDefinitionResponse::Link(_) => None,
+    let stats = IndexStats {
+        files_crawled: crawl_stats.files_crawled,
+        files_resumed: crawl_stats.files_resumed,
+        files_failed: crawl_stats.files_failed,
+        files_truncated: crawl_stats.files_truncated,
+        requests_sent: client.requests_sent(),
+        requests_per_second: client.requests_sent() as f64 / (crawl_ms.max(1) as f64 / 1000.0),
+        server_respawns: client.respawns(),
+        server_restarts: client.restarts(),
+        failed_definition_requests: crawl_stats.failed_definition_requests,
+        empty_definition_responses: crawl_stats.empty_definition_responses,
+        timed_out,
+        durations: PhaseDurations {
+            crawl_ms,
+            ..stats.durations
+        },
+        ..stats
+    };
+    Ok((stats, emitter))
+}
+
+/// The definitions and references found in a single file.
+#[derive(Default)]
+struct CrawledFile {
+    definitions: Vec<Definition>,
+    references: Vec<Reference>,
+    extras: Option<DocumentExtras>,
+    failed_requests: u64,
+    /// The number of definition requests that found nothing.
+    empty_responses: u64,
+    /// The number of words that were not probed because of `--max-requests-per-file`.
+    skipped_words: usize,
+}
+
+/// The words of the last tokenized file, keyed by its path, a hash of its text and the
+/// position encoding of their ranges.
+#[derive(Default)]
+struct TokenCache {
+    key: Option<(PathBuf, Vec<u8>, PositionEncoding)>,
+    words: Vec<(String, LspRange)>,
+}
+
+impl TokenCache {
+    /// Returns the words of the given file, which are only tokenized if it is not the last
+    /// file, or if its text or the position encoding changed.
+    fn words(
+        &mut self,
+        p: &Path,
+        text: &str,
+        config: &LSConfig,
+        encoding: PositionEncoding,
+    ) -> Vec<(String, LspRange)> {
+        let key = (
+            p.to_path_buf(),
+            Sha256::digest(text.as_bytes()).to_vec(),
+            encoding,
+        );
+        if self.key.as_ref() != Some(&key) {
+            self.words = tokenize(text.to_string(), config, encoding);
+            self.key = Some(key);
+        }
+        self.words.clone()
+    }
+}
+
+/// Sends the definition requests for every word in the given file and returns the
+/// definitions and references that were found, as classified by `strategy`. With
+/// `--strategy references`, sends a references request for each document symbol instead.
+/// With `--strategy workspace-symbols`, `workspace_symbols` are the symbols of the file, and
+/// its document symbols are only requested if there are none, e.g. since they may be
+/// incomplete.
+///
+/// `error_streak` counts the definition or references requests in a row, across files, that
+/// the server returned an error for.
+///
+/// `hovered_definitions` are the definitions crawled so far that have a hover, either their
+/// own or the one at one of their references. The hover at a reference is only requested
+/// for the other definitions, since the indexer only needs one.
+///
+/// `token_cache` keeps the words of the file, so that they are not tokenized again when the
+/// file is crawled again after the server is respawned.
+#[allow(clippy::too_many_arguments)]
+fn crawl_file(
+    p: &Path,
+    args: &Args,
+    config: &LSConfig,
+    client: &mut LSClient,
+    strategy: &dyn ResolutionStrategy,
+    workspace_symbols: Option<&[SymbolInformation]>,
+    error_streak: &mut u64,
+    hovered_definitions: &mut HashSet<Location>,
+    token_cache: &mut TokenCache,
+) -> Result<CrawledFile> {
+    let text = args
+        .overlay
+        .read_to_string(p)
+        .with_context(|| format!("Could not read `{}`", p.display()))?;
+
+    let mut crawled = crawl_text(
+        p,
+        &text,
+        args,
+        config,
+        client,
+        strategy,
+        workspace_symbols,
+        error_streak,
+        hovered_definitions,
+        token_cache,
+    )?;
+    if args.emit_offsets {
+        add_offsets(&mut crawled, p, text, client.position_encoding(), args);
+    }
+    Ok(crawled)
+}
+
+/// Same as `crawl_file`, with the given text of the file.
+#[allow(clippy::too_many_arguments)]
+fn crawl_text(
+    p: &Path,
+    text: &str,
+    args: &Args,
+    config: &LSConfig,
+    client: &mut LSClient,
+    strategy: &dyn ResolutionStrategy,
+    workspace_symbols: Option<&[SymbolInformation]>,
+    error_streak: &mut u64,
+    hovered_definitions: &mut HashSet<Location>,
+    token_cache: &mut TokenCache,
+) -> Result<CrawledFile> {
+    client.set_document(p, config.language_id(p, &args.language), text.to_string())?;
+    if let Some(timeout) = args.wait_for_diagnostics {
+        if !client.wait_until_diagnostics_published(p, Duration::from_millis(timeout)) {
+            log::debug!(
+                "No diagnostics were published for `{}` within {} ms",
+                p.display(),
+                timeout
+            );
+        }
+    }
+    if args.open_settle_ms > 0 {
+        std::thread::sleep(Duration::from_millis(args.open_settle_ms));
+    }
+
+    let uri = file_uri(p);
+    let lines = text.split('\n').collect::<Vec<_>>();
+    let encoding = client.position_encoding();
+    let symbols = match workspace_symbols {
+        Some(symbols) if !symbols.is_empty() => symbols
+            .iter()
+            .map(|symbol| workspace_symbol_info(symbol, &lines, encoding))
+            .collect(),
+        _ if client.supports_document_symbol() => client
+            .get_document_symbols(p)
+            .ok()
+            .flatten()
+            .map(flatten_document_symbols)
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    let mut crawled = CrawledFile::default();
+    if args.extras {
+        crawled.extras = Some(get_document_extras(p, client));
+    }
+
+    if args.defs_only && (workspace_symbols.is_some() || client.supports_document_symbol()) {
+        // The symbols are all the definitions there are, so nothing needs to be probed
+        crawled.definitions = symbol_definitions(p, &symbols, &lines, args, config, client);
+        return Ok(crawled);
+    }
+    if args.strategy != Strategy::Probe {
+        crawled.definitions = symbol_definitions(p, &symbols, &lines, args, config, client);
+        for def in &crawled.definitions {
+            let params = query_params(p, def.location.range.range().start, args)?;
+            match client.get_references(params) {
+                Ok(locations) => {
+                    *error_streak = 0;
+                    let mut locations = locations.unwrap_or_default();
+                    if let Some(adjust) = response_adjust(args) {
+                        for location in &mut locations {
+                            location.range = adjust.revert(location.range);
+                        }
+                    }
+                    crawled.references.extend(symbol_references(def, locations));
+                }
+                Err(err) if is_fatal(&err) => return Err(err),
+                Err(err) => {
+                    if is_server_error(&err) {
+                        *error_streak += 1;
+                    }
+                    crawled.failed_requests += 1;
+                }
+            }
+        }
+        return Ok(crawled);
+    }
+
+    let mut words = token_cache
+        .words(p, text, config, client.position_encoding())
+        .into_iter()
+        .filter(|(word, _range)| !config.keywords.contains(word))
+        .collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    if args.semantic_tokens && client.supports_semantic_tokens() {
+        if let Ok(semantic_tokens) = client.get_semantic_tokens(p) {
+            words = identifier_words(words, &semantic_tokens);
+            tokens = semantic_tokens;
+        }
+    }
+    if args.max_requests_per_file > 0 && words.len() > args.max_requests_per_file {
+        crawled.skipped_words = words.len() - args.max_requests_per_file;
+        words.truncate(args.max_requests_per_file);
+    }
+    let mut responses = get_definitions_at(
+        p,
+        words.iter().map(|(_word, range)| range.start),
+        args,
+        client,
+    )?;
+    if config.requery_member_access {
+        let retries = member_access_retries(text, &words, &responses, client.position_encoding());
+        let retried =
+            get_definitions_at(p, retries.iter().map(|idx| words[*idx].1.end), args, client)?;
+        for (idx, resp) in retries.into_iter().zip(retried) {
+            if resp.is_ok() && !has_no_definition(&resp) {
+                responses[idx] = resp;
+            }
+        }
+    }
+
+    let mut reference_hovers = HashMap::new();
+    let mut declarations = HashSet::new();
+    words
+        .into_iter()
+        .zip(responses)
+        .try_for_each(|((word, range), resp)| -> Result<()> {
+            let start = range.start;
+
+            match &resp {
+                Err(err) if is_server_error(err) => *error_streak += 1,
+                _ => *error_streak = 0,
+            }
+            if has_no_definition(&resp) {
+                crawled.empty_responses += 1;
+            }
+            match resp {
+                Ok(resp) => match claim_declaration(
+                    strategy.classify(&word, &uri, range, &resp),
+                    &uri,
+                    &mut declarations,
+                ) {
+                    Classification::Definition(resolved_range) => {
+                        let symbol = innermost_symbol_at(&symbols, start);
+                        let comment = match definition_kind(symbol.as_ref(), &tokens, start) {
+                            Some(kind) if skips_hover(args, kind) => None,
+                            _ => get_hover_text(p, start, client),
+                        };
+                        let location = Location {
+                            file_path: uri.clone(),
+                            range: Range::new(range),
                         };
-                        if def_location.is_none() {
-                            return Ok(());
+                        if comment.is_some() {
+                            hovered_definitions.insert(location.clone());
+                            if let Some(resolved_range) = &resolved_range {
+                                hovered_definitions.insert(Location {
+                                    file_path: uri.clone(),
+                                    range: resolved_range.clone(),
+                                });
+                            }
                         }
-                        let def_location = def_location.unwrap();
-
-                        if def_location.range.start == start
-                            && Url::from_file_path(&p).unwrap().to_string()
-                                == def_location.uri.to_string()
-                        {
-                            // it defines itself, so it's a declaration
-                            def_tx.send(Definition {
-                                location: Location {
-                                    file_path: def_location.uri.to_string(),
-                                    range: Range { lsp_range: range },
-                                },
-                                node_name: word.clone(),
-                                comment: None,
-                            })?;
+                        crawled.definitions.push(Definition {
+                            resolved_range,
+                            declaration: get_declaration(p, &location, client),
+                            renameable: get_renameable(p, &location, args, client),
+                            exported: is_exported(config, &lines, range, &word, encoding),
+                            location,
+                            node_name: word.clone(),
+                            comment,
+                            symbol,
+                        });
+                    }
+                    Classification::Reference(_) if args.defs_only => {}
+                    Classification::Reference(def_location) => {
+                        // Used in case the definition has no hover of its own
+                        let comment = if hovered_definitions.contains(&def_location) {
+                            None
                         } else {
-                            ref_tx.send(Reference {
-                                location: Location {
-                                    file_path: Url::from_file_path(&p).unwrap().to_string(),
-                                    range: Range { lsp_range: range },
-                                },
-                                node_name: word.clone(),
-                                def: Definition {
-                                    location: Location {
-                                        file_path: def_location.uri.to_string(),
-                                        range: Range {
-                                            lsp_range: def_location.range,
-                                        },
-                                    },
-                                    node_name: word,
-                                    comment: None,
-                                },
-                            })?;
+                            reference_hovers
+                                .entry(def_location.clone())
+                                .or_insert_with(|| get_hover_text(p, start, client))
+                                .clone()
+                        };
+                        if comment.is_some() {
+                            hovered_definitions.insert(def_location.clone());
                         }
+                        crawled.references.push(Reference {
+                            location: Location {
+                                file_path: uri.clone(),
+                                range: Range::new(range),
+                            },
+                            node_name: word.clone(),
+                            comment,
+                            def: Definition {
+                                location: def_location,
+                                node_name: word,
+                                comment: None,
+                                symbol: None,
+                                resolved_range: None,
+                                declaration: None,
+                                exported: false,
+                                renameable: None,
+                            },
+                        });
                     }
-                    Err(_err) => {
-                        //dbg!(err);
+                    Classification::Skip => {}
+                },
+                Err(_err) => {
+                    //dbg!(err);
+                    crawled.failed_requests += 1;
+                }
+            }
+
+            Ok(())
+        })?;
+
+    Ok(crawled)
+}
+
+/// Turns a word that the server resolves to a declaration that an earlier word of the file
+/// declares into a reference to it, e.g. the recursive call in `def foo; foo; end`, since
+/// only the first word in a declaration is its name.
+fn claim_declaration(
+    classification: Classification,
+    uri: &str,
+    declarations: &mut HashSet<Range>,
+) -> Classification {
+    match classification {
+        Classification::Definition(Some(declaration))
+            if !declarations.insert(declaration.clone()) =>
+        {
+            Classification::Reference(Location {
+                file_path: uri.to_string(),
+                range: declaration,
+            })
+        }
+        classification => classification,
+    }
+}
+
+/// The queries to list the workspace symbols with when the server returns none for an empty
+/// query. Servers match the query fuzzily, so every symbol contains one of them.
+const WORKSPACE_SYMBOL_QUERIES: &[&str] = &[
+    "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s",
+    "t", "u", "v", "w", "x", "y", "z", "_",
+];
+
+/// The symbols of the whole workspace, as returned by `get_workspace_symbols`.
+#[derive(Debug, Default)]
+struct WorkspaceSymbols {
+    /// File (as returned by `file_uri`) -> its symbols
+    by_file: HashMap<String, Vec<SymbolInformation>>,
+    /// The files that were in a response that the server may have cut, whose symbols may be
+    /// incomplete
+    cut_files: HashSet<String>,
+    /// Whether every query was answered in full, so that a file without symbols has none
+    complete: bool,
+}
+
+impl WorkspaceSymbols {
+    /// Returns the symbols of the given file, or none if they may be incomplete, so that the
+    /// file falls back to `textDocument/documentSymbol`.
+    fn of_file(&self, p: &Path) -> &[SymbolInformation] {
+        let uri = file_uri(p);
+        match self.by_file.get(&uri) {
+            Some(symbols) if !self.cut_files.contains(&uri) => symbols,
+            _ => &[],
+        }
+    }
+
+    /// Returns whether the given file is known to have no symbols, so that it need not be
+    /// crawled.
+    fn has_no_symbols(&self, p: &Path) -> bool {
+        self.complete && !self.by_file.contains_key(&file_uri(p))
+    }
+}
+
+/// Returns the symbols of the whole workspace with `workspace/symbol`. Servers that require
+/// a non-empty query or cut the response to it are asked for the symbols that contain each
+/// letter instead. A response with `limit` symbols is taken as cut by the server, and its
+/// files as incomplete. The queries that fail are skipped, and leave all files incomplete.
+fn get_workspace_symbols(client: &mut LSClient, limit: Option<usize>) -> Result<WorkspaceSymbols> {
+    let is_cut = |symbols: &[SymbolInformation]| limit.is_some_and(|limit| symbols.len() >= limit);
+    let mut responses = Vec::new();
+    let mut complete = true;
+    match client.workspace_symbols("") {
+        Err(err) if is_fatal(&err) => return Err(err),
+        Ok(symbols) if !symbols.is_empty() && !is_cut(&symbols) => responses.push(symbols),
+        _ => {
+            for query in WORKSPACE_SYMBOL_QUERIES {
+                match client.workspace_symbols(query) {
+                    Ok(symbols) => responses.push(symbols),
+                    Err(err) if is_fatal(&err) => return Err(err),
+                    Err(err) => {
+                        log::warn!("Skipping the workspace symbols for `{}`: {:#}", query, err);
+                        complete = false;
                     }
                 }
+            }
+        }
+    }
 
-                Ok(())
-            })?;
+    Ok(group_workspace_symbols(responses, complete, is_cut))
+}
+
+/// Groups the symbols of the given `workspace/symbol` responses by file. `complete` is
+/// whether no query failed, and `is_cut` whether the server cut the given response.
+fn group_workspace_symbols(
+    responses: Vec<Vec<SymbolInformation>>,
+    mut complete: bool,
+    is_cut: impl Fn(&[SymbolInformation]) -> bool,
+) -> WorkspaceSymbols {
+    let mut seen = HashSet::new();
+    let mut symbols = WorkspaceSymbols::default();
+    for response in responses {
+        let cut = is_cut(&response);
+        complete &= !cut;
+        for symbol in response {
+            let file_path = normalize_uri(&symbol.location.uri);
+            if cut {
+                symbols.cut_files.insert(file_path.clone());
+            }
+            if seen.insert((file_path.clone(), Range::new(symbol.location.range))) {
+                symbols.by_file.entry(file_path).or_default().push(symbol);
+            }
+        }
+    }
+    // A server that returns no symbols at all may not have indexed the workspace
+    symbols.complete = complete && !symbols.by_file.is_empty();
+    log::debug!(
+        "Found {} workspace symbols in {} files, {} of them possibly incomplete",
+        seen.len(),
+        symbols.by_file.len(),
+        symbols.cut_files.len()
+    );
+    symbols
+}
+
+/// Converts a workspace symbol of the file with the given lines. Its range is that of the
+/// whole declaration, so its name is looked up in it for the references requests.
+fn workspace_symbol_info(
+    symbol: &SymbolInformation,
+    lines: &[&str],
+    encoding: PositionEncoding,
+) -> SymbolInfo {
+    let range = symbol.location.range;
+    SymbolInfo {
+        name: symbol.name.clone(),
+        kind: symbol.kind,
+        full_range: range,
+        selection_range: name_range(lines, range, &symbol.name, encoding).unwrap_or(range),
+        detail: symbol.container_name.clone(),
+    }
+}
+
+/// Returns the range of the first occurrence of the word `name` in the given range of a file,
+/// e.g. of the name of a function in its declaration.
+fn name_range(
+    lines: &[&str],
+    range: LspRange,
+    name: &str,
+    encoding: PositionEncoding,
+) -> Option<LspRange> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    for line_number in range.start.line..=range.end.line {
+        let line = lines.get(line_number as usize)?;
+        let mut from = if line_number == range.start.line {
+            encoding.byte_offset(line, range.start.character)
+        } else {
+            0
+        };
+        while let Some(found) = line[from..].find(name) {
+            let (start, end) = (from + found, from + found + name.len());
+            let before = line[..start].chars().next_back();
+            let after = line[end..].chars().next();
+            if !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char) {
+                return Some(LspRange::new(
+                    Position::new(line_number, encoding.offset(line, start)),
+                    Position::new(line_number, encoding.offset(line, end)),
+                ));
+            }
+            from = end;
+        }
+    }
+    None
+}
+
+/// Returns the definitions of the given document symbols of the file at `p`.
+fn symbol_definitions(
+    p: &Path,
+    symbols: &[SymbolInfo],
+    lines: &[&str],
+    args: &Args,
+    config: &LSConfig,
+    client: &mut LSClient,
+) -> Vec<Definition> {
+    let encoding = client.position_encoding();
+    let mut definitions = Vec::new();
+    for symbol in symbols {
+        let location = Location {
+            file_path: file_uri(p),
+            range: Range::new(symbol.selection_range),
+        };
+        definitions.push(Definition {
+            declaration: get_declaration(p, &location, client),
+            renameable: get_renameable(p, &location, args, client),
+            exported: is_exported(
+                config,
+                lines,
+                symbol.selection_range,
+                &symbol.name,
+                encoding,
+            ),
+            location,
+            node_name: symbol.name.clone(),
+            comment: if skips_hover(args, symbol_kind_name(symbol.kind)) {
+                None
+            } else {
+                get_hover_text(p, symbol.selection_range.start, client)
+            },
+            symbol: Some(symbol.clone()),
+            resolved_range: None,
+        });
+    }
+    definitions
+}
+
+/// Returns the references to the given definition at the locations returned by a
+/// `textDocument/references` request, without the definition itself, which some servers
+/// return anyway.
+fn symbol_references(def: &Definition, locations: Vec<LspLocation>) -> Vec<Reference> {
+    locations
+        .into_iter()
+        .map(|location| Location {
+            file_path: normalize_uri(&location.uri),
+            range: Range::new(location.range),
+        })
+        .filter(|location| location != &def.location)
+        .map(|location| Reference {
+            location,
+            node_name: def.node_name.clone(),
+            // The hover of the definition is used
+            comment: None,
+            def: Definition {
+                location: def.location.clone(),
+                node_name: def.node_name.clone(),
+                comment: None,
+                symbol: None,
+                resolved_range: None,
+                declaration: None,
+                exported: false,
+                renameable: None,
+            },
+        })
+        .collect()
+}
+
+/// Reports the crawling progress with a progress bar, or with a plain line every few files
+/// when `plain` is set, since progress bars garble non-TTY logs.
+struct Progress {
+    bar: Option<ProgressBar>,
+    position: u64,
+    total: u64,
+    /// The position at which the next plain line is printed
+    next_line: u64,
+}
+
+impl Progress {
+    fn new(total: u64, plain: bool) -> Self {
+        let bar = if plain {
+            None
+        } else {
+            let bar = ProgressBar::new(total);
+            bar.set_message("Waiting for the language server to finish indexing");
+            Some(bar)
+        };
+        Self {
+            bar,
+            position: 0,
+            total,
+            next_line: Self::line_every(total),
+        }
+    }
 
-        pb.inc(1);
+    /// Returns how many files are crawled between two plain lines.
+    fn line_every(total: u64) -> u64 {
+        (total / 20).max(1)
     }
 
-    drop(def_tx);
-    drop(ref_tx);
-    indexer_proc.join().unwrap()?;
-    flush_signal.recv()?;
+    fn inc(&mut self) {
+        self.position += 1;
+        match &self.bar {
+            Some(bar) => bar.inc(1),
+            None => {
+                if self.position >= self.next_line || self.position == self.total {
+                    eprintln!("Indexed {}/{} files", self.position, self.total);
+                    self.next_line += Self::line_every(self.total);
+                }
+            }
+        }
+    }
+
+    fn println(&self, msg: String) {
+        match &self.bar {
+            Some(bar) => bar.println(msg),
+            None => eprintln!("{}", msg),
+        }
+    }
+}
+
+/// Returns the contents of the hover at the given position, or `None` if the server does
+/// not support hovers or the hover is empty.
+fn get_hover_text(p: &Path, position: Position, client: &mut LSClient) -> Option<String> {
+    if !client.supports_hover() {
+        return None;
+    }
+    client
+        .get_hover(position_params(p, position).ok()?)
+        .ok()
+        .flatten()
+        .map(hover_to_string)
+        .filter(|text| !text.trim().is_empty())
+}
+
+/// Returns whether the hover of a definition of the given kind is not requested, with
+/// `--lazy-hover`.
+fn skips_hover(args: &Args, kind: &str) -> bool {
+    args.lazy_hover
+        && args
+            .lazy_hover_skip_kinds
+            .iter()
+            .any(|skipped| skipped == kind)
+}
+
+/// Returns the kind of the definition whose name starts at the given position: that of its
+/// document symbol, or else the type of its semantic token, e.g. `variable` or `parameter`.
+fn definition_kind<'a>(
+    symbol: Option<&SymbolInfo>,
+    tokens: &'a [SemanticToken],
+    start: Position,
+) -> Option<&'a str> {
+    if let Some(symbol) = symbol {
+        return Some(symbol_kind_name(symbol.kind));
+    }
+    tokens
+        .iter()
+        .find(|t| (t.line, t.start) == (start.line, start.character))
+        .map(|t| t.token_type.as_str())
+}
+
+/// Returns the name of the given symbol kind, in camelCase like the semantic token types.
+fn symbol_kind_name(kind: SymbolKind) -> &'static str {
+    match kind {
+        SymbolKind::File => "file",
+        SymbolKind::Module => "module",
+        SymbolKind::Namespace => "namespace",
+        SymbolKind::Package => "package",
+        SymbolKind::Class => "class",
+        SymbolKind::Method => "method",
+        SymbolKind::Property => "property",
+        SymbolKind::Field => "field",
+        SymbolKind::Constructor => "constructor",
+        SymbolKind::Enum => "enum",
+        SymbolKind::Interface => "interface",
+        SymbolKind::Function => "function",
+        SymbolKind::Variable => "variable",
+        SymbolKind::Constant => "constant",
+        SymbolKind::String => "string",
+        SymbolKind::Number => "number",
+        SymbolKind::Boolean => "boolean",
+        SymbolKind::Array => "array",
+        SymbolKind::Object => "object",
+        SymbolKind::Key => "key",
+        SymbolKind::Null => "null",
+        SymbolKind::EnumMember => "enumMember",
+        SymbolKind::Struct => "struct",
+        SymbolKind::Event => "event",
+        SymbolKind::Operator => "operator",
+        SymbolKind::TypeParameter => "typeParameter",
+        SymbolKind::Unknown => "unknown",
+    }
+}
+
+/// Returns the location of the declaration of the symbol defined at the given location, if
+/// the server supports `textDocument/declaration` and the declaration is somewhere else.
+fn get_declaration(p: &Path, definition: &Location, client: &mut LSClient) -> Option<Location> {
+    if !client.supports_declaration() {
+        return None;
+    }
+    let response = client
+        .get_declaration(position_params(p, definition.range.range().start).ok()?)
+        .ok()??;
+    let location = match response {
+        GotoDefinitionResponse::Scalar(it) => it,
+        GotoDefinitionResponse::Array(it) => it.into_iter().next()?,
+        GotoDefinitionResponse::Link(_) => return None,
+    };
+    let declaration = Location {
+        file_path: normalize_uri(&location.uri),
+        range: Range::new(location.range),
+    };
+    if declaration.file_path == definition.file_path
+        && declaration.range.range().start == definition.range.range().start
+    {
+        // Declared where it is defined
+        return None;
+    }
+    Some(declaration)
+}
+
+/// Returns whether the symbol defined at the given location can be renamed, with `--extras`
+/// if the server supports `textDocument/prepareRename`. The server answers `null` or an
+/// error for the symbols that cannot be renamed.
+fn get_renameable(
+    p: &Path,
+    definition: &Location,
+    args: &Args,
+    client: &mut LSClient,
+) -> Option<bool> {
+    if !args.extras || !client.supports_prepare_rename() {
+        return None;
+    }
+    let params = position_params(p, definition.range.range().start).ok()?;
+    match client.prepare_rename(params) {
+        Ok(response) => Some(!response.is_null()),
+        Err(err) if is_fatal(&err) => None,
+        Err(_) => Some(false),
+    }
+}
+
+/// Returns the folding ranges and document links of the given file, skipping the requests
+/// that the server does not support.
+fn get_document_extras(p: &Path, client: &mut LSClient) -> DocumentExtras {
+    let folding_ranges = if client.supports_folding_range() {
+        client.get_folding_ranges(p).ok()
+    } else {
+        None
+    };
+    let document_links = if client.supports_document_link() {
+        client.get_document_links(p).ok()
+    } else {
+        None
+    };
+    DocumentExtras {
+        file_path: file_uri(p),
+        folding_ranges: folding_ranges.filter(|v| !v.is_null()),
+        document_links: document_links.filter(|v| !v.is_null()),
+        diagnostics: None,
+    }
+}
+
+/// Returns true if the given error means that the crawl cannot continue, e.g. because the
+/// language server has exited, which shows as its messages ending or as a failed write.
+/// Any other error only affects the file being crawled.
+fn is_fatal(err: &Error) -> bool {
+    err.is::<RecvError>() || err.is::<WriteError>()
+}
+
+/// Returns the symbols in the given `textDocument/documentSymbol` response, with nested
+/// symbols following their parents.
+fn flatten_document_symbols(resp: DocumentSymbolResponse) -> Vec<SymbolInfo> {
+    fn push_nested(symbols: Vec<DocumentSymbol>, res: &mut Vec<SymbolInfo>) {
+        for s in symbols {
+            res.push(SymbolInfo {
+                name: s.name,
+                kind: s.kind,
+                full_range: s.range,
+                selection_range: s.selection_range,
+                detail: s.detail,
+            });
+            push_nested(s.children.unwrap_or_default(), res);
+        }
+    }
+
+    let mut res = Vec::new();
+    match resp {
+        DocumentSymbolResponse::Flat(symbols) => {
+            res.extend(symbols.into_iter().map(|s| SymbolInfo {
+                name: s.name,
+                kind: s.kind,
+                full_range: s.location.range,
+                selection_range: s.location.range,
+                detail: None,
+            }))
+        }
+        DocumentSymbolResponse::Nested(symbols) => push_nested(symbols, &mut res),
+    }
+    res
+}
+
+/// The advertised semantic token types of the words that cannot be definitions or references.
+const NON_IDENTIFIER_TOKEN_TYPES: &[&str] = &[
+    "keyword",
+    "modifier",
+    "comment",
+    "string",
+    "number",
+    "regexp",
+    "operator",
+    "decorator",
+];
+
+lazy_static! {
+    /// The semantic token types of the words that can be definitions or references.
+    static ref IDENTIFIER_TOKEN_TYPES: Vec<&'static str> = SEMANTIC_TOKEN_TYPES
+        .iter()
+        .copied()
+        .filter(|token_type| !NON_IDENTIFIER_TOKEN_TYPES.contains(token_type))
+        .collect();
+}
+
+/// Returns the words that start at a semantic token with one of `IDENTIFIER_TOKEN_TYPES`,
+/// skipping keywords, comments, strings and the like.
+fn identifier_words(
+    words: Vec<(String, LspRange)>,
+    tokens: &[SemanticToken],
+) -> Vec<(String, LspRange)> {
+    let starts = tokens
+        .iter()
+        .filter(|t| IDENTIFIER_TOKEN_TYPES.contains(&t.token_type.as_str()))
+        .map(|t| (t.line, t.start))
+        .collect::<HashSet<_>>();
+    words
+        .into_iter()
+        .filter(|(_word, range)| starts.contains(&(range.start.line, range.start.character)))
+        .collect()
+}
+
+/// Returns the innermost symbol whose name contains the given position.
+fn innermost_symbol_at(symbols: &[SymbolInfo], position: Position) -> Option<SymbolInfo> {
+    symbols
+        .iter()
+        .rev()
+        .find(|s| range_contains(&s.selection_range, position))
+        .cloned()
+}
+
+fn range_contains(range: &LspRange, position: Position) -> bool {
+    let pos = (position.line, position.character);
+    (range.start.line, range.start.character) <= pos && pos <= (range.end.line, range.end.character)
+}
+
+/// Returns the parameters for a position-based request on the given file.
+fn position_params(path: &Path, position: Position) -> Result<TextDocumentPositionParams> {
+    Ok(TextDocumentPositionParams {
+        text_document: TextDocumentIdentifier {
+            uri: path_to_uri(path)?,
+        },
+        position,
+    })
+}
+
+/// Same as `position_params`, for a definition or references request, with the position
+/// moved by `--position-adjust`.
+fn query_params(
+    path: &Path,
+    position: Position,
+    args: &Args,
+) -> Result<TextDocumentPositionParams> {
+    let position = match args.position_adjust {
+        Some(adjust) => adjust.apply(position),
+        None => position,
+    };
+    position_params(path, position)
+}
+
+/// Sends a definition request at each of the given positions of the file at `p`, moved by
+/// `--position-adjust`, and moves the ranges of the responses back with
+/// `--position-adjust-responses`.
+fn get_definitions_at(
+    p: &Path,
+    positions: impl Iterator<Item = Position>,
+    args: &Args,
+    client: &mut LSClient,
+) -> Result<Vec<Result<GotoDefinitionResponse>>> {
+    let responses = client.get_definitions(
+        positions
+            .map(|position| query_params(p, position, args))
+            .collect::<Result<_>>()?,
+        args.concurrency_limit,
+    )?;
+    let adjust = match response_adjust(args) {
+        Some(adjust) => adjust,
+        None => return Ok(responses),
+    };
+    let revert = |mut location: LspLocation| {
+        location.range = adjust.revert(location.range);
+        location
+    };
+    Ok(responses
+        .into_iter()
+        .map(|resp| {
+            resp.map(|resp| match resp {
+                GotoDefinitionResponse::Scalar(location) => {
+                    GotoDefinitionResponse::Scalar(revert(location))
+                }
+                GotoDefinitionResponse::Array(locations) => {
+                    GotoDefinitionResponse::Array(locations.into_iter().map(revert).collect())
+                }
+                GotoDefinitionResponse::Link(mut links) => {
+                    for link in &mut links {
+                        link.target_range = adjust.revert(link.target_range);
+                        link.target_selection_range = link
+                            .target_selection_range
+                            .map(|range| adjust.revert(range));
+                    }
+                    GotoDefinitionResponse::Link(links)
+                }
+            })
+        })
+        .collect())
+}
+
+/// Returns the offset to move the ranges of the responses back by, with
+/// `--position-adjust-responses`.
+fn response_adjust(args: &Args) -> Option<PositionAdjust> {
+    args.position_adjust
+        .filter(|_adjust| args.position_adjust_responses)
+}
+
+/// Returns whether the symbol with the given name, defined at the given range of one of the
+/// given lines, is exported.
+fn is_exported(
+    config: &LSConfig,
+    lines: &[&str],
+    range: LspRange,
+    name: &str,
+    encoding: PositionEncoding,
+) -> bool {
+    lines.get(range.start.line as usize).is_some_and(|line| {
+        let start = encoding.byte_offset(line, range.start.character);
+        config.is_exported(&line[..start], name)
+    })
+}
+
+/// Returns the indices of the words that are the member of a member access, e.g. `c` in
+/// `a.b.c()` or `a::b::c`, and for which `responses` has no definition.
+fn member_access_retries(
+    text: &str,
+    words: &[(String, LspRange)],
+    responses: &[Result<GotoDefinitionResponse>],
+    encoding: PositionEncoding,
+) -> Vec<usize> {
+    let lines = text.split('\n').collect::<Vec<_>>();
+    words
+        .iter()
+        .zip(responses)
+        .enumerate()
+        .filter(|(_idx, ((_word, range), resp))| {
+            let line = match lines.get(range.start.line as usize) {
+                Some(line) => line,
+                None => return false,
+            };
+            let before = line[..encoding.byte_offset(line, range.start.character)].trim_end();
+            let is_member =
+                (before.ends_with('.') && !before.ends_with("..")) || before.ends_with("::");
+            is_member && has_no_definition(resp)
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Returns whether the server found no definition in its response to a definition request,
+/// as opposed to returning one or failing.
+fn has_no_definition(resp: &Result<GotoDefinitionResponse>) -> bool {
+    match resp {
+        Ok(GotoDefinitionResponse::Array(locations)) => locations.is_empty(),
+        Ok(_) | Err(_) => false,
+    }
+}
 
-    Ok(())
+/// Returns the words to probe in the given text: the identifiers found by tree-sitter if the
+/// language has a grammar and the `tree-sitter` feature is enabled, or all the words found by
+/// `get_words` otherwise.
+#[cfg_attr(not(feature = "tree-sitter"), allow(unused_variables))]
+pub fn tokenize(
+    text: String,
+    config: &LSConfig,
+    encoding: PositionEncoding,
+) -> Vec<(String, LspRange)> {
+    #[cfg(feature = "tree-sitter")]
+    {
+        let words = config
+            .grammar
+            .as_deref()
+            .and_then(|grammar| crate::syntax::identifier_words(&text, grammar, encoding));
+        if let Some(words) = words {
+            return words;
+        }
+    }
+    get_words(text, encoding)
 }
 
-fn get_words(text: String) -> Vec<(String, LspRange)> {
+/// Returns the words of the given text with their ranges, with offsets in the given encoding.
+pub fn get_words(text: String, encoding: PositionEncoding) -> Vec<(String, LspRange)> {
     let mut res = Vec::new();
     for (idx, line) in text.split('\n').enumerate() {
         lazy_static! {
             static ref RE: Regex = Regex::new("\\w+(?:'\\w+)*").unwrap();
         }
 
+        // The byte and encoded offsets of the previous match, so that each offset is counted
+        // from there rather than from the start of the line
+        let mut previous = (0, 0);
+        let mut offset = |byte_offset: usize| {
+            previous.1 += encoding.offset(&line[previous.0..], byte_offset - previous.0);
+            previous.0 = byte_offset;
+            previous.1
+        };
         for m in RE.find_iter(line) {
             let range = LspRange {
                 start: Position {
                     line: idx as u64,
-                    character: m.start() as u64,
+                    character: offset(m.start()),
                 },
                 end: Position {
                     line: idx as u64,
-                    character: m.end() as u64,
+                    character: offset(m.end()),
                 },
             };
             res.push((m.as_str().to_string(), range));
@@ -148,11 +1356,31 @@ fn get_words(text: String) -> Vec<(String, LspRange)> {
     res
 }
 
+/// Creates the output file, or the first shard with `--shard-by`. Shards left over from a
+/// previous run are removed.
 fn get_output_file(args: &Args) -> Result<File> {
+    let path = match args.shards() {
+        Some(shards) => {
+            for path in shards.existing_paths() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Could not remove `{}`", path.display()))?;
+            }
+            shards.path(0)
+        }
+        None => args.output.clone().unwrap(),
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| {
+            format!(
+                "Could not create the output directory `{}`",
+                parent.display()
+            )
+        })?;
+    }
     let output = std::fs::OpenOptions::new()
         .write(true)
         .create(true)
-        .open(&args.output.clone().unwrap())
+        .open(&path)
         .context("Could not open the output file")?;
     output
         .set_len(0)
@@ -160,41 +1388,83 @@ fn get_output_file(args: &Args) -> Result<File> {
     Ok(output)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Definition {
     pub location: Location,
     pub node_name: String,
     pub comment: Option<String>,
+    pub symbol: Option<SymbolInfo>,
+    /// The range of the same file that the server resolves the symbol to, if it is not that
+    /// of `location`, e.g. its whole declaration. References that resolve to it are linked
+    /// to this definition.
+    #[serde(default)]
+    pub resolved_range: Option<Range>,
+    /// Where the symbol is declared, if that is not where it is defined, e.g. in a header.
+    #[serde(default)]
+    pub declaration: Option<Location>,
+    /// Whether the symbol is visible outside of its project, as told by `LSConfig::is_exported`.
+    #[serde(default)]
+    pub exported: bool,
+    /// Whether the server can rename the symbol, as told by `textDocument/prepareRename`
+    /// with `--extras`.
+    #[serde(default)]
+    pub renameable: Option<bool>,
+}
+
+/// The results of the requests enabled with `--extras` for a single file, passed through
+/// from the language server as they are.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocumentExtras {
+    pub file_path: String,
+    pub folding_ranges: Option<serde_json::Value>,
+    pub document_links: Option<serde_json::Value>,
+    /// The diagnostics published by the server, with `--include-diagnostics`.
+    #[serde(default)]
+    pub diagnostics: Option<serde_json::Value>,
 }
 
+/// Information about a symbol, as reported by `textDocument/documentSymbol`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolInfo {
+    #[serde(default)]
+    pub name: String,
+    pub kind: SymbolKind,
+    pub full_range: LspRange,
+    pub selection_range: LspRange,
+    pub detail: Option<String>,
+}
+
+/// A definition or reference found by the crawler, in the order they were found.
 #[derive(Debug, Clone)]
+pub enum Occurrence {
+    Definition(Definition),
+    Reference(Reference),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Reference {
     pub location: Location,
     pub node_name: String,
+    /// The hover at the reference, attached to the definition if it has none.
+    #[serde(default)]
+    pub comment: Option<String>,
     pub def: Definition,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Location {
     pub file_path: String,
     pub range: Range,
 }
 
-impl Location {
-    /// Returns the name of the file (the final component of the file path)
-    pub fn file_name(&self) -> String {
-        PathBuf::from(&self.file_path)
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string()
-    }
-}
-
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// A range of a file. Ranges with the same positions are equal, whether their offsets are
+/// known or not.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Range {
     lsp_range: protocol::types::Range,
+    /// The byte offsets of the range, with `--emit-offsets`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    offsets: Option<ByteOffsets>,
 }
 
 impl std::hash::Hash for Range {
@@ -208,29 +1478,194 @@ impl Definition {
     pub fn range(&self) -> protocol::types::Range {
         self.location.range.range()
     }
+
+    /// Returns the range vertex for this definition, tagged with its symbol information
+    /// if it is known.
+    pub fn range_vertex(&self) -> RangeVertex {
+        let tag = match &self.symbol {
+            Some(symbol) => RangeTag::Definition(DefinitionTag {
+                text: self.node_name.clone(),
+                kind: symbol.kind,
+                full_range: symbol.full_range,
+                detail: symbol.detail.clone(),
+            }),
+            None => RangeTag::Unknown(ReferenceTag {
+                text: self.node_name.clone(),
+            }),
+        };
+        RangeVertex {
+            range: self.range(),
+            tag: Some(tag),
+            offsets: self.location.range.offsets(),
+            renameable: self.renameable,
+        }
+    }
 }
 
 impl Reference {
     pub fn range(&self) -> protocol::types::Range {
         self.location.range.range()
     }
+
+    pub fn range_vertex(&self) -> RangeVertex {
+        RangeVertex {
+            range: self.range(),
+            tag: Some(RangeTag::Reference(ReferenceTag {
+                text: self.node_name.clone(),
+            })),
+            offsets: self.location.range.offsets(),
+            renameable: None,
+        }
+    }
 }
 
 impl Range {
+    pub fn new(lsp_range: protocol::types::Range) -> Self {
+        Self {
+            lsp_range,
+            offsets: None,
+        }
+    }
+
     pub fn range(&self) -> protocol::types::Range {
         self.lsp_range.clone()
     }
+
+    /// Returns the byte offsets of the range in its file, if they are known.
+    pub fn offsets(&self) -> Option<ByteOffsets> {
+        self.offsets
+    }
 }
 
-pub fn paths<P: AsRef<Path>>(root: P, extensions: Vec<String>) -> Vec<PathBuf> {
-    Walk::new(root)
-        .into_iter()
+impl PartialEq for Range {
+    fn eq(&self, other: &Self) -> bool {
+        self.lsp_range == other.lsp_range
+    }
+}
+
+impl Eq for Range {}
+
+/// The text of a file and the byte offset of the start of each of its lines, to convert
+/// positions to byte offsets with `--emit-offsets`.
+struct LineIndex {
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self { text, line_starts }
+    }
+
+    /// Returns the byte offset of the given position, or `None` if it is past the last line.
+    fn byte_offset(&self, position: Position, encoding: PositionEncoding) -> Option<u64> {
+        let line = position.line as usize;
+        let start = *self.line_starts.get(line)?;
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.text.len(), |next| next - 1);
+        Some((start + encoding.byte_offset(&self.text[start..end], position.character)) as u64)
+    }
+
+    fn offsets(&self, range: LspRange, encoding: PositionEncoding) -> Option<ByteOffsets> {
+        Some(ByteOffsets {
+            start: self.byte_offset(range.start, encoding)?,
+            end: self.byte_offset(range.end, encoding)?,
+        })
+    }
+}
+
+/// Sets the byte offsets of the ranges that were found in the file with the given path and
+/// text, for `--emit-offsets`. The ranges in other files, e.g. the declarations in a header,
+/// are converted with the text of those files, which is only read for this file.
+fn add_offsets(
+    crawled: &mut CrawledFile,
+    p: &Path,
+    text: String,
+    encoding: PositionEncoding,
+    args: &Args,
+) {
+    // File (as returned by `file_uri`) -> its lines, or `None` if it could not be read
+    let mut indexes = HashMap::new();
+    indexes.insert(file_uri(p), Some(LineIndex::new(text)));
+    let locations = crawled
+        .definitions
+        .iter_mut()
+        .flat_map(|def| std::iter::once(&mut def.location).chain(def.declaration.as_mut()))
+        .chain(crawled.references.iter_mut().map(|r| &mut r.location));
+    for location in locations {
+        let index = indexes
+            .entry(location.file_path.clone())
+            .or_insert_with(|| {
+                let path = Url::parse(&location.file_path).ok()?.to_file_path().ok()?;
+                args.overlay.read_to_string(&path).ok().map(LineIndex::new)
+            });
+        if let Some(index) = index {
+            location.range.offsets = index.offsets(location.range.range(), encoding);
+        }
+    }
+}
+
+/// Returns the paths of the files to index: the file given with `--file`, or all the files
+/// in the project with one of the language's extensions, or with one of its shebangs if
+/// `--detect-shebangs` is given. Files matched by a `.lsifignore`, or by a `.gitignore`
+/// (same syntax) unless `--no-gitignore` is given, are left out. The files of a directory
+/// are sorted by name, so that the same project always gives the same dump. With `--sample`,
+/// only some of the files are returned.
+pub fn paths(args: &Args, config: &LSConfig) -> Vec<PathBuf> {
+    if let Some(file) = &args.file {
+        return vec![file.clone()];
+    }
+    let root = args.project_root.clone().unwrap();
+    let paths = WalkBuilder::new(&root)
+        .follow_links(args.follow_symlinks)
+        .add_custom_ignore_filename(".lsifignore")
+        .git_ignore(!args.no_gitignore)
+        .git_global(!args.no_gitignore)
+        .git_exclude(!args.no_gitignore)
+        .ignore(!args.no_gitignore)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
         .filter_map(Result::ok)
         .filter(move |entry| {
-            entry.metadata().unwrap().is_file() && matches_extensions(entry, &extensions)
+            entry.metadata().unwrap().is_file()
+                && (matches_extensions(entry, &config.extensions)
+                    || (args.detect_shebangs && matches_shebang(entry, config)))
         })
         .map(DirEntry::into_path)
-        .collect()
+        .collect();
+    match args.sample {
+        Some(n) => sample_paths(paths, &root, n, args.seed),
+        None => paths,
+    }
+}
+
+/// Returns `n` of the given paths picked at random with the given seed, in their order.
+/// The paths are ranked by a hash of the seed and their path in the project, so that the
+/// same files are picked in every checkout of the project.
+fn sample_paths(paths: Vec<PathBuf>, root: &Path, n: usize, seed: u64) -> Vec<PathBuf> {
+    if paths.len() <= n {
+        return paths;
+    }
+    let ranks = paths
+        .iter()
+        .map(|path| {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            let mut hasher = Sha256::new();
+            hasher.update(seed.to_le_bytes());
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.finalize()
+        })
+        .collect::<Vec<_>>();
+    let mut picked = (0..paths.len()).collect::<Vec<_>>();
+    picked.sort_by_key(|&i| ranks[i]);
+    picked.truncate(n);
+    picked.sort_unstable();
+    picked.into_iter().map(|i| paths[i].clone()).collect()
 }
 
 /// Returns true if the given `DirEntry` has an extension equal to one of
@@ -239,6 +1674,18 @@ fn matches_extensions(dir_entry: &DirEntry, extensions: &[String]) -> bool {
     extensions.iter().any(|ex| has_extension(dir_entry, ex))
 }
 
+/// Returns true if the first line of the given file is a shebang of the language. Only the
+/// start of the file is read, and unreadable files do not match.
+fn matches_shebang(dir_entry: &DirEntry, config: &LSConfig) -> bool {
+    let mut start = Vec::with_capacity(256);
+    let read = File::open(dir_entry.path()).and_then(|file| file.take(256).read_to_end(&mut start));
+    if read.is_err() {
+        return false;
+    }
+    let first_line = start.split(|&b| b == b'\n').next().unwrap_or_default();
+    std::str::from_utf8(first_line).is_ok_and(|line| config.matches_shebang(line.trim_end()))
+}
+
 /// Returns true if the given `DirEntry`'s extension is equal to the given
 /// extension.
 fn has_extension(dir_entry: &DirEntry, target_ext: &str) -> bool {
@@ -250,10 +1697,602 @@ fn has_extension(dir_entry: &DirEntry, target_ext: &str) -> bool {
         .unwrap_or(false)
 }
 
+#[cfg(test)]
 mod tests {
-    use crate::Result;
+    use std::{collections::HashSet, path::Path, sync::mpsc::RecvError};
+
+    use crate::{error, Error, Result};
+
+    use languageserver_types::{
+        request::GotoDefinitionResponse, DocumentSymbolResponse, Location as LspLocation, Position,
+        Range as LspRange, SymbolInformation, SymbolKind, Url,
+    };
+    use serde_json::json;
+
+    use crate::protocol::types::{ByteOffsets, Vertex};
+
+    use structopt::StructOpt;
+
+    use crate::{
+        checkpoint::{Checkpoint, CheckpointEntry},
+        cli::Args,
+        configs::language_configs,
+        lsp::{LSClient, PositionEncoding, SemanticToken, WriteError},
+        resolution::Classification,
+        uri::file_uri,
+    };
+
+    use super::{
+        add_offsets, claim_declaration, definition_kind, flatten_document_symbols, get_output_file,
+        get_words, group_workspace_symbols, identifier_words, innermost_symbol_at, is_fatal,
+        member_access_retries, paths, skips_hover, symbol_references, traverse_in_memory,
+        workspace_symbol_info, CrawledFile, Definition, Location, Range, TokenCache,
+        IDENTIFIER_TOKEN_TYPES,
+    };
+
+    #[cfg(unix)]
+    #[test]
+    fn test_paths_with_symlink_loop() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "").unwrap();
+        std::os::unix::fs::symlink(dir, dir.join("src/loop")).unwrap();
+
+        let config = language_configs()["rust"].clone();
+        for follow_symlinks in &[false, true] {
+            let mut argv = vec!["universal-lsif", "rust", dir.to_str().unwrap()];
+            if *follow_symlinks {
+                argv.push("--follow-symlinks");
+            }
+            let mut args = Args::from_iter(argv);
+            args.canonicalize_paths().unwrap();
+            assert_eq!(paths(&args, &config).len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_paths_with_lsifignore() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("src/fixtures")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "").unwrap();
+        std::fs::write(dir.join("src/fixtures/huge.rs"), "").unwrap();
+        std::fs::write(dir.join(".lsifignore"), "fixtures/\n").unwrap();
+
+        let mut args = Args::from_iter(vec!["universal-lsif", "rust", dir.to_str().unwrap()]);
+        args.canonicalize_paths().unwrap();
+        let paths = paths(&args, &language_configs()["rust"]);
+        assert_eq!(paths.len(), 1);
+        assert!(paths[0].ends_with("src/main.rs"));
+    }
+
+    #[test]
+    fn test_paths_with_no_gitignore() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("target/generated")).unwrap();
+        std::fs::write(dir.join("main.rs"), "").unwrap();
+        std::fs::write(dir.join("target/generated/bindings.rs"), "").unwrap();
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+
+        let config = language_configs()["rust"].clone();
+        let mut args = Args::from_iter(vec!["universal-lsif", "rust", dir.to_str().unwrap()]);
+        args.canonicalize_paths().unwrap();
+        assert_eq!(paths(&args, &config).len(), 1);
+
+        args.no_gitignore = true;
+        assert_eq!(paths(&args, &config).len(), 2);
+    }
+
+    #[test]
+    fn test_paths_with_sample() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for i in 0..20 {
+            std::fs::write(dir.join(format!("file{:02}.rs", i)), "").unwrap();
+        }
+        let config = language_configs()["rust"].clone();
+        let sample = |seed: &str| {
+            let argv = vec![
+                "universal-lsif",
+                "rust",
+                dir.to_str().unwrap(),
+                "--sample",
+                "5",
+                "--seed",
+                seed,
+            ];
+            let mut args = Args::from_iter(argv);
+            args.canonicalize_paths().unwrap();
+            paths(&args, &config)
+        };
+
+        let first = sample("1");
+        assert_eq!(first.len(), 5);
+        assert!(first.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(sample("1"), first);
+        assert_ne!(sample("2"), first);
+    }
+
+    #[test]
+    fn test_paths_with_shebangs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::write(dir.join("main.py"), "").unwrap();
+        std::fs::write(
+            dir.join("bin/deploy"),
+            "#!/usr/bin/env python3\nimport os\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("bin/setup"), "#!/bin/sh\nset -e\n").unwrap();
+
+        let config = language_configs()["python"].clone();
+        let mut args = Args::from_iter(vec!["universal-lsif", "python", dir.to_str().unwrap()]);
+        args.canonicalize_paths().unwrap();
+        assert_eq!(paths(&args, &config).len(), 1);
 
-    use super::get_words;
+        args.detect_shebangs = true;
+        let paths = paths(&args, &config);
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].ends_with("bin/deploy"));
+    }
+
+    #[test]
+    fn test_identifier_words() {
+        let words = get_words("fn foo() { \"bar\" }".to_string(), PositionEncoding::Utf16);
+        let token = |start, length, token_type: &str| SemanticToken {
+            line: 0,
+            start,
+            length,
+            token_type: token_type.to_string(),
+        };
+        let tokens = vec![
+            token(0, 2, "keyword"),
+            token(3, 3, "function"),
+            token(11, 5, "string"),
+        ];
+
+        let words = identifier_words(words, &tokens);
+        assert_eq!(words.len(), 1);
+        assert_eq!(words[0].0, "foo");
+
+        assert!(IDENTIFIER_TOKEN_TYPES.contains(&"label"));
+        assert!(!IDENTIFIER_TOKEN_TYPES.contains(&"keyword"));
+    }
+
+    #[test]
+    fn test_lazy_hover() {
+        let resp: DocumentSymbolResponse = serde_json::from_value(json!([{
+            "name": "foo",
+            "kind": 12,
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 2, "character": 1}},
+            "selectionRange": {"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 6}}
+        }]))
+        .unwrap();
+        let symbols = flatten_document_symbols(resp);
+        let tokens = vec![SemanticToken {
+            line: 0,
+            start: 7,
+            length: 1,
+            token_type: "parameter".to_string(),
+        }];
+        let kind = |line, character| {
+            let position = Position::new(line, character);
+            definition_kind(
+                innermost_symbol_at(&symbols, position).as_ref(),
+                &tokens,
+                position,
+            )
+            .map(str::to_string)
+        };
+        assert_eq!(kind(0, 3).as_deref(), Some("function"));
+        assert_eq!(kind(0, 7).as_deref(), Some("parameter"));
+        assert_eq!(kind(1, 8), None);
+
+        let args = Args::from_iter(vec!["universal-lsif", "rust"]);
+        assert!(!skips_hover(&args, "parameter"));
+        let args = Args::from_iter(vec!["universal-lsif", "rust", "--lazy-hover"]);
+        assert!(skips_hover(&args, "parameter"));
+        assert!(!skips_hover(&args, "function"));
+        let args = Args::from_iter(vec![
+            "universal-lsif",
+            "rust",
+            "--lazy-hover",
+            "--lazy-hover-skip-kinds",
+            "field,enumMember",
+        ]);
+        assert!(skips_hover(&args, "enumMember"));
+        assert!(!skips_hover(&args, "parameter"));
+    }
+
+    #[test]
+    fn test_get_words_in_position_encoding() {
+        let text = "let café = \"😀\" + bar;".to_string();
+
+        let words = get_words(text.clone(), PositionEncoding::Utf8);
+        assert_eq!(words[2].0, "bar");
+        assert_eq!(words[2].1.start.character, 21);
+        assert_eq!(words[2].1.end.character, 24);
+
+        let words = get_words(text, PositionEncoding::Utf16);
+        assert_eq!(words[2].1.start.character, 18);
+        assert_eq!(words[2].1.end.character, 21);
+    }
+
+    #[test]
+    fn test_token_cache() {
+        let config = language_configs()["rust"].clone();
+        let mut cache = TokenCache::default();
+        let words = |cache: &mut TokenCache, path, text| {
+            cache
+                .words(Path::new(path), text, &config, PositionEncoding::Utf16)
+                .into_iter()
+                .map(|(word, _range)| word)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(words(&mut cache, "/src/main.rs", "foo"), ["foo"]);
+        // Not tokenized again, e.g. when the file is crawled again after a respawn
+        cache.words.clear();
+        assert!(words(&mut cache, "/src/main.rs", "foo").is_empty());
+        assert_eq!(words(&mut cache, "/src/main.rs", "bar"), ["bar"]);
+        assert_eq!(words(&mut cache, "/src/lib.rs", "bar"), ["bar"]);
+    }
+
+    #[test]
+    fn test_member_access_retries() {
+        let text = "let x = a.b.c();\nuse std::io;\nfor i in 0..n {}\nlet é = a. b;";
+        let words = get_words(text.to_string(), PositionEncoding::Utf16);
+        let responses = words
+            .iter()
+            .map(|(word, _range)| match word.as_str() {
+                "b" => Ok(GotoDefinitionResponse::Scalar(LspLocation::new(
+                    Url::parse("file:///a.rs").unwrap(),
+                    LspRange::default(),
+                ))),
+                _ => Ok(GotoDefinitionResponse::Array(vec![])),
+            })
+            .collect::<Vec<_>>();
+        let retried = member_access_retries(text, &words, &responses, PositionEncoding::Utf16)
+            .into_iter()
+            .map(|idx| words[idx].0.as_str())
+            .collect::<Vec<_>>();
+        // `b` has a definition, `n` follows a range and `a` is not a member
+        assert_eq!(retried, vec!["c", "io"]);
+    }
+
+    #[test]
+    fn test_paths_with_single_file() {
+        let project = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/test_data/rust");
+        let file = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/tests/test_data/rust/src/main.rs"
+        );
+        let mut args = Args::from_iter(vec!["universal-lsif", "rust", project, "--file", file]);
+        args.canonicalize_paths().unwrap();
+
+        let config = language_configs()["rust"].clone();
+        assert_eq!(
+            paths(&args, &config),
+            vec![std::path::Path::new(file).canonicalize().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_output_file_parent_directories_are_created() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let output = dir.join("build/lsif/dump.json");
+        let args = Args::from_iter(vec![
+            "universal-lsif",
+            "rust",
+            "--output",
+            output.to_str().unwrap(),
+        ]);
+
+        get_output_file(&args).unwrap();
+        assert!(output.is_file());
+    }
+
+    #[test]
+    fn test_definition_range_tag() {
+        let resp: DocumentSymbolResponse = serde_json::from_value(json!([{
+            "name": "Point",
+            "kind": 23,
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 3, "character": 1}},
+            "selectionRange": {"start": {"line": 0, "character": 7}, "end": {"line": 0, "character": 12}},
+            "children": [{
+                "name": "x",
+                "kind": 8,
+                "detail": "i32",
+                "range": {"start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 10}},
+                "selectionRange": {"start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 5}}
+            }]
+        }]))
+        .unwrap();
+        let symbols = flatten_document_symbols(resp);
+        let range = LspRange::new(Position::new(1, 4), Position::new(1, 5));
+        let def = Definition {
+            location: Location {
+                file_path: "file:///point.rs".into(),
+                range: Range::new(range),
+            },
+            node_name: "x".into(),
+            comment: None,
+            symbol: innermost_symbol_at(&symbols, range.start),
+            resolved_range: None,
+            declaration: None,
+            exported: false,
+            renameable: None,
+        };
+
+        let vertex = serde_json::to_value(Vertex::from(def.range_vertex())).unwrap();
+        assert_eq!(
+            vertex["tag"],
+            json!({
+                "type": "definition",
+                "text": "x",
+                "kind": 8,
+                "fullRange": {"start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 10}},
+                "detail": "i32"
+            })
+        );
+        assert_eq!(vertex["start"], json!({"line": 1, "character": 4}));
+    }
+
+    #[test]
+    fn test_symbol_references() {
+        let location = |uri: &str, line| LspLocation {
+            uri: Url::parse(uri).unwrap(),
+            range: LspRange::new(Position::new(line, 3), Position::new(line, 6)),
+        };
+        let def = Definition {
+            location: Location {
+                file_path: "file:///src/main.rs".to_string(),
+                range: Range::new(location("file:///src/main.rs", 0).range),
+            },
+            node_name: "foo".to_string(),
+            comment: Some("fn foo()".to_string()),
+            symbol: None,
+            resolved_range: None,
+            declaration: None,
+            exported: true,
+            renameable: None,
+        };
+
+        let references = symbol_references(
+            &def,
+            vec![
+                location("file:///src/main.rs", 0),
+                location("file:///src/main.rs", 4),
+                location("file:///src/lib.rs", 2),
+            ],
+        );
+        let locations = references
+            .iter()
+            .map(|r| {
+                (
+                    r.location.file_path.as_str(),
+                    r.location.range.range().start.line,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            locations,
+            vec![("file:///src/main.rs", 4), ("file:///src/lib.rs", 2)]
+        );
+        assert!(references
+            .iter()
+            .all(|r| r.def.location == def.location && r.node_name == "foo"));
+    }
+
+    #[test]
+    fn test_resume_crawls_failed_files_again() {
+        struct ClosedWriter;
+
+        impl std::io::Write for ClosedWriter {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        // Fails to be read, then the server stops at the next file
+        std::fs::write(dir.join("src/a.rs"), [0xff, 0xfe]).unwrap();
+        std::fs::write(dir.join("src/b.rs"), "fn main() {}\n").unwrap();
+        let output = dir.join("dump.lsif");
+        let mut args = Args::from_iter(vec![
+            "universal-lsif",
+            "rust",
+            dir.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+            "--resume",
+        ]);
+        args.canonicalize_paths().unwrap();
+        let mut checkpoint = Checkpoint::new(args.checkpoint_path());
+        checkpoint.record(CheckpointEntry {
+            file: dir.join("src/main.rs"),
+            ..CheckpointEntry::default()
+        });
+        checkpoint.save().unwrap();
+
+        let client = LSClient::with_capabilities(
+            Box::new(ClosedWriter),
+            json!({"definitionProvider": true}),
+        );
+        assert!(
+            traverse_in_memory(args.clone(), client, language_configs()["rust"].clone()).is_err()
+        );
+
+        let (checkpoint, _) = Checkpoint::load(args.checkpoint_path()).unwrap();
+        assert_eq!(checkpoint.processed_files.len(), 1);
+        assert!(!checkpoint.processed_files.contains(&dir.join("src/a.rs")));
+    }
+
+    #[test]
+    fn test_claim_declaration() {
+        let mut declarations = HashSet::new();
+        // `def foo; foo; end`, which the server resolves both words to
+        let declaration = Range::new(LspRange::new(Position::new(0, 0), Position::new(0, 17)));
+        let mut claim = |classification| {
+            claim_declaration(classification, "file:///main.rb", &mut declarations)
+        };
+
+        assert_eq!(
+            claim(Classification::Definition(Some(declaration.clone()))),
+            Classification::Definition(Some(declaration.clone()))
+        );
+        assert_eq!(
+            claim(Classification::Definition(Some(declaration.clone()))),
+            Classification::Reference(Location {
+                file_path: "file:///main.rb".to_string(),
+                range: declaration,
+            })
+        );
+        assert_eq!(
+            claim(Classification::Definition(None)),
+            Classification::Definition(None)
+        );
+    }
+
+    #[test]
+    fn test_workspace_symbol_info() {
+        let lines = vec![
+            "/// foo",
+            "pub fn foo_bar() {}",
+            "pub fn foo() -> Foo {",
+            "}",
+        ];
+        let symbol = |name: &str, start_line| SymbolInformation {
+            name: name.to_string(),
+            kind: SymbolKind::Function,
+            deprecated: None,
+            location: LspLocation {
+                uri: Url::parse("file:///src/main.rs").unwrap(),
+                range: LspRange::new(Position::new(start_line, 0), Position::new(3, 1)),
+            },
+            container_name: None,
+        };
+
+        // The name in the doc comment is outside of the range, and `foo_bar` is another word
+        let info = workspace_symbol_info(&symbol("foo", 1), &lines, PositionEncoding::Utf16);
+        assert_eq!(
+            info.selection_range,
+            LspRange::new(Position::new(2, 7), Position::new(2, 10))
+        );
+        assert_eq!(info.full_range.start, Position::new(1, 0));
+        // Not found, e.g. a name with a signature
+        let info =
+            workspace_symbol_info(&symbol("foo(x: i32)", 2), &lines, PositionEncoding::Utf16);
+        assert_eq!(info.selection_range, info.full_range);
+    }
+
+    #[test]
+    fn test_add_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let (main, header) = (dir.path().join("main.cpp"), dir.path().join("main.h"));
+        std::fs::write(&header, "int foo();\n").unwrap();
+        let location = |path: &Path, start: (u64, u64), end: (u64, u64)| Location {
+            file_path: file_uri(path),
+            range: Range::new(LspRange::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            )),
+        };
+        let mut crawled = CrawledFile::default();
+        crawled.definitions.push(Definition {
+            location: location(&main, (1, 4), (1, 7)),
+            node_name: "foo".to_string(),
+            comment: None,
+            symbol: None,
+            resolved_range: None,
+            declaration: Some(location(&header, (0, 4), (0, 7))),
+            exported: false,
+            renameable: None,
+        });
+        crawled.definitions.push(Definition {
+            location: location(&main, (2, 0), (3, 0)),
+            ..crawled.definitions[0].clone()
+        });
+
+        let args = Args::from_iter(vec!["universal-lsif", "cpp"]);
+        let text = "// é\nint foo() {}\n".to_string();
+        add_offsets(&mut crawled, &main, text, PositionEncoding::Utf16, &args);
+        let def = &crawled.definitions[0];
+        assert_eq!(
+            def.range_vertex().offsets,
+            Some(ByteOffsets { start: 10, end: 13 })
+        );
+        assert_eq!(
+            def.declaration.as_ref().unwrap().range.offsets(),
+            Some(ByteOffsets { start: 4, end: 7 })
+        );
+        // Past the last line
+        assert_eq!(crawled.definitions[1].location.range.offsets(), None);
+        // The offsets do not count in comparisons
+        assert_eq!(def.location, location(&main, (1, 4), (1, 7)));
+    }
+
+    #[test]
+    fn test_group_workspace_symbols() {
+        let symbol = |name: &str, file: &str| SymbolInformation {
+            name: name.to_string(),
+            kind: SymbolKind::Function,
+            deprecated: None,
+            location: LspLocation {
+                uri: Url::from_file_path(file).unwrap(),
+                range: LspRange::new(Position::new(0, 0), Position::new(0, name.len() as u64)),
+            },
+            container_name: None,
+        };
+        let responses = || {
+            vec![
+                vec![symbol("a", "/src/a.rs"), symbol("ab", "/src/b.rs")],
+                vec![symbol("b", "/src/c.rs"), symbol("ab", "/src/b.rs")],
+            ]
+        };
+
+        let symbols = group_workspace_symbols(responses(), true, |_| false);
+        assert_eq!(symbols.of_file(Path::new("/src/b.rs")).len(), 1);
+        assert!(symbols.has_no_symbols(Path::new("/src/d.rs")));
+        assert!(!symbols.has_no_symbols(Path::new("/src/a.rs")));
+
+        // The files of a cut response fall back, and the files without symbols are crawled
+        let symbols =
+            group_workspace_symbols(responses(), true, |response| response[0].name == "a");
+        assert!(symbols.of_file(Path::new("/src/a.rs")).is_empty());
+        assert!(symbols.of_file(Path::new("/src/b.rs")).is_empty());
+        assert_eq!(symbols.of_file(Path::new("/src/c.rs")).len(), 1);
+        assert!(!symbols.has_no_symbols(Path::new("/src/d.rs")));
+
+        // A failed query or an empty workspace
+        let symbols = group_workspace_symbols(responses(), false, |_| false);
+        assert!(!symbols.has_no_symbols(Path::new("/src/d.rs")));
+        let symbols = group_workspace_symbols(Vec::new(), true, |_| false);
+        assert!(!symbols.has_no_symbols(Path::new("/src/d.rs")));
+    }
+
+    #[test]
+    fn test_is_fatal() {
+        assert!(is_fatal(&Error::from(RecvError).context("server exited")));
+        let broken_pipe = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        assert!(is_fatal(&Error::from(WriteError(broken_pipe))));
+        let not_found = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(!is_fatal(
+            &Error::from(not_found).context("Could not read `main.rs`")
+        ));
+        assert!(!is_fatal(&error!("position out of range")));
+    }
 
     #[test]
     fn test_for_each_word() {
@@ -265,7 +2304,7 @@ mod tests {
             }
         "#;
         let mut words = Vec::new();
-        get_words(text.to_string())
+        get_words(text.to_string(), PositionEncoding::Utf16)
             .into_iter()
             .try_for_each(|(word, _range)| -> Result<()> {
                 words.push(word.to_string());
@@ -309,7 +2348,7 @@ mod tests {
         }
         "#;
         let mut words = Vec::new();
-        get_words(text.to_string())
+        get_words(text.to_string(), PositionEncoding::Utf16)
             .into_iter()
             .try_for_each(|(word, _range)| -> Result<()> {
                 words.push(word.to_string());
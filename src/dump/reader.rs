@@ -0,0 +1,237 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::Context;
+
+use crate::{protocol::types::Entry, ret_error, Result};
+
+/// Whether the dump is JSON lines or a JSON array, found from its first non-blank byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Start,
+    Lines,
+    Array,
+    Done,
+}
+
+/// Reads the entries of a dump one at a time, without loading the whole dump in memory. The
+/// dump is either JSON lines or a JSON array, as written with `--output-format`.
+///
+/// The iterator ends after the first error.
+pub struct DumpReader<R> {
+    reader: R,
+    /// The name of the dump in errors, e.g. its path
+    name: String,
+    state: State,
+    /// The number of entries read so far
+    entries: usize,
+    /// The number of lines read so far, with JSON lines
+    lines: usize,
+}
+
+impl DumpReader<BufReader<File>> {
+    /// Opens the dump in the given file.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Could not open `{}`", path.display()))?;
+        Ok(Self::new(BufReader::new(file), path.display().to_string()))
+    }
+}
+
+impl<R: BufRead> DumpReader<R> {
+    pub fn new(reader: R, name: String) -> Self {
+        Self {
+            reader,
+            name,
+            state: State::Start,
+            entries: 0,
+            lines: 0,
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry>> {
+        if self.state == State::Start {
+            self.skip_whitespace()?;
+            self.state = if self.peek()? == Some(b'[') {
+                self.reader.consume(1);
+                State::Array
+            } else {
+                State::Lines
+            };
+        }
+        let entry = match self.state {
+            State::Lines => self.next_line_entry()?,
+            State::Array => self.next_array_entry()?,
+            State::Start | State::Done => None,
+        };
+        match entry {
+            Some(_) => self.entries += 1,
+            None => self.state = State::Done,
+        }
+        Ok(entry)
+    }
+
+    fn next_line_entry(&mut self) -> Result<Option<Entry>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.lines += 1;
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+        let entry = serde_json::from_str(&line).with_context(|| {
+            format!(
+                "Line {} of `{}` is not a valid LSIF entry",
+                self.lines, self.name
+            )
+        })?;
+        Ok(Some(entry))
+    }
+
+    fn next_array_entry(&mut self) -> Result<Option<Entry>> {
+        self.skip_whitespace()?;
+        match self.peek()? {
+            Some(b']') => {
+                self.reader.consume(1);
+                return Ok(None);
+            }
+            Some(b',') if self.entries > 0 => {
+                self.reader.consume(1);
+                self.skip_whitespace()?;
+            }
+            None => ret_error!("`{}` ends before its array is closed", self.name),
+            Some(_) if self.entries > 0 => ret_error!(
+                "Expected `,` or `]` after entry {} of `{}`",
+                self.entries,
+                self.name
+            ),
+            Some(_) => {}
+        }
+        let value = self.read_object()?;
+        let entry = serde_json::from_slice(&value).with_context(|| {
+            format!(
+                "Entry {} of `{}` is not a valid LSIF entry",
+                self.entries + 1,
+                self.name
+            )
+        })?;
+        Ok(Some(entry))
+    }
+
+    /// Returns the bytes of the JSON object at the current position, up to its closing brace.
+    fn read_object(&mut self) -> Result<Vec<u8>> {
+        match self.peek()? {
+            Some(b'{') => {}
+            None => ret_error!("`{}` ends before its array is closed", self.name),
+            Some(_) => ret_error!(
+                "Entry {} of `{}` is not an object",
+                self.entries + 1,
+                self.name
+            ),
+        }
+        let mut object = Vec::new();
+        let (mut depth, mut in_string, mut escaped) = (0, false, false);
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                ret_error!("`{}` ends in the middle of an entry", self.name);
+            }
+            let mut end = None;
+            for (idx, byte) in buf.iter().enumerate() {
+                if in_string {
+                    match byte {
+                        _ if escaped => escaped = false,
+                        b'\\' => escaped = true,
+                        b'"' => in_string = false,
+                        _ => {}
+                    }
+                    continue;
+                }
+                match byte {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    end = Some(idx + 1);
+                    break;
+                }
+            }
+            let used = end.unwrap_or(buf.len());
+            object.extend_from_slice(&buf[..used]);
+            self.reader.consume(used);
+            if end.is_some() {
+                return Ok(object);
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().copied())
+    }
+
+    fn skip_whitespace(&mut self) -> Result<()> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            let blank = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+            let done = blank < buf.len() || buf.is_empty();
+            self.reader.consume(blank);
+            if done {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for DumpReader<R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        let entry = self.next_entry();
+        if entry.is_err() {
+            self.state = State::Done;
+        }
+        entry.transpose()
+    }
+}
+
+/// Reads the entries of the dump in the given files one at a time, e.g. the shards of a dump
+/// in order.
+pub fn read_dump<P: AsRef<Path>>(paths: &[P]) -> impl Iterator<Item = Result<Entry>> + '_ {
+    paths
+        .iter()
+        .flat_map(|path| match DumpReader::open(path.as_ref()) {
+            Ok(reader) => Box::new(reader) as Box<dyn Iterator<Item = Result<Entry>>>,
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DumpReader;
+
+    #[test]
+    fn test_invalid_dumps() {
+        let error = |dump: &str| {
+            let mut reader = DumpReader::new(dump.as_bytes(), "dump.json".to_string());
+            let err = reader.find_map(Result::err).unwrap();
+            assert!(reader.next().is_none());
+            format!("{:#}", err)
+        };
+        let entry = r#"{"id": 1, "type": "vertex", "label": "resultSet"}"#;
+
+        assert!(error(&format!("{}\n\n{{\"id\": 2}}", entry)).starts_with("Line 3 of"));
+        assert!(error(&format!("[{}, {{\"id\": 2}}]", entry)).starts_with("Entry 2 of"));
+        assert!(error(&format!("[{} {}]", entry, entry)).starts_with("Expected `,` or `]`"));
+        assert!(error(&format!("[{}, ", entry)).contains("ends before its array is closed"));
+        assert!(error(r#"[{"id": 1, "label": "}"#).contains("ends in the middle"));
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::protocol::types::{key, Edge, Element, Entry, Vertex};
+
+/// The order in which the ranges and the result sets they point to with `next` edges are
+/// written. Consumers that read a dump in one pass disagree on it:
+///
+/// - `AsEmitted` writes the entries as the indexer emits them, without buffering. Consumers
+///   that load the whole dump before resolving it, e.g. `verify-dump` and most uploaders,
+///   accept any order.
+/// - `SpecDefault` writes the result sets of a document before its ranges, as in the examples
+///   of the LSIF specification. Consumers that look up the result set of a range as soon as
+///   they read its `next` edge, e.g. to attach its hover, need this order.
+/// - `RangesFirst` writes the ranges of a document before the result sets they point to.
+///   Consumers that build the ranges of each document first and resolve the results
+///   afterwards need this order.
+///
+/// Either way, a vertex is written before the edges that connect it.
+///
+/// The `contains` edges are only emitted once all the files are indexed, so `SpecDefault` and
+/// `RangesFirst` hold the ranges and result sets of the whole dump in memory until then.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmitOrder {
+    AsEmitted,
+    SpecDefault,
+    RangesFirst,
+}
+
+impl EmitOrder {
+    /// Returns whether the given vertex is held back until its document is complete.
+    fn holds(self, vertex: &Vertex) -> bool {
+        match self {
+            EmitOrder::AsEmitted => false,
+            EmitOrder::SpecDefault | EmitOrder::RangesFirst => {
+                matches!(vertex, Vertex::Range(_) | Vertex::ResultSet(_))
+            }
+        }
+    }
+
+    /// Returns whether the given vertex is written before the other held vertices of its
+    /// document.
+    fn writes_first(self, vertex: &Vertex) -> bool {
+        match self {
+            EmitOrder::SpecDefault => matches!(vertex, Vertex::ResultSet(_)),
+            EmitOrder::AsEmitted | EmitOrder::RangesFirst => matches!(vertex, Vertex::Range(_)),
+        }
+    }
+}
+
+/// Buffers the cluster of each document, i.e. its ranges and the result sets they point to,
+/// and writes it in the given order once the `contains` edge of the document is emitted.
+/// The edges that connect a held vertex are held along with it. A result set that is shared
+/// by several documents is written with the first of them.
+pub(crate) struct Reorderer {
+    order: EmitOrder,
+    /// The number of entries pushed, to write the held entries in their original order
+    pushed: usize,
+    /// Held vertex ID -> the vertex
+    held: HashMap<String, (usize, Entry)>,
+    /// Held vertex ID -> the edges that wait for it
+    waiting: HashMap<String, Vec<(usize, Entry)>>,
+}
+
+impl Reorderer {
+    pub(crate) fn new(order: EmitOrder) -> Self {
+        Self {
+            order,
+            pushed: 0,
+            held: HashMap::new(),
+            waiting: HashMap::new(),
+        }
+    }
+
+    /// Takes the next emitted entry and returns the entries that can be written, in order.
+    pub(crate) fn push(&mut self, entry: Entry) -> Vec<Entry> {
+        self.pushed += 1;
+        let mut ready = Vec::new();
+        match &entry.data {
+            Element::Vertex(vertex) if self.order.holds(vertex) => {
+                self.held.insert(key(&entry.id), (self.pushed, entry));
+            }
+            Element::Vertex(_) => ready.push(entry),
+            Element::Edge(edge) => {
+                if let Edge::Contains(data) = edge {
+                    let ranges = data.in_vs.iter().map(key).collect::<Vec<_>>();
+                    self.release_cluster(&ranges, &mut ready);
+                }
+                self.write_or_wait(self.pushed, entry, &mut ready);
+            }
+        }
+        ready
+    }
+
+    /// Returns the entries that are still held, once all the entries have been pushed.
+    pub(crate) fn finish(&mut self) -> Vec<Entry> {
+        let ids = self.held.keys().cloned().collect::<Vec<_>>();
+        let mut ready = Vec::new();
+        self.release(ids, &mut ready);
+        ready
+    }
+
+    /// Adds the given edge to the ready entries, unless it connects a held vertex.
+    fn write_or_wait(&mut self, index: usize, entry: Entry, ready: &mut Vec<Entry>) {
+        let held = match &entry.data {
+            Element::Edge(edge) => edge
+                .vertex_keys()
+                .into_iter()
+                .find(|id| self.held.contains_key(id)),
+            Element::Vertex(_) => None,
+        };
+        match held {
+            Some(id) => self.waiting.entry(id).or_default().push((index, entry)),
+            None => ready.push(entry),
+        }
+    }
+
+    /// Adds the given held ranges to the ready entries along with the result sets that they
+    /// point to, directly or through other result sets.
+    fn release_cluster(&mut self, ranges: &[String], ready: &mut Vec<Entry>) {
+        let mut cluster = HashSet::new();
+        let mut stack = ranges
+            .iter()
+            .filter(|id| self.held.contains_key(*id))
+            .cloned()
+            .collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            if !cluster.insert(id.clone()) {
+                continue;
+            }
+            for (_, edge) in self.waiting.get(&id).into_iter().flatten() {
+                if let Element::Edge(Edge::Next(data)) = &edge.data {
+                    let result_set = key(&data.in_v);
+                    if key(&data.out_v) == id && self.held.contains_key(&result_set) {
+                        stack.push(result_set);
+                    }
+                }
+            }
+        }
+        self.release(cluster.into_iter().collect(), ready);
+    }
+
+    /// Adds the given held vertices to the ready entries in the order, followed by the edges
+    /// that only waited for them.
+    fn release(&mut self, ids: Vec<String>, ready: &mut Vec<Entry>) {
+        let mut vertices = ids
+            .iter()
+            .filter_map(|id| self.held.remove(id))
+            .collect::<Vec<_>>();
+        let order = self.order;
+        vertices.sort_by_key(|(index, entry)| match &entry.data {
+            Element::Vertex(vertex) => (!order.writes_first(vertex), *index),
+            Element::Edge(_) => (true, *index),
+        });
+        ready.extend(vertices.into_iter().map(|(_, vertex)| vertex));
+
+        let mut edges = ids
+            .iter()
+            .flat_map(|id| self.waiting.remove(id).unwrap_or_default())
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|(index, _)| *index);
+        for (index, edge) in edges {
+            self.write_or_wait(index, edge, ready);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use languageserver_types::NumberOrString;
+
+    use super::{EmitOrder, Reorderer};
+    use crate::{
+        edge,
+        protocol::types::{
+            DefinitionResult, Edge, EdgeData, Element, Entry, RangeVertex, ResultSet, Vertex,
+        },
+    };
+
+    #[test]
+    fn test_reorder() {
+        let range = || -> Vertex {
+            RangeVertex {
+                range: Default::default(),
+                tag: None,
+                offsets: None,
+                renameable: None,
+            }
+            .into()
+        };
+        // A definition in one document, then a reference to it in another one
+        let (def_range, result_set, def_result, ref_range) = (1, 2, 3, 6);
+        let (def_document, ref_document) = (100, 101);
+        let elements: Vec<Element> = vec![
+            Element::Vertex(range()),
+            Element::Vertex(ResultSet {}.into()),
+            Element::Vertex(DefinitionResult {}.into()),
+            Element::Edge(edge!(Next, def_range -> result_set)),
+            Element::Edge(edge!(Definition, result_set -> def_result)),
+            Element::Vertex(range()),
+            Element::Edge(edge!(Next, ref_range -> result_set)),
+            Element::Edge(Edge::contains(def_document, vec![def_range])),
+            Element::Edge(Edge::contains(ref_document, vec![ref_range])),
+        ];
+        let ids = |order| {
+            let mut reorderer = Reorderer::new(order);
+            let mut ids = Vec::new();
+            for (id, data) in (1..).zip(elements.clone()) {
+                let entry = Entry {
+                    id: NumberOrString::Number(id),
+                    data,
+                };
+                ids.extend(reorderer.push(entry).into_iter().map(|entry| entry.id));
+            }
+            ids.extend(reorderer.finish().into_iter().map(|entry| entry.id));
+            ids.into_iter()
+                .map(|id| match id {
+                    NumberOrString::Number(id) => id,
+                    NumberOrString::String(_) => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(EmitOrder::AsEmitted), (1..=9).collect::<Vec<_>>());
+        // Each document is written at its `contains` edge, not at the end
+        assert_eq!(ids(EmitOrder::SpecDefault), vec![3, 2, 1, 4, 5, 8, 6, 7, 9]);
+        assert_eq!(ids(EmitOrder::RangesFirst), vec![3, 1, 2, 4, 5, 8, 6, 7, 9]);
+    }
+}
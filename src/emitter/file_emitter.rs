@@ -1,16 +1,118 @@
 use std::{
     fs::File,
     io::{BufWriter, Write},
-    sync::mpsc::{channel, Receiver, Sender},
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    emitter::emitter::Emitter,
+    emitter::{
+        emit_order::{EmitOrder, Reorderer},
+        emitter::Emitter,
+    },
     protocol::types::{Edge, Element, Entry, NumberOrString, Vertex, ID},
 };
 
 const DEFAULT_BUF_SIZE: usize = 64 * 1024;
 
+/// Splits the output into shards of at most `max_bytes` bytes, named after the output file
+/// and the index of the shard: `dump.json` becomes `dump.000.json`, `dump.001.json`, ...
+///
+/// The shards are consecutive parts of one dump, not independent dumps: IDs are unique across
+/// all of them, the metadata vertex is only in the first one, and edges can point to vertices
+/// in earlier shards. Concatenating them in order gives the whole dump.
+#[derive(Debug, Clone)]
+pub struct Shards {
+    output: PathBuf,
+    max_bytes: u64,
+}
+
+impl Shards {
+    pub fn new(output: PathBuf, max_bytes: u64) -> Self {
+        Self { output, max_bytes }
+    }
+
+    /// Returns the path of the shard with the given index.
+    pub fn path(&self, index: usize) -> PathBuf {
+        let stem = self
+            .output
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let name = match self.output.extension() {
+            Some(ext) => format!("{}.{:03}.{}", stem, index, ext.to_string_lossy()),
+            None => format!("{}.{:03}", stem, index),
+        };
+        self.output.with_file_name(name)
+    }
+
+    /// Returns the paths of the shards that exist on disk, in order.
+    pub fn existing_paths(&self) -> Vec<PathBuf> {
+        (0..)
+            .map(|index| self.path(index))
+            .take_while(|path| path.exists())
+            .collect()
+    }
+}
+
+/// How the entries are written to the output file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// One entry per line.
+    JsonLines,
+    /// A single JSON array, with one entry per line.
+    JsonArray,
+}
+
+impl OutputFormat {
+    /// Returns what is written at the start of a file.
+    fn start(self) -> &'static [u8] {
+        match self {
+            OutputFormat::JsonLines => b"",
+            OutputFormat::JsonArray => b"[\n",
+        }
+    }
+
+    /// Returns what is written between two entries.
+    fn separator(self) -> &'static [u8] {
+        match self {
+            OutputFormat::JsonLines => b"\n",
+            OutputFormat::JsonArray => b",\n",
+        }
+    }
+
+    /// Returns what is written at the end of a file, depending on whether it has entries.
+    fn end(self, empty: bool) -> &'static [u8] {
+        match (self, empty) {
+            (OutputFormat::JsonLines, true) => b"",
+            (OutputFormat::JsonLines, false) => b"\n",
+            (OutputFormat::JsonArray, true) => b"]\n",
+            (OutputFormat::JsonArray, false) => b"\n]\n",
+        }
+    }
+}
+
+/// When the output is flushed and synced to disk besides at the end, so that a crash loses
+/// at most a bounded amount of data. Never if neither is given.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlushPolicy {
+    /// After this many entries were written since the last flush.
+    pub every_entries: Option<u64>,
+    /// After this long since the last flush.
+    pub interval: Option<Duration>,
+}
+
+impl FlushPolicy {
+    /// Returns whether to flush, given the number of entries written and the time elapsed
+    /// since the last flush.
+    fn is_due(&self, unflushed: u64, elapsed: Duration) -> bool {
+        unflushed > 0
+            && (self.every_entries.is_some_and(|n| unflushed >= n)
+                || self.interval.is_some_and(|interval| elapsed >= interval))
+    }
+}
+
 /// An `Emitter` that emits data to a file, in a new OS thread.
 pub struct FileEmitter {
     id: ID,
@@ -32,19 +134,95 @@ impl FileEmitter {
     ///
     /// It is the reponsibiliy of the user of this struct to call `end` when there is
     /// no more data to be emitted and then wait for the flush signal.
-    pub(crate) fn new(file: File) -> (Self, Receiver<()>) {
+    ///
+    /// The emitted data is also flushed and synced to disk as often as `flush` says.
+    ///
+    /// If `shards` is given, `file` is the first shard and the next ones are created as
+    /// needed. With `OutputFormat::JsonArray`, each shard is an array of its own.
+    ///
+    /// The entries are written in the given `order`, which may hold the entries of a document
+    /// back until its `contains` edge.
+    pub(crate) fn new(
+        file: File,
+        format: OutputFormat,
+        flush: FlushPolicy,
+        shards: Option<Shards>,
+        order: EmitOrder,
+    ) -> (Self, Receiver<()>) {
         let (signal_tx, signal_rx) = channel();
-        let (entry_tx, entry_rx) = channel();
+        let (entry_tx, entry_rx) = channel::<Entry>();
 
         std::thread::spawn(move || {
             let mut buf_writer = BufWriter::with_capacity(DEFAULT_BUF_SIZE, file);
+            let (mut last_sync, mut unflushed) = (Instant::now(), 0);
+            // The size of the current shard, with its framing but without its end
+            let (mut shard_index, mut shard_bytes) = (0, format.start().len() as u64);
+            // Whether no entry was written to the current file
+            let mut empty = true;
+            buf_writer.write_all(format.start()).unwrap();
+            let mut reorderer = Reorderer::new(order);
+            let mut ended = false;
 
-            for entry in entry_rx {
-                let line = serde_json::to_vec(&entry).unwrap();
-                buf_writer.write(&line).unwrap();
-                buf_writer.write(b"\n").unwrap();
+            while !ended {
+                let entries = match flush.interval {
+                    Some(interval) => {
+                        match entry_rx.recv_timeout(interval.saturating_sub(last_sync.elapsed())) {
+                            Ok(entry) => reorderer.push(entry),
+                            Err(RecvTimeoutError::Timeout) => Vec::new(),
+                            Err(RecvTimeoutError::Disconnected) => {
+                                ended = true;
+                                reorderer.finish()
+                            }
+                        }
+                    }
+                    None => match entry_rx.recv() {
+                        Ok(entry) => reorderer.push(entry),
+                        Err(_) => {
+                            ended = true;
+                            reorderer.finish()
+                        }
+                    },
+                };
+
+                for entry in entries {
+                    let line = serde_json::to_vec(&entry).unwrap();
+                    if let Some(shards) = &shards {
+                        let separator = format.separator().len() as u64;
+                        let end = format.end(false).len() as u64;
+                        if !empty
+                            && shard_bytes + separator + line.len() as u64 + end > shards.max_bytes
+                        {
+                            buf_writer.write_all(format.end(empty)).unwrap();
+                            buf_writer.flush().unwrap();
+                            shard_index += 1;
+                            let file = File::create(shards.path(shard_index)).unwrap();
+                            buf_writer = BufWriter::with_capacity(DEFAULT_BUF_SIZE, file);
+                            buf_writer.write_all(format.start()).unwrap();
+                            shard_bytes = format.start().len() as u64;
+                            empty = true;
+                        }
+                        if !empty {
+                            shard_bytes += separator;
+                        }
+                        shard_bytes += line.len() as u64;
+                    }
+                    if !empty {
+                        buf_writer.write_all(format.separator()).unwrap();
+                    }
+                    buf_writer.write_all(&line).unwrap();
+                    empty = false;
+                    unflushed += 1;
+                }
+
+                if flush.is_due(unflushed, last_sync.elapsed()) {
+                    buf_writer.flush().unwrap();
+                    buf_writer.get_ref().sync_data().unwrap();
+                    last_sync = Instant::now();
+                    unflushed = 0;
+                }
             }
 
+            buf_writer.write_all(format.end(empty)).unwrap();
             buf_writer.flush().unwrap();
             signal_tx.send(()).unwrap();
         });
@@ -91,3 +269,134 @@ impl Emitter for FileEmitter {
         drop(entry_tx);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FileEmitter, FlushPolicy, OutputFormat, Shards};
+    use crate::{
+        check::for_each_entry,
+        emitter::{emit_order::EmitOrder, emitter::Emitter},
+        protocol::types::{NumberOrString, ResultSet},
+    };
+
+    #[test]
+    fn test_flush_policy() {
+        let never = FlushPolicy::default();
+        assert!(!never.is_due(1000, Duration::from_secs(1000)));
+
+        let every_entries = FlushPolicy {
+            every_entries: Some(2),
+            interval: None,
+        };
+        assert!(!every_entries.is_due(1, Duration::from_secs(1000)));
+        assert!(every_entries.is_due(2, Duration::ZERO));
+
+        let interval = FlushPolicy {
+            every_entries: None,
+            interval: Some(Duration::from_secs(1)),
+        };
+        assert!(!interval.is_due(1000, Duration::from_millis(999)));
+        assert!(interval.is_due(1, Duration::from_secs(1)));
+        // Nothing to flush
+        assert!(!interval.is_due(0, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_flush_every_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.json");
+        let file = std::fs::File::create(&path).unwrap();
+        let flush = FlushPolicy {
+            every_entries: Some(2),
+            interval: None,
+        };
+        let (mut emitter, flush_signal) = FileEmitter::new(
+            file,
+            OutputFormat::JsonLines,
+            flush,
+            None,
+            EmitOrder::AsEmitted,
+        );
+
+        emitter.emit_vertex(ResultSet {});
+        emitter.emit_vertex(ResultSet {});
+        // Written by the thread of the emitter, so not right away. The entries would stay in
+        // its buffer until the end without the flush.
+        let lines = || std::fs::read_to_string(&path).unwrap().lines().count();
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while lines() < 2 && std::time::Instant::now() < deadline {
+            std::thread::yield_now();
+        }
+        assert_eq!(lines(), 2);
+
+        emitter.end();
+        flush_signal.recv().unwrap();
+    }
+
+    #[test]
+    fn test_shards() {
+        // Each entry is 44 bytes: two fit in 92 bytes with their newlines, but not with the
+        // brackets and separators of an array.
+        for (format, shard_count) in &[(OutputFormat::JsonLines, 3), (OutputFormat::JsonArray, 5)] {
+            let dir = tempfile::tempdir().unwrap();
+            let shards = Shards::new(dir.path().join("dump.json"), 92);
+            assert_eq!(shards.path(1), dir.path().join("dump.001.json"));
+
+            let file = std::fs::File::create(shards.path(0)).unwrap();
+            let (mut emitter, flush_signal) = FileEmitter::new(
+                file,
+                *format,
+                FlushPolicy::default(),
+                Some(shards.clone()),
+                EmitOrder::AsEmitted,
+            );
+            for _ in 0..5 {
+                emitter.emit_vertex(ResultSet {});
+            }
+            emitter.end();
+            flush_signal.recv().unwrap();
+
+            let paths = shards.existing_paths();
+            assert_eq!(paths.len(), *shard_count, "{:?}", format);
+            let mut entries = Vec::new();
+            for_each_entry(&paths, |entry| entries.push(entry.id.clone())).unwrap();
+            assert_eq!(entries.len(), 5);
+            for path in &paths {
+                assert!(std::fs::metadata(path).unwrap().len() <= 92);
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_formats_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, format) in &[
+            ("jsonl", OutputFormat::JsonLines),
+            ("json-array", OutputFormat::JsonArray),
+        ] {
+            for count in 0..3 {
+                let path = dir.path().join(format!("{}-{}.json", name, count));
+                let file = std::fs::File::create(&path).unwrap();
+                let (mut emitter, flush_signal) = FileEmitter::new(
+                    file,
+                    *format,
+                    FlushPolicy::default(),
+                    None,
+                    EmitOrder::AsEmitted,
+                );
+                for _ in 0..count {
+                    emitter.emit_vertex(ResultSet {});
+                }
+                emitter.end();
+                flush_signal.recv().unwrap();
+
+                let mut entries = Vec::new();
+                for_each_entry(&[&path], |entry| entries.push(entry.id.clone())).unwrap();
+                let ids = (1..=count).map(NumberOrString::Number).collect::<Vec<_>>();
+                assert_eq!(entries, ids, "{} with {} entries", name, count);
+            }
+        }
+    }
+}
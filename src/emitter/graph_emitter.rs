@@ -0,0 +1,35 @@
+use crate::{
+    emitter::emitter::Emitter,
+    graph::Graph,
+    protocol::types::{Edge, Vertex, ID},
+};
+
+/// An `Emitter` that builds the graph in memory, to query it instead of writing a dump.
+/// IDs are assigned the same way as by `FileEmitter`.
+#[derive(Debug, Default)]
+pub struct GraphEmitter {
+    id: ID,
+    graph: Graph,
+}
+
+impl GraphEmitter {
+    pub fn into_graph(self) -> Graph {
+        self.graph
+    }
+}
+
+impl Emitter for GraphEmitter {
+    fn emit_vertex<V: Into<Vertex>>(&mut self, v: V) -> ID {
+        self.id += 1;
+        self.graph.add_vertex(self.id, &v.into());
+        self.id
+    }
+
+    fn emit_edge<E: Into<Edge>>(&mut self, e: E) -> ID {
+        self.id += 1;
+        self.graph.add_edge(&e.into());
+        self.id
+    }
+
+    fn end(&mut self) {}
+}
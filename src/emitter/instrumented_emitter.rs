@@ -0,0 +1,145 @@
+use std::ops::{Deref, DerefMut};
+
+use serde_derive::Serialize;
+
+use crate::{
+    emitter::emitter::Emitter,
+    protocol::types::{Edge, Vertex, ID},
+};
+
+/// The number of vertices and edges of each kind that were emitted, e.g. to tell at a glance
+/// that no reference results were produced.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct EmitCounts {
+    pub vertices: u64,
+    pub edges: u64,
+
+    pub documents: u64,
+    pub ranges: u64,
+    pub result_sets: u64,
+    pub definition_results: u64,
+    pub reference_results: u64,
+    pub hover_results: u64,
+    pub monikers: u64,
+
+    pub contains_edges: u64,
+    pub next_edges: u64,
+    pub item_edges: u64,
+    pub hover_edges: u64,
+}
+
+impl EmitCounts {
+    fn count_vertex(&mut self, v: &Vertex) {
+        self.vertices += 1;
+        match v {
+            Vertex::Document(_) => self.documents += 1,
+            Vertex::Range(_) => self.ranges += 1,
+            Vertex::ResultSet(_) => self.result_sets += 1,
+            Vertex::DefinitionResult(_) => self.definition_results += 1,
+            Vertex::ReferenceResult(_) => self.reference_results += 1,
+            Vertex::HoverResult(_) => self.hover_results += 1,
+            Vertex::Moniker(_) => self.monikers += 1,
+            _ => {}
+        }
+    }
+
+    fn count_edge(&mut self, e: &Edge) {
+        self.edges += 1;
+        match e {
+            Edge::Contains(_) => self.contains_edges += 1,
+            Edge::Next(_) => self.next_edges += 1,
+            Edge::Item(_) => self.item_edges += 1,
+            Edge::Hover(_) => self.hover_edges += 1,
+            _ => {}
+        }
+    }
+}
+
+/// An `Emitter` that counts what is emitted through the emitter it wraps.
+#[derive(Debug, Default)]
+pub struct InstrumentedEmitter<E> {
+    emitter: E,
+    counts: EmitCounts,
+}
+
+impl<E> InstrumentedEmitter<E> {
+    pub fn new(emitter: E) -> Self {
+        Self {
+            emitter,
+            counts: EmitCounts::default(),
+        }
+    }
+
+    pub fn counts(&self) -> &EmitCounts {
+        &self.counts
+    }
+
+    pub fn into_inner(self) -> E {
+        self.emitter
+    }
+}
+
+impl<E> Deref for InstrumentedEmitter<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.emitter
+    }
+}
+
+impl<E> DerefMut for InstrumentedEmitter<E> {
+    fn deref_mut(&mut self) -> &mut E {
+        &mut self.emitter
+    }
+}
+
+impl<E: Emitter> Emitter for InstrumentedEmitter<E> {
+    fn emit_vertex<V: Into<Vertex>>(&mut self, v: V) -> ID {
+        let v = v.into();
+        self.counts.count_vertex(&v);
+        self.emitter.emit_vertex(v)
+    }
+
+    fn emit_edge<E2: Into<Edge>>(&mut self, e: E2) -> ID {
+        let e = e.into();
+        self.counts.count_edge(&e);
+        self.emitter.emit_edge(e)
+    }
+
+    fn end(&mut self) {
+        self.emitter.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmitCounts, InstrumentedEmitter};
+    use crate::{
+        emitter::{emitter::Emitter, memory_emitter::MemoryEmitter},
+        protocol::types::{DefinitionResult, Edge, ResultSet},
+    };
+
+    #[test]
+    fn test_counts() {
+        let mut emitter = InstrumentedEmitter::new(MemoryEmitter::default());
+        let result_set_id = emitter.emit_vertex(ResultSet {});
+        let def_result_id = emitter.emit_vertex(DefinitionResult {});
+        let document_id = emitter.emit_vertex(ResultSet {});
+        emitter.emit_edge(Edge::contains(document_id, vec![result_set_id]));
+        emitter.emit_edge(Edge::contains(document_id, vec![def_result_id]));
+
+        assert_eq!(
+            emitter.counts(),
+            &EmitCounts {
+                vertices: 3,
+                edges: 2,
+                result_sets: 2,
+                definition_results: 1,
+                contains_edges: 2,
+                ..Default::default()
+            }
+        );
+        let emitter = emitter.into_inner();
+        assert_eq!((emitter.vertices.len(), emitter.edges.len()), (3, 2));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{
+    emitter::emitter::Emitter,
+    protocol::types::{Edge, Vertex, ID},
+};
+
+/// An `Emitter` that keeps the emitted data in memory, so that the graph can be inspected.
+/// IDs are assigned the same way as by `FileEmitter`.
+#[derive(Debug, Default)]
+pub struct MemoryEmitter {
+    id: ID,
+    pub vertices: Vec<Vertex>,
+    pub edges: Vec<Edge>,
+}
+
+impl Emitter for MemoryEmitter {
+    fn emit_vertex<V: Into<Vertex>>(&mut self, v: V) -> ID {
+        self.id += 1;
+        self.vertices.push(v.into());
+        self.id
+    }
+
+    fn emit_edge<E: Into<Edge>>(&mut self, e: E) -> ID {
+        self.id += 1;
+        self.edges.push(e.into());
+        self.id
+    }
+
+    fn end(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use languageserver_types::{NumberOrString, Position, Range, SymbolKind, Url};
+    use serde_json::json;
+
+    use super::MemoryEmitter;
+    use crate::{
+        dump::reader::DumpReader,
+        edge,
+        emitter::emitter::Emitter,
+        protocol::types::{
+            ByteOffsets, Contents, DeclarationResult, DefinitionResult, DefinitionTag,
+            DiagnosticResult, Document, DocumentLinkResult, Edge, EdgeData, Element, Entry,
+            FoldingRangeResult, HoverResult, Invocation, Item, LSIFMarkedString, Language,
+            MetaData, Moniker, MonikerUnique, MultiEdgeDataWithDocument, PackageInformation,
+            Project, RangeTag, RangeVertex, ReferenceResult, ReferenceTag, ResultSet, ToolInfo,
+            Vertex,
+        },
+    };
+
+    /// Emits each kind of vertex and edge, the vertices first, with their optional fields set
+    /// on some of them.
+    fn emit_all(emitter: &mut MemoryEmitter) {
+        let range = Range::new(Position::new(2, 3), Position::new(2, 6));
+        let range_vertex = |tag| RangeVertex {
+            range,
+            tag,
+            offsets: None,
+            renameable: None,
+        };
+
+        emitter.emit_vertex(MetaData {
+            version: "0.4.0".to_string(),
+            position_encoding: "utf-16".to_string(),
+            tool_info: Some(ToolInfo::default()),
+            project_root: Url::parse("file:///zas").unwrap(),
+            project_name: None,
+            invocation: None,
+        });
+        let project = emitter.emit_vertex(Project {
+            language_id: Language::TypeScript,
+        });
+        let document = emitter.emit_vertex(Document {
+            uri: Url::parse("file:///zas/main.ts").unwrap(),
+            language_id: "typescript".to_string(),
+            content_hash: None,
+        });
+        let definition =
+            emitter.emit_vertex(range_vertex(Some(RangeTag::Definition(DefinitionTag {
+                text: "foo".to_string(),
+                kind: SymbolKind::Function,
+                full_range: range,
+                detail: None,
+            }))));
+        let reference =
+            emitter.emit_vertex(range_vertex(Some(RangeTag::Reference(ReferenceTag {
+                text: "foo".to_string(),
+            }))));
+        let unknown = emitter.emit_vertex(range_vertex(Some(RangeTag::Unknown(ReferenceTag {
+            text: "bar".to_string(),
+        }))));
+        let untagged = emitter.emit_vertex(range_vertex(None));
+        let result_set = emitter.emit_vertex(ResultSet {});
+        let definition_result = emitter.emit_vertex(DefinitionResult {});
+        let declaration_result = emitter.emit_vertex(DeclarationResult {});
+        let reference_result = emitter.emit_vertex(ReferenceResult {});
+        let hover_result = emitter.emit_vertex(HoverResult {
+            result: Contents {
+                contents: vec![LSIFMarkedString {
+                    language: "typescript".to_string(),
+                    value: "function foo(): void".to_string(),
+                    is_raw_string: false,
+                }],
+            },
+        });
+        let folding_ranges = emitter.emit_vertex(FoldingRangeResult {
+            result: json!([{"startLine": 0, "endLine": 3}]),
+        });
+        let document_links = emitter.emit_vertex(DocumentLinkResult { result: json!([]) });
+        let diagnostics = emitter.emit_vertex(DiagnosticResult { result: json!([]) });
+        let moniker = emitter.emit_vertex(Moniker {
+            kind: "local".to_string(),
+            scheme: "zas".to_string(),
+            identifier: "main.ts:foo".to_string(),
+            unique: MonikerUnique::Document,
+        });
+        let package = emitter.emit_vertex(PackageInformation {
+            name: "zas".to_string(),
+            manager: "npm".to_string(),
+            version: Some("0.1.0".to_string()),
+        });
+        emitter.emit_vertex(Vertex::ExportResult);
+        emitter.emit_vertex(Vertex::ExternalImportResult);
+        emitter.emit_vertex(MetaData {
+            version: "0.4.0".to_string(),
+            position_encoding: "utf-8".to_string(),
+            tool_info: Some(ToolInfo {
+                name: "universal-lsif".to_string(),
+                version: Some("0.1.0".to_string()),
+                args: Some(vec!["typescript".to_string()]),
+            }),
+            project_root: Url::parse("file:///zas").unwrap(),
+            project_name: Some("zas".to_string()),
+            invocation: Some(Box::new(Invocation {
+                language: "typescript".to_string(),
+                server_command: Some("typescript-language-server".to_string()),
+                server_args: Some("--stdio".to_string()),
+                timestamp: 1,
+                os: "linux".to_string(),
+                arch: "x86_64".to_string(),
+            })),
+        });
+        emitter.emit_vertex(Document {
+            uri: Url::parse("file:///zas/lib.ts").unwrap(),
+            language_id: "typescript".to_string(),
+            content_hash: Some("00".to_string()),
+        });
+        emitter.emit_vertex(RangeVertex {
+            range,
+            tag: Some(RangeTag::Definition(DefinitionTag {
+                text: "baz".to_string(),
+                kind: SymbolKind::Function,
+                full_range: range,
+                detail: Some("function baz(): void".to_string()),
+            })),
+            offsets: Some(ByteOffsets { start: 12, end: 15 }),
+            renameable: Some(true),
+        });
+
+        let item = |out_v, in_v| MultiEdgeDataWithDocument {
+            document,
+            in_vs: vec![NumberOrString::Number(in_v)],
+            out_v: NumberOrString::Number(out_v),
+        };
+        let edges = vec![
+            Edge::contains(project, vec![document]),
+            Edge::contains(document, vec![definition, reference, unknown, untagged]),
+            edge!(Next, definition -> result_set),
+            edge!(RefersTo, reference -> result_set),
+            edge!(Moniker, result_set -> moniker),
+            edge!(PackageInformation, moniker -> package),
+            edge!(Definition, result_set -> definition_result),
+            edge!(Declaration, result_set -> declaration_result),
+            edge!(Hover, result_set -> hover_result),
+            edge!(References, result_set -> reference_result),
+            edge!(Implementation, result_set -> definition_result),
+            edge!(TypeDefinition, result_set -> definition_result),
+            edge!(FoldingRange, document -> folding_ranges),
+            edge!(DocumentLink, document -> document_links),
+            edge!(DocumentSymbol, document -> untagged),
+            edge!(Diagnostic, document -> diagnostics),
+            Edge::Item(Item::Definition(item(reference_result, definition))),
+            Edge::Item(Item::Reference(item(reference_result, reference))),
+            Edge::Item(Item::Neither(item(definition_result, definition))),
+        ];
+        for edge in edges {
+            emitter.emit_edge(edge);
+        }
+    }
+
+    #[test]
+    fn test_emitted_entries_round_trip() {
+        let mut emitter = MemoryEmitter::default();
+        emit_all(&mut emitter);
+        let elements = emitter
+            .vertices
+            .iter()
+            .cloned()
+            .map(Element::Vertex)
+            .chain(emitter.edges.iter().cloned().map(Element::Edge));
+        let entries = (1..)
+            .zip(elements)
+            .map(|(id, data)| Entry {
+                id: NumberOrString::Number(id),
+                data,
+            })
+            .collect::<Vec<_>>();
+
+        let read = |dump: &str| {
+            DumpReader::new(dump.as_bytes(), "dump.json".to_string())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        let lines = entries
+            .iter()
+            .map(|entry| serde_json::to_string(entry).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(read(&lines.join("\n")), entries);
+        assert_eq!(read(&format!("\n{}\n\n", lines.join("\n"))), entries);
+        assert_eq!(read(&format!("[\n{}\n]\n", lines.join(",\n"))), entries);
+        assert_eq!(
+            read(&serde_json::to_string_pretty(&entries).unwrap()),
+            entries
+        );
+        assert!(read("").is_empty());
+        assert!(read(" [ ] ").is_empty());
+
+        // The wire format of the fields that consumers rely on
+        let json = |idx: usize| serde_json::to_value(&entries[idx]).unwrap();
+        assert_eq!(
+            json(2),
+            json!({
+                "id": 3, "type": "vertex", "label": "document",
+                "uri": "file:///zas/main.ts", "languageId": "typescript",
+            })
+        );
+        assert_eq!(
+            json(3),
+            json!({
+                "id": 4, "type": "vertex", "label": "range",
+                "start": {"line": 2, "character": 3}, "end": {"line": 2, "character": 6},
+                "tag": {
+                    "type": "definition", "text": "foo", "kind": 12,
+                    "fullRange": {
+                        "start": {"line": 2, "character": 3},
+                        "end": {"line": 2, "character": 6},
+                    },
+                },
+            })
+        );
+        assert_eq!(
+            json(15),
+            json!({
+                "id": 16, "type": "vertex", "label": "moniker",
+                "kind": "local", "scheme": "zas", "identifier": "main.ts:foo",
+                "unique": "document",
+            })
+        );
+        let item = entries.len() - 3;
+        assert_eq!(
+            json(item),
+            json!({
+                "id": item + 1, "type": "edge", "label": "item", "property": "definitions",
+                "document": 3, "inVs": [4], "outV": 11,
+            })
+        );
+        // Not an item of a reference result, so it has no `property`
+        assert_eq!(
+            json(item + 2),
+            json!({
+                "id": item + 3, "type": "edge", "label": "item",
+                "document": 3, "inVs": [4], "outV": 9,
+            })
+        );
+    }
+}
@@ -1,2 +1,7 @@
+pub mod emit_order;
 pub mod emitter;
 pub mod file_emitter;
+pub mod graph_emitter;
+pub mod instrumented_emitter;
+#[cfg(test)]
+pub mod memory_emitter;
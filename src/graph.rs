@@ -0,0 +1,147 @@
+use std::{cmp::Reverse, collections::HashMap, path::Path};
+
+use languageserver_types::{Location, Position, Range};
+
+use crate::{
+    protocol::types::{Edge, Item, NumberOrString, Url, Vertex, ID},
+    uri::{file_uri, normalize_uri},
+};
+
+/// The maximum length of a chain of `next` edges that is followed, in case of a cycle.
+const MAX_NEXT_CHAIN: usize = 32;
+
+/// An LSIF graph kept in memory and indexed to answer code intelligence queries, as built by
+/// `GraphEmitter`.
+#[derive(Debug, Default)]
+pub struct Graph {
+    /// Document key (as returned by `file_uri`) -> Document ID
+    documents: HashMap<String, ID>,
+    /// Document ID -> URI
+    uris: HashMap<ID, Url>,
+    /// Range ID -> Range
+    ranges: HashMap<ID, Range>,
+    /// Document ID -> IDs of the ranges it contains
+    contains: HashMap<ID, Vec<ID>>,
+    /// Range ID -> ID of its document
+    range_documents: HashMap<ID, ID>,
+    /// Vertex ID -> ID of the vertex its `next` edge points to
+    next: HashMap<ID, ID>,
+    /// Result set ID -> definition result ID
+    definition_results: HashMap<ID, ID>,
+    /// Result set ID -> reference result ID
+    reference_results: HashMap<ID, ID>,
+    /// Definition or reference result ID -> IDs of its definition ranges
+    definition_items: HashMap<ID, Vec<ID>>,
+    /// Reference result ID -> IDs of its reference ranges
+    reference_items: HashMap<ID, Vec<ID>>,
+}
+
+impl Graph {
+    pub fn add_vertex(&mut self, id: ID, vertex: &Vertex) {
+        match vertex {
+            Vertex::Document(document) => {
+                self.documents.insert(normalize_uri(&document.uri), id);
+                self.uris.insert(id, document.uri.clone());
+            }
+            Vertex::Range(range) => {
+                self.ranges.insert(id, range.range);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn add_edge(&mut self, edge: &Edge) {
+        match edge {
+            Edge::Contains(data) => {
+                let document_id = number(&data.out_v);
+                for range_id in data.in_vs.iter().map(number) {
+                    self.contains.entry(document_id).or_default().push(range_id);
+                    self.range_documents.insert(range_id, document_id);
+                }
+            }
+            Edge::Next(data) => {
+                self.next.insert(number(&data.out_v), number(&data.in_v));
+            }
+            Edge::Definition(data) => {
+                self.definition_results
+                    .insert(number(&data.out_v), number(&data.in_v));
+            }
+            Edge::References(data) => {
+                self.reference_results
+                    .insert(number(&data.out_v), number(&data.in_v));
+            }
+            Edge::Item(Item::Definition(data)) | Edge::Item(Item::Neither(data)) => self
+                .definition_items
+                .entry(number(&data.out_v))
+                .or_default()
+                .extend(data.in_vs.iter().map(number)),
+            Edge::Item(Item::Reference(data)) => self
+                .reference_items
+                .entry(number(&data.out_v))
+                .or_default()
+                .extend(data.in_vs.iter().map(number)),
+            _ => {}
+        }
+    }
+
+    /// Returns where the symbol at the given position is defined. If it has more than one
+    /// definition, the first one is returned.
+    pub fn definition_of(&self, file: &Path, position: Position) -> Option<Location> {
+        let result_id = self.result_at(file, position, &self.definition_results)?;
+        self.locations(self.definition_items.get(&result_id)?)
+            .into_iter()
+            .next()
+    }
+
+    /// Returns where the symbol at the given position is referenced, not including its
+    /// definitions.
+    pub fn references_to(&self, file: &Path, position: Position) -> Vec<Location> {
+        self.result_at(file, position, &self.reference_results)
+            .and_then(|result_id| self.reference_items.get(&result_id))
+            .map(|range_ids| self.locations(range_ids))
+            .unwrap_or_default()
+    }
+
+    /// Returns the innermost range of the given file that contains the given position.
+    fn range_at(&self, file: &Path, position: Position) -> Option<ID> {
+        let document_id = self.documents.get(&file_uri(file))?;
+        self.contains
+            .get(document_id)?
+            .iter()
+            .filter_map(|id| Some((*id, self.ranges.get(id)?)))
+            .filter(|(_id, range)| range.start <= position && position < range.end)
+            .max_by_key(|(_id, range)| (range.start, Reverse(range.end)))
+            .map(|(id, _range)| id)
+    }
+
+    /// Follows the `next` edges from the range at the given position to the first vertex
+    /// that has a result in `results`, and returns the ID of that result.
+    fn result_at(&self, file: &Path, position: Position, results: &HashMap<ID, ID>) -> Option<ID> {
+        let mut id = self.range_at(file, position)?;
+        for _ in 0..MAX_NEXT_CHAIN {
+            if let Some(result_id) = results.get(&id) {
+                return Some(*result_id);
+            }
+            id = *self.next.get(&id)?;
+        }
+        None
+    }
+
+    fn locations(&self, range_ids: &[ID]) -> Vec<Location> {
+        range_ids
+            .iter()
+            .filter_map(|id| {
+                let uri = self.uris.get(self.range_documents.get(id)?)?;
+                Some(Location::new(uri.clone(), *self.ranges.get(id)?))
+            })
+            .collect()
+    }
+}
+
+fn number(id: &NumberOrString) -> ID {
+    match id {
+        NumberOrString::Number(n) => *n,
+        // Only emitters that assign numbers build a graph
+        NumberOrString::String(s) => s.parse().unwrap_or_default(),
+    }
+}
@@ -1,44 +1,124 @@
 use std::{
-    collections::HashMap,
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
     sync::{
         mpsc::{channel, Receiver},
         Arc,
     },
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Result;
 use ignore::{DirEntry, Walk};
-use languageserver_types::{NumberOrString, Url};
+use languageserver_types::NumberOrString;
+use serde_derive::Serialize;
 use serde_json::to_string;
+use sha2::{Digest, Sha256};
 
 use crate::{
-    cli::Args,
-    crawler::{paths, Definition, Reference},
+    cli::{redact_args, Args, HoverFormat, Monikers},
+    crawler::{paths, Definition, DocumentExtras, Location, Occurrence, Reference},
     edge,
-    emitter::emitter::Emitter,
+    emitter::{
+        emitter::Emitter,
+        instrumented_emitter::{EmitCounts, InstrumentedEmitter},
+    },
     lsif_data_cache::{DefinitionInfo, LsifDataCache},
-    lsp::LSConfig,
+    lsp::{LSConfig, PositionEncoding},
+    package::package_information,
     protocol::types::{
-        Contents, DefinitionResult, Document, Edge, EdgeData, HoverResult, LSIFMarkedString,
-        Language, MetaData, Moniker, ReferenceResult, ResultSet, ToolInfo, ID,
+        Contents, DeclarationResult, DefinitionResult, DiagnosticResult, Document,
+        DocumentLinkResult, Edge, EdgeData, FoldingRangeResult, HoverResult, Invocation,
+        LSIFMarkedString, Language, MetaData, Moniker, MonikerUnique, RangeVertex, ReferenceResult,
+        ResultSet, ToolInfo, Url, ID,
     },
+    uri::{dir_to_uri, file_uri, path_to_uri},
 };
 
 pub struct Indexer<E>
 where
     E: Emitter,
 {
-    emitter: E,
+    emitter: InstrumentedEmitter<E>,
     tool_info: ToolInfo,
     opt: Args,
     config: LSConfig,
+    /// The encoding of the positions of the ranges, as negotiated with the server.
+    position_encoding: PositionEncoding,
 
     project_id: ID,
+    /// The `packageInformation` vertex of the project's package, linked to the `export`
+    /// monikers, if the manifest of the language's package manager names one.
+    package_information_id: Option<ID>,
+    /// The name of the project's package, which prefixes the identifiers of global monikers.
+    package_name: Option<String>,
 
     cache: LsifDataCache,
 
     cached_file_paths: Option<Vec<PathBuf>>,
+
+    /// The result sets of the definitions that are declared somewhere else, with the
+    /// locations of their declarations. Linked once all ranges are emitted.
+    declarations: Vec<(ID, Location)>,
+
+    /// Definition location -> the result set with the `import` moniker of its references,
+    /// for the definitions that were not indexed
+    imported_result_sets: HashMap<Location, ID>,
+
+    /// Package root of a dependency -> its package name and `packageInformation` vertex,
+    /// if its manifest names one
+    dependency_packages: HashMap<PathBuf, Option<(String, ID)>>,
+
+    stats: IndexStats,
+}
+
+/// Metrics about a run, written with `--stats-json`. The crawler fills in the fields about
+/// files and requests, the indexer those about the emitted data.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct IndexStats {
+    pub files_crawled: u64,
+    /// Files skipped because a checkpoint says they were crawled by a previous run
+    pub files_resumed: u64,
+    /// Files skipped because of an error
+    pub files_failed: u64,
+    /// Files that had more words than `--max-requests-per-file`
+    pub files_truncated: u64,
+    pub requests_sent: u64,
+    /// The requests sent per second of crawling
+    pub requests_per_second: f64,
+    pub server_respawns: u64,
+    /// Restarts of the language server because of `--server-restart-every`
+    pub server_restarts: u64,
+    /// Definition requests that the server returned an error for, or references requests
+    /// with `--strategy references`
+    pub failed_definition_requests: u64,
+    /// Definition requests that the server found no definition for, e.g. because the word
+    /// is not a symbol
+    pub empty_definition_responses: u64,
+    pub definitions: u64,
+    pub declarations: u64,
+    pub references: u64,
+    /// References whose definitions were not indexed. Those defined in dependencies are linked
+    /// to `import` monikers instead.
+    pub unresolved_references: u64,
+    pub monikers: u64,
+    /// Whether the crawl stopped at `--timeout-total` before all files were crawled
+    pub timed_out: bool,
+    pub durations: PhaseDurations,
+    /// The vertices and edges of each kind in the dump
+    pub emitted: EmitCounts,
+}
+
+/// The wall-clock time of each phase in milliseconds. The crawl phase overlaps with the
+/// definitions and references phase, since the indexer runs on its own thread.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PhaseDurations {
+    pub crawl_ms: u64,
+    pub documents_ms: u64,
+    pub definitions_and_references_ms: u64,
+    pub extras_ms: u64,
+    pub reference_results_ms: u64,
+    pub contains_ms: u64,
 }
 
 impl<E> Indexer<E>
@@ -46,40 +126,67 @@ where
     E: Emitter,
 {
     /// Generates an LSIF dump from a project by traversing through files of the given language
-    /// and emitting the LSIF equivalent using the given emitter.
+    /// and emitting the LSIF equivalent using the given emitter, which is returned once it
+    /// has ended.
     pub fn index(
         opt: Args,
         config: LSConfig,
+        position_encoding: PositionEncoding,
         emitter: E,
-        def_rx: Receiver<Definition>,
-        ref_rx: Receiver<Reference>,
-    ) -> Result<()> {
+        occurrence_rx: Receiver<Occurrence>,
+        extras_rx: Receiver<DocumentExtras>,
+    ) -> Result<(IndexStats, E)> {
         let mut indexer = Self {
-            emitter,
+            emitter: InstrumentedEmitter::new(emitter),
             config,
             tool_info: ToolInfo::default(),
             opt: opt.clone(),
+            position_encoding,
             project_id: 0,
+            package_information_id: None,
+            package_name: None,
             cache: LsifDataCache::default(),
             cached_file_paths: Default::default(),
+            declarations: Vec::new(),
+            imported_result_sets: HashMap::new(),
+            dependency_packages: HashMap::new(),
+            stats: IndexStats::default(),
         };
 
-        indexer.emit_metadata_and_project_vertex();
-        indexer.emit_documents();
-        indexer.emit_defs_and_refs(def_rx, ref_rx);
-        indexer.link_reference_results_to_ranges();
-        indexer.emit_contains();
+        indexer.emit_metadata_and_project_vertex()?;
+        let mut documents = Ok(());
+        let documents_ms = timed(|| documents = indexer.emit_documents());
+        documents?;
+        let durations = PhaseDurations {
+            documents_ms,
+            definitions_and_references_ms: timed(|| indexer.emit_defs_and_refs(occurrence_rx)),
+            extras_ms: timed(|| indexer.emit_document_extras(extras_rx)),
+            reference_results_ms: timed(|| indexer.link_reference_results_to_ranges()),
+            contains_ms: timed(|| indexer.emit_contains()),
+            crawl_ms: 0,
+        };
 
         indexer.emitter.end();
 
-        Ok(())
+        let stats = IndexStats {
+            durations,
+            emitted: indexer.emitter.counts().clone(),
+            ..indexer.stats
+        };
+        Ok((stats, indexer.emitter.into_inner()))
     }
 
     /// Emits the contains relationship for all documents and the ranges that they contain.
     fn emit_contains(&mut self) {
         let documents = self.cache.get_documents();
         for d in documents {
-            let all_range_ids = [&d.reference_range_ids[..], &d.definition_range_ids[..]].concat();
+            // A range can be both a definition and a reference, e.g. of a recursive function
+            let mut seen = HashSet::new();
+            let all_range_ids = [&d.reference_range_ids[..], &d.definition_range_ids[..]]
+                .concat()
+                .into_iter()
+                .filter(|id| seen.insert(*id))
+                .collect::<Vec<_>>();
             if !all_range_ids.is_empty() {
                 self.emitter.emit_edge(Edge::contains(d.id, all_range_ids));
             }
@@ -102,7 +209,10 @@ where
 
     /// Adds item relations between the given definition range and the ranges that
     /// define and reference it.
-    fn link_items_to_definitions(def_infos: &Vec<&mut DefinitionInfo>, emitter: &mut E) {
+    fn link_items_to_definitions(
+        def_infos: &Vec<&mut DefinitionInfo>,
+        emitter: &mut InstrumentedEmitter<E>,
+    ) {
         for d in def_infos {
             let ref_result_id = emitter.emit_vertex(ReferenceResult {});
 
@@ -123,57 +233,366 @@ where
         }
     }
 
-    fn emit_defs_and_refs(&mut self, def_rx: Receiver<Definition>, ref_rx: Receiver<Reference>) {
-        for def in def_rx {
-            //dbg!(&def);
-            self.index_definition(def);
+    /// Indexes definitions and references as they arrive. References to definitions that
+    /// have not arrived yet, e.g. from a file that is crawled later, are indexed once all
+    /// definitions are known.
+    fn emit_defs_and_refs(&mut self, occurrence_rx: Receiver<Occurrence>) {
+        let mut pending = Vec::new();
+        for occurrence in occurrence_rx {
+            match occurrence {
+                Occurrence::Definition(def) => self.index_definition(def),
+                Occurrence::Reference(r) => {
+                    if !self.index_reference(&r) {
+                        pending.push(r);
+                    }
+                }
+            }
         }
 
-        for r in ref_rx {
-            //dbg!(&r);
-            self.index_reference(r);
+        for r in pending {
+            if !self.index_reference(&r) {
+                self.stats.unresolved_references += 1;
+                self.index_imported_reference(&r);
+            }
+        }
+        if self.stats.unresolved_references > 0 {
+            // Mostly references to definitions outside of the project
+            log::warn!(
+                "{} references have definitions that were not indexed, linked those in dependencies to `import` monikers",
+                self.stats.unresolved_references
+            );
+        }
+
+        self.emit_declarations();
+    }
+
+    /// Emits a declaration result for each definition that is declared somewhere else in the
+    /// project. The range of the declaration is emitted unless it is already a definition or
+    /// a reference.
+    fn emit_declarations(&mut self) {
+        for (result_set_id, location) in std::mem::take(&mut self.declarations) {
+            let document_id = match self.cache.get_document_id(&location.file_path) {
+                Some(it) => it,
+                None => continue,
+            };
+            let range_id = match self
+                .cache
+                .get_range_id(&location.file_path, &location.range)
+            {
+                Some(it) => it,
+                None => {
+                    let range_id = self.emitter.emit_vertex(RangeVertex {
+                        range: location.range.range(),
+                        tag: None,
+                        offsets: location.range.offsets(),
+                        renameable: None,
+                    });
+                    self.cache.cache_declaration_range(&location, range_id);
+                    range_id
+                }
+            };
+
+            let declaration_result_id = self.emitter.emit_vertex(DeclarationResult {});
+            self.emitter
+                .emit_edge(edge!(Declaration, result_set_id -> declaration_result_id));
+            self.emitter.emit_edge(Edge::item(
+                declaration_result_id,
+                vec![range_id],
+                document_id,
+            ));
+            self.stats.declarations += 1;
+        }
+    }
+
+    /// Emits the folding range, document link and diagnostic results of each document.
+    fn emit_document_extras(&mut self, extras_rx: Receiver<DocumentExtras>) {
+        for extras in extras_rx {
+            let document_id = match self.cache.get_document_id(&extras.file_path) {
+                Some(it) => it,
+                None => continue,
+            };
+
+            if let Some(result) = extras.folding_ranges {
+                let result_id = self.emitter.emit_vertex(FoldingRangeResult { result });
+                self.emitter
+                    .emit_edge(edge!(FoldingRange, document_id -> result_id));
+            }
+            if let Some(result) = extras.document_links {
+                let result_id = self.emitter.emit_vertex(DocumentLinkResult { result });
+                self.emitter
+                    .emit_edge(edge!(DocumentLink, document_id -> result_id));
+            }
+            if let Some(result) = extras.diagnostics {
+                let result_id = self.emitter.emit_vertex(DiagnosticResult { result });
+                self.emitter
+                    .emit_edge(edge!(Diagnostic, document_id -> result_id));
+            }
         }
     }
 
     /// Emits data for the given reference object and caches it for emitting 'contains' later.
-    fn index_reference(&mut self, r: Reference) {
-        self.index_reference_to_definition(&r.def, &r);
+    /// Returns false if its definition has not been indexed yet.
+    fn index_reference(&mut self, r: &Reference) -> bool {
+        self.index_reference_to_definition(&r.def, r)
     }
 
     /// Returns a range identifier for the given reference. If a range for the object has
     /// not been emitted, a new vertex is created.
-    fn ensure_range_for(&mut self, r: &Reference) -> ID {
-        match self
+    ///
+    /// Returns `None` if the range is a definition, e.g. the name of a recursive function
+    /// that the server resolves to itself, so that it is not a reference at the same time.
+    fn ensure_range_for(&mut self, r: &Reference) -> Option<ID> {
+        if self.cache.get_definition_info(&r.location).is_some() {
+            return None;
+        }
+        let range_id = match self
             .cache
             .get_range_id(&r.location.file_path, &r.location.range)
         {
             Some(range_id) => range_id,
             None => {
-                let range_id = self.emitter.emit_vertex(r.range());
+                let range_id = self.emitter.emit_vertex(r.range_vertex());
                 self.cache.cache_reference_range(r, range_id);
                 range_id
             }
-        }
+        };
+        Some(range_id)
     }
 
     /// Emits data for the given reference object that is defined within
     /// an index target package.
-    fn index_reference_to_definition(&mut self, def: &Definition, r: &Reference) {
+    ///
+    /// Returns false if the definition has not been indexed yet, in which case nothing is
+    /// emitted.
+    fn index_reference_to_definition(&mut self, def: &Definition, r: &Reference) -> bool {
+        if self.cache.get_document_id(&r.location.file_path).is_none() {
+            // E.g. found with `--strategy references` in a file that is not indexed
+            return true;
+        }
+        let (def_result_set_id, def_has_hover) = match self.cache.get_definition_info(&def.location)
+        {
+            Some(it) => (it.result_set_id, it.has_hover),
+            None => return false,
+        };
+
         // 1. Emit/Get vertices(s)
-        let range_id = self.ensure_range_for(r);
+        let range_id = match self.ensure_range_for(r) {
+            Some(it) => it,
+            // Already linked to its result set as a definition
+            None => return true,
+        };
 
         // 2. Connect the emitted vertices
-        let next_edge = {
-            let def_result_set_id = match self.cache.get_definition_info(&def.location) {
-                Some(it) => it.result_set_id,
-                None => return,
-            };
-            edge!(Next, range_id -> def_result_set_id)
-        };
-        self.emitter.emit_edge(next_edge);
+        self.emitter
+            .emit_edge(edge!(Next, range_id -> def_result_set_id));
+        self.stats.references += 1;
+
+        // The hover is shared by the definition and all of its references, so fall back
+        // to the hover at the reference if the definition has none.
+        if let (false, Some(comment)) = (def_has_hover, r.comment.clone()) {
+            self.emit_hover(def_result_set_id, comment);
+            if let Some(def_info) = self.cache.get_mut_definition_info(&def.location) {
+                def_info.has_hover = true;
+            }
+        }
 
         // 3. Cache the result
         self.cache.cache_reference(&def, &r, range_id);
+        true
+    }
+
+    /// Links the given reference, whose definition was not indexed because it is in a
+    /// dependency, to a result set with an `import` moniker, so that it can be resolved with
+    /// the dump of the project that exports the definition. The references to the same
+    /// definition share the result set. References to definitions in the project, in files
+    /// that were not indexed, are left unlinked.
+    fn index_imported_reference(&mut self, r: &Reference) {
+        if self.cache.get_document_id(&r.location.file_path).is_none() {
+            return;
+        }
+        let result_set_id = match self.imported_result_sets.get(&r.def.location) {
+            Some(it) => *it,
+            None => {
+                let (moniker, package_id) =
+                    match self.import_moniker(&r.def.location, &r.def.node_name) {
+                        Some(it) => it,
+                        // Defined in the project, in a file that was not indexed
+                        None => return,
+                    };
+                let result_set_id = self.emitter.emit_vertex(ResultSet {});
+                let moniker_id = self.ensure_moniker(moniker, package_id);
+                self.emitter
+                    .emit_edge(edge!(Moniker, result_set_id -> moniker_id));
+                if let Some(comment) = r.comment.clone() {
+                    self.emit_hover(result_set_id, comment);
+                }
+                self.imported_result_sets
+                    .insert(r.def.location.clone(), result_set_id);
+                result_set_id
+            }
+        };
+        let range_id = match self.ensure_range_for(r) {
+            Some(it) => it,
+            None => return,
+        };
+        self.emitter
+            .emit_edge(edge!(Next, range_id -> result_set_id));
+        if let Some(document) = self.cache.get_mut_document(&r.location.file_path) {
+            document.reference_range_ids.push(range_id);
+        }
+    }
+
+    /// Links the given result set to a hover result with the given contents. The hover
+    /// results are shared by all the result sets with the same contents, e.g. `i: i32`.
+    fn emit_hover(&mut self, result_set_id: ID, contents: String) {
+        let hover_result_id = match self.cache.get_hover_result_id(&contents) {
+            Some(hover_result_id) => hover_result_id,
+            None => {
+                let hover_result_id = self.emit_hover_result(contents.clone());
+                self.cache.cache_hover_result(contents, hover_result_id);
+                hover_result_id
+            }
+        };
+        self.emitter
+            .emit_edge(edge!(Hover, result_set_id -> hover_result_id));
+    }
+
+    /// Emits a hover result with the given contents. Markdown is emitted as a raw string, and
+    /// plain text as a `plaintext` block so that it is not rendered.
+    fn emit_hover_result(&mut self, contents: String) -> ID {
+        let marked_string = match self.opt.hover_format {
+            HoverFormat::Markdown => LSIFMarkedString {
+                language: self.opt.language.to_string(),
+                value: contents,
+                is_raw_string: true,
+            },
+            HoverFormat::Plaintext => LSIFMarkedString {
+                language: "plaintext".to_string(),
+                value: contents,
+                is_raw_string: false,
+            },
+        };
+        self.emitter.emit_vertex(HoverResult {
+            result: Contents {
+                contents: vec![marked_string],
+            },
+        })
+    }
+
+    /// Returns the moniker of the given kind of the symbol with the given name defined at the
+    /// given location. Its identifier is the path of the file relative to the project root,
+    /// or its whole path if it is outside of the project, and the name, e.g.
+    /// `src/lsp/mod.rs:new`. The identifiers of global `export` monikers are also prefixed
+    /// with the name of the project's package, so that they are unique across repositories.
+    /// `local` monikers are unique in their document.
+    fn moniker(&self, kind: &str, location: &Location, node_name: &str) -> Moniker {
+        let path = Url::parse(&location.file_path)
+            .ok()
+            .and_then(|uri| uri.to_file_path().ok());
+        let relative_path = match (&path, &self.opt.project_root) {
+            (Some(path), Some(root)) => relative_path(path, root),
+            _ => None,
+        };
+        let path = match (relative_path, &path) {
+            (Some(relative_path), _) => relative_path,
+            (None, Some(path)) => path.display().to_string(),
+            (None, None) => location.file_path.clone(),
+        };
+        let unique = match kind {
+            "local" => MonikerUnique::Document,
+            _ => self.opt.moniker_unique,
+        };
+        let identifier = match &self.package_name {
+            Some(package) if kind == "export" && unique == MonikerUnique::Global => {
+                format!("{}:{}:{}", package, path, node_name)
+            }
+            _ => format!("{}:{}", path, node_name),
+        };
+        Moniker {
+            kind: kind.to_string(),
+            scheme: "zas".to_string(),
+            identifier,
+            unique,
+        }
+    }
+
+    /// Returns the `import` moniker of a definition in a dependency, with the same identifier
+    /// as the `export` moniker in the dump of the dependency, and the `packageInformation`
+    /// vertex of the dependency. `None` for definitions in the project, which are in files
+    /// that were not indexed, and for files outside of any package.
+    fn import_moniker(
+        &mut self,
+        location: &Location,
+        node_name: &str,
+    ) -> Option<(Moniker, Option<ID>)> {
+        let path = Url::parse(&location.file_path).ok()?.to_file_path().ok()?;
+        let project_root = self.opt.project_root.as_ref()?;
+        if path.starts_with(project_root) {
+            return None;
+        }
+        let manager = self.config.package_manager?;
+        let root = path
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.join(manager.manifest()).is_file())?
+            .to_path_buf();
+        let relative_path = relative_path(&path, &root)?;
+        let package = match self.dependency_packages.get(&root) {
+            Some(package) => package.clone(),
+            None => {
+                let package = match package_information(manager, &root) {
+                    Ok(package) => package,
+                    Err(err) => {
+                        log::warn!("Not emitting the package information: {:#}", err);
+                        None
+                    }
+                };
+                let package = package.map(|package| {
+                    let name = package.name.clone();
+                    (name, self.emitter.emit_vertex(package))
+                });
+                self.dependency_packages.insert(root, package.clone());
+                package
+            }
+        };
+        let unique = self.opt.moniker_unique;
+        let identifier = match &package {
+            Some((name, _)) if unique == MonikerUnique::Global => {
+                format!("{}:{}:{}", name, relative_path, node_name)
+            }
+            _ => format!("{}:{}", relative_path, node_name),
+        };
+        let moniker = Moniker {
+            kind: "import".to_string(),
+            scheme: "zas".to_string(),
+            identifier,
+            unique,
+        };
+        Some((moniker, package.map(|(_, package_id)| package_id)))
+    }
+
+    /// Returns the identifier of the given moniker. If an equal moniker has not been
+    /// emitted, a new vertex is created and linked to the given package information.
+    /// Monikers unique in their document are never shared, since two symbols of the same
+    /// name, e.g. two locals, are different symbols.
+    fn ensure_moniker(&mut self, moniker: Moniker, package_id: Option<ID>) -> ID {
+        let cached = match moniker.unique {
+            MonikerUnique::Document => None,
+            _ => self.cache.get_moniker_id(&moniker),
+        };
+        match cached {
+            Some(moniker_id) => moniker_id,
+            None => {
+                let moniker_id = self.emitter.emit_vertex(moniker.clone());
+                if let Some(package_id) = package_id {
+                    self.emitter
+                        .emit_edge(edge!(PackageInformation, moniker_id -> package_id));
+                }
+                self.stats.monikers += 1;
+                self.cache.cache_moniker(&moniker, moniker_id);
+                moniker_id
+            }
+        }
     }
 
     /// Emits data for the given definition object and caches it for
@@ -183,81 +602,984 @@ where
             Some(it) => it,
             None => return,
         };
+        // The same declaration can be crawled more than once
+        if self.cache.get_definition_info(&def.location).is_some() {
+            return;
+        }
 
         // 1. Emit Vertices
-        let range_id = self.emitter.emit_vertex(def.range());
+        let range_id = self.emitter.emit_vertex(def.range_vertex());
         let result_set_id = self.emitter.emit_vertex(ResultSet {});
         let def_result_id = self.emitter.emit_vertex(DefinitionResult {});
-        let hover_result_id = def.comment.clone().map(|c| {
-            self.emitter.emit_vertex(HoverResult {
-                result: Contents {
-                    contents: vec![LSIFMarkedString {
-                        language: self.opt.language.to_string(),
-                        value: c,
-                        is_raw_string: true,
-                    }],
-                },
-            })
-        });
-        let moniker_id = self.emitter.emit_vertex(Moniker {
-            kind: "local".to_string(),
-            scheme: "zas".to_string(),
-            identifier: format!("{}:{}", def.location.file_name(), def.node_name.clone()),
-        });
+        let moniker_id = if def.exported || self.opt.monikers == Monikers::All {
+            let kind = if def.exported { "export" } else { "local" };
+            let moniker = self.moniker(kind, &def.location, &def.node_name);
+            let package_id = self.package_information_id.filter(|_| def.exported);
+            Some(self.ensure_moniker(moniker, package_id))
+        } else {
+            None
+        };
 
         // 2. Connect the emitted vertices
         let next_edge = edge!(Next, range_id -> result_set_id);
         let definition_edge = edge!(Definition, result_set_id -> def_result_id);
         let item_edge = Edge::item(def_result_id, vec![range_id], document_id);
-        let moniker_edge = edge!(Moniker, result_set_id -> moniker_id);
 
-        for edge in vec![next_edge, definition_edge, item_edge, moniker_edge].into_iter() {
+        for edge in vec![next_edge, definition_edge, item_edge].into_iter() {
             self.emitter.emit_edge(edge);
         }
+        if let Some(moniker_id) = moniker_id {
+            self.emitter
+                .emit_edge(edge!(Moniker, result_set_id -> moniker_id));
+        }
+
+        if let Some(comment) = def.comment.clone() {
+            self.emit_hover(result_set_id, comment);
+        }
 
-        if let Some(id) = hover_result_id {
-            self.emitter.emit_edge(edge!(Hover, result_set_id -> id));
+        if let Some(declaration) = def.declaration.clone() {
+            self.declarations.push((result_set_id, declaration));
         }
 
         // 3. Cache the result
-        self.cache
-            .cache_definition(&def, document_id, range_id, result_set_id);
+        self.stats.definitions += 1;
+        self.cache.cache_definition(
+            &def,
+            document_id,
+            range_id,
+            result_set_id,
+            def.comment.is_some(),
+        );
     }
 
     /// Emits a metadata and project vertex. This method caches the identifier of the project
     /// vertex, which is needed to construct the project/document contains relation later.
-    fn emit_metadata_and_project_vertex(&mut self) {
+    fn emit_metadata_and_project_vertex(&mut self) -> Result<()> {
+        let mut tool_info = self.tool_info.clone();
+        if self.opt.record_invocation {
+            tool_info.version = Some(env!("CARGO_PKG_VERSION").to_string());
+            tool_info.args = Some(redact_args(std::env::args().skip(1)));
+        }
         self.project_id = self.emitter.emit_vertex(MetaData {
             version: "0.1".into(),
-            position_encoding: "utf-16".into(),
-            tool_info: Some(self.tool_info.clone()),
-            project_root: Url::from_directory_path(&self.opt.project_root.clone().unwrap()).unwrap(),
+            position_encoding: self.position_encoding.as_str().into(),
+            tool_info: Some(tool_info),
+            project_root: dir_to_uri(&self.opt.project_root.clone().unwrap())?,
+            project_name: self.opt.project_name.clone(),
+            invocation: if self.opt.record_invocation {
+                Some(Box::new(self.invocation()))
+            } else {
+                None
+            },
         });
+        self.emit_package_information();
+        Ok(())
     }
 
-    fn emit_documents(&mut self) {
-        self.file_paths().iter().for_each(|filepath| {
+    /// Emits the `packageInformation` vertex of the project, if the manifest of the
+    /// language's package manager names a package.
+    fn emit_package_information(&mut self) {
+        let manager = match self.config.package_manager {
+            Some(manager) => manager,
+            None => return,
+        };
+        match package_information(manager, self.opt.project_root.as_ref().unwrap()) {
+            Ok(Some(package)) => {
+                self.package_name = Some(package.name.clone());
+                self.package_information_id = Some(self.emitter.emit_vertex(package));
+            }
+            Ok(None) => {}
+            Err(err) => log::warn!("Not emitting the package information: {:#}", err),
+        }
+    }
+
+    /// Returns how this dump is made, for `--record-invocation`.
+    fn invocation(&self) -> Invocation {
+        let attached = self.opt.attach_stdio || self.opt.attach_socket.is_some();
+        let (server_command, server_args) = match self.config.server_command(&self.opt) {
+            Ok((command, args)) if !attached => (Some(command), args),
+            _ => (None, None),
+        };
+        Invocation {
+            language: self.opt.language.clone(),
+            server_command,
+            server_args,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+        }
+    }
+
+    fn emit_documents(&mut self) -> Result<()> {
+        self.ensure_file_paths();
+        // Borrows the cached paths apart from the emitter and the cache
+        for filepath in self.cached_file_paths.iter().flatten() {
+            let contents = match self.opt.overlay.read(filepath) {
+                Ok(contents) => Some(contents),
+                Err(err) => {
+                    log::warn!(
+                        "Not emitting the content hash of `{}`: {}",
+                        filepath.display(),
+                        err
+                    );
+                    None
+                }
+            };
             let document_id = self.emitter.emit_vertex(Document {
-                uri: Url::from_file_path(&filepath).unwrap(),
-                language_id: self.opt.language.clone(),
+                uri: path_to_uri(filepath)?,
+                language_id: self.config.language_id(filepath, &self.opt.language),
+                content_hash: contents.as_deref().map(content_hash),
             });
-            self.cache.cache_document(
-                Url::from_file_path(filepath).unwrap().to_string(),
-                document_id,
-            );
+            self.cache.cache_document(file_uri(filepath), document_id);
+        }
+        Ok(())
+    }
+
+    /// Looks up the paths of all the files that have the same format as this indexer's
+    /// language into `cached_file_paths`, unless they were already.
+    fn ensure_file_paths(&mut self) {
+        if self.cached_file_paths.is_none() {
+            self.cached_file_paths = Some(paths(&self.opt, &self.config));
+        }
+    }
+}
+
+/// Runs `f` and returns its duration in milliseconds.
+fn timed(f: impl FnOnce()) -> u64 {
+    let start = Instant::now();
+    f();
+    start.elapsed().as_millis() as u64
+}
+
+/// Returns the path of `path` relative to `root`, joined with `/` on all platforms, or `None`
+/// if it is not in `root`.
+fn relative_path(path: &Path, root: &Path) -> Option<String> {
+    let relative_path = path.strip_prefix(root).ok()?;
+    Some(
+        relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Returns the hex-encoded SHA-256 hash of the given contents.
+fn content_hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::mpsc::channel};
+
+    use languageserver_types::{NumberOrString, Position, Range as LspRange};
+    use structopt::StructOpt;
+
+    use super::{content_hash, Indexer};
+    use crate::{
+        cli::Args,
+        configs::language_configs,
+        crawler::{Definition, DocumentExtras, Location, Occurrence, Range, Reference},
+        emitter::{
+            emitter::Emitter, graph_emitter::GraphEmitter,
+            instrumented_emitter::InstrumentedEmitter, memory_emitter::MemoryEmitter,
+        },
+        lsp::PositionEncoding,
+        protocol::types::{Document, Edge, Item, Moniker, MonikerUnique, Url, Vertex, ID},
+    };
+
+    fn test_indexer() -> Indexer<MemoryEmitter> {
+        indexer_with(MemoryEmitter::default())
+    }
+
+    fn indexer_with<E: Emitter>(emitter: E) -> Indexer<E> {
+        Indexer {
+            emitter: InstrumentedEmitter::new(emitter),
+            tool_info: Default::default(),
+            opt: Args::from_iter(vec!["universal-lsif", "rust"]),
+            config: language_configs()["rust"].clone(),
+            position_encoding: PositionEncoding::Utf16,
+            project_id: 0,
+            package_information_id: None,
+            package_name: None,
+            cache: Default::default(),
+            cached_file_paths: None,
+            declarations: Vec::new(),
+            imported_result_sets: Default::default(),
+            dependency_packages: Default::default(),
+            stats: Default::default(),
+        }
+    }
+
+    fn location(line: u64) -> Location {
+        Location {
+            file_path: "file:///src/main.rs".to_string(),
+            range: Range::new(LspRange::new(
+                Position::new(line, 4),
+                Position::new(line, 7),
+            )),
+        }
+    }
+
+    /// Returns a definition at `location(line)`, with no hover, symbol or declaration.
+    fn definition(line: u64, name: &str) -> Definition {
+        Definition {
+            location: location(line),
+            node_name: name.to_string(),
+            comment: None,
+            symbol: None,
+            resolved_range: None,
+            declaration: None,
+            exported: false,
+            renameable: None,
+        }
+    }
+
+    #[test]
+    fn test_monikers_are_emitted_once() {
+        let mut indexer = test_indexer();
+        let moniker = |kind: &str, identifier: &str| Moniker {
+            kind: kind.to_string(),
+            scheme: "zas".to_string(),
+            identifier: identifier.to_string(),
+            unique: MonikerUnique::Scheme,
+        };
+
+        let first = indexer.ensure_moniker(moniker("export", "main.rs:foo"), None);
+        let second = indexer.ensure_moniker(moniker("export", "main.rs:foo"), None);
+        let other_kind = indexer.ensure_moniker(moniker("import", "main.rs:foo"), None);
+
+        assert_eq!(first, second);
+        assert_ne!(first, other_kind);
+        assert_eq!(indexer.emitter.vertices.len(), 2);
+
+        // Unique in their document, so never shared
+        let local = Moniker {
+            unique: MonikerUnique::Document,
+            ..moniker("local", "main.rs:x")
+        };
+        let first = indexer.ensure_moniker(local.clone(), None);
+        assert_ne!(indexer.ensure_moniker(local, None), first);
+    }
+
+    #[test]
+    fn test_moniker_identifiers() {
+        let mut indexer = test_indexer();
+        indexer.opt = Args::from_iter(vec!["universal-lsif", "rust", "--moniker-unique", "global"]);
+        indexer.opt.project_root = Some(PathBuf::from("/zas"));
+        indexer.package_name = Some("zas-core".to_string());
+        let location = |path: &str| Location {
+            file_path: format!("file://{}", path),
+            ..location(0)
+        };
+        let identifier = |kind, path| indexer.moniker(kind, &location(path), "new").identifier;
+
+        assert_eq!(
+            identifier("export", "/zas/src/lsp/mod.rs"),
+            "zas-core:src/lsp/mod.rs:new"
+        );
+        assert_eq!(
+            identifier("local", "/zas/src/lsp/mod.rs"),
+            "src/lsp/mod.rs:new"
+        );
+        // Not the same symbol as `new` in another `mod.rs`
+        assert_ne!(
+            identifier("local", "/zas/src/lsp/mod.rs"),
+            identifier("local", "/zas/src/emitter/mod.rs")
+        );
+    }
+
+    #[test]
+    fn test_import_monikers() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_root = dir.path().join("zas");
+        let dependency = dir.path().join("registry/serde-1.0.0");
+        std::fs::create_dir_all(dependency.join("src")).unwrap();
+        std::fs::write(
+            dependency.join("Cargo.toml"),
+            "[package]\nname = \"serde\"\nversion = \"1.0.0\"\n",
+        )
+        .unwrap();
+
+        let mut indexer = test_indexer();
+        indexer.opt = Args::from_iter(vec!["universal-lsif", "rust", "--moniker-unique", "global"]);
+        indexer.opt.project_root = Some(project_root.clone());
+        let uri = |path: PathBuf| Url::from_file_path(path).unwrap().to_string();
+        let main = uri(project_root.join("src/main.rs"));
+        indexer.cache.cache_document(main.clone(), 1);
+        let reference = |line, def_path| Reference {
+            location: Location {
+                file_path: main.clone(),
+                ..location(line)
+            },
+            node_name: "Deserialize".to_string(),
+            comment: None,
+            def: Definition {
+                location: Location {
+                    file_path: uri(def_path),
+                    ..location(0)
+                },
+                ..definition(0, "Deserialize")
+            },
+        };
+
+        indexer.index_imported_reference(&reference(1, dependency.join("src/de.rs")));
+        // In the project, in a file that was not indexed, e.g. with `--sample`
+        indexer.index_imported_reference(&reference(2, project_root.join("src/lib.rs")));
+
+        let monikers: Vec<_> = indexer
+            .emitter
+            .vertices
+            .iter()
+            .filter_map(|v| match v {
+                Vertex::Moniker(moniker) => {
+                    Some((moniker.kind.as_str(), moniker.identifier.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(monikers, [("import", "serde:src/de.rs:Deserialize")]);
+        let packages: Vec<_> = indexer
+            .emitter
+            .vertices
+            .iter()
+            .filter_map(|v| match v {
+                Vertex::PackageInformation(package) => Some(package.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(packages, ["serde"]);
+        let package_edges = indexer
+            .emitter
+            .edges
+            .iter()
+            .filter(|e| matches!(e, Edge::PackageInformation(_)))
+            .count();
+        assert_eq!(package_edges, 1);
+        let document = indexer.cache.get_document(&main).unwrap();
+        assert_eq!(document.reference_range_ids.len(), 1);
+    }
+
+    #[test]
+    fn test_moniker_kinds() {
+        let definitions = |args: &[&str]| {
+            let mut indexer = test_indexer();
+            indexer.opt = Args::from_iter(args);
+            indexer
+                .cache
+                .cache_document("file:///src/main.rs".to_string(), 1);
+            for (line, exported) in [(0, true), (1, false)] {
+                indexer.index_definition(Definition {
+                    exported,
+                    ..definition(line, &format!("foo{}", line))
+                });
+            }
+            indexer
+                .emitter
+                .into_inner()
+                .vertices
+                .into_iter()
+                .filter_map(|v| match v {
+                    Vertex::Moniker(moniker) => Some((moniker.kind, moniker.unique)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            definitions(&["universal-lsif", "rust"]),
+            vec![
+                ("export".to_string(), MonikerUnique::Scheme),
+                ("local".to_string(), MonikerUnique::Document)
+            ]
+        );
+        assert_eq!(
+            definitions(&["universal-lsif", "rust", "--monikers", "exports-only"]),
+            vec![("export".to_string(), MonikerUnique::Scheme)]
+        );
+        assert_eq!(
+            definitions(&["universal-lsif", "rust", "--moniker-unique", "global"]),
+            vec![
+                ("export".to_string(), MonikerUnique::Global),
+                ("local".to_string(), MonikerUnique::Document)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_invocation() {
+        let metadata = |args: &[&str]| {
+            let mut indexer = test_indexer();
+            indexer.opt = Args::from_iter(args);
+            indexer.emit_metadata_and_project_vertex().unwrap();
+            match indexer.emitter.vertices.remove(0) {
+                Vertex::MetaData(metadata) => metadata,
+                v => panic!("Expected the metadata, found {:?}", v),
+            }
+        };
+
+        let metadata_without = metadata(&["universal-lsif", "rust", "/repo"]);
+        assert_eq!(metadata_without.invocation, None);
+        assert_eq!(metadata_without.tool_info.unwrap().version, None);
+
+        let metadata = metadata(&["universal-lsif", "rust", "/repo", "--record-invocation"]);
+        let invocation = metadata.invocation.unwrap();
+        assert_eq!(invocation.language, "rust");
+        assert_eq!(invocation.server_command.as_deref(), Some("rust-analyzer"));
+        assert_eq!(invocation.os, std::env::consts::OS);
+        assert!(invocation.timestamp > 0);
+        assert_eq!(
+            metadata.tool_info.unwrap().version.as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn test_hover_formats() {
+        let hover = |args: &[&str]| {
+            let mut indexer = test_indexer();
+            indexer.opt = Args::from_iter(args);
+            indexer.emit_hover(1, "Returns *foo*.".to_string());
+            match indexer.emitter.vertices.remove(0) {
+                Vertex::HoverResult(mut hover) => hover.result.contents.remove(0),
+                v => panic!("Expected a hover result, found {:?}", v),
+            }
+        };
+
+        let markdown = hover(&["universal-lsif", "rust"]);
+        assert!(markdown.is_raw_string);
+        assert_eq!(markdown.value, "Returns *foo*.");
+
+        let plaintext = hover(&["universal-lsif", "rust", "--hover-format", "plaintext"]);
+        assert!(!plaintext.is_raw_string);
+        assert_eq!(plaintext.language, "plaintext");
+    }
+
+    #[test]
+    fn test_export_monikers_link_the_package() {
+        let mut indexer = test_indexer();
+        indexer.package_information_id = Some(99);
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+        for (line, exported) in [(0, true), (1, false)] {
+            indexer.index_definition(Definition {
+                exported,
+                ..definition(line, &format!("foo{}", line))
+            });
+        }
+
+        let packages = indexer
+            .emitter
+            .edges
+            .iter()
+            .filter_map(|e| match e {
+                Edge::PackageInformation(data) => Some(data.in_v.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(packages, vec![NumberOrString::Number(99)]);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(
+            content_hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_reference_hover_fallback() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+
+        let def = definition(0, "foo");
+        indexer.index_definition(def.clone());
+        for line in 1..3 {
+            indexer.index_reference(&Reference {
+                location: location(line),
+                node_name: "foo".to_string(),
+                comment: Some("fn foo()".to_string()),
+                def: def.clone(),
+            });
+        }
+
+        let hovers = indexer
+            .emitter
+            .vertices
+            .iter()
+            .filter(|v| matches!(v, Vertex::HoverResult(_)))
+            .count();
+        assert_eq!(hovers, 1);
+        assert!(
+            indexer
+                .cache
+                .get_definition_info(&def.location)
+                .unwrap()
+                .has_hover
+        );
+    }
+
+    #[test]
+    fn test_identical_hovers_are_shared() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+        for (line, comment) in [(0, "i: i32"), (1, "i: i32"), (2, "j: u8")] {
+            indexer.index_definition(Definition {
+                comment: Some(comment.to_string()),
+                ..definition(line, "i")
+            });
+        }
+
+        let hovers = indexer
+            .emitter
+            .vertices
+            .iter()
+            .filter(|v| matches!(v, Vertex::HoverResult(_)))
+            .count();
+        assert_eq!(hovers, 2);
+        let hover_targets = indexer
+            .emitter
+            .edges
+            .iter()
+            .filter_map(|e| match e {
+                Edge::Hover(data) => Some(data.in_v.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(hover_targets.len(), 3);
+        assert_eq!(hover_targets[0], hover_targets[1]);
+        assert_ne!(hover_targets[0], hover_targets[2]);
+    }
+
+    #[test]
+    fn test_duplicate_definition_is_emitted_once() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+
+        let def = Definition {
+            comment: Some("fn foo()".to_string()),
+            ..definition(0, "foo")
+        };
+        indexer.index_definition(def.clone());
+        let emitted = indexer.emitter.vertices.len();
+        indexer.index_definition(def.clone());
+
+        assert_eq!(indexer.emitter.vertices.len(), emitted);
+        assert_eq!(indexer.stats.definitions, 1);
+        assert_eq!(indexer.stats.monikers, 1);
+        assert_eq!(
+            indexer
+                .cache
+                .get_document("file:///src/main.rs")
+                .unwrap()
+                .definition_range_ids
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_references_are_linked_after_all_definitions() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+
+        let def = definition(0, "foo");
+        let (occurrence_tx, occurrence_rx) = channel();
+        occurrence_tx
+            .send(Occurrence::Reference(Reference {
+                location: location(1),
+                node_name: "foo".to_string(),
+                comment: None,
+                def: def.clone(),
+            }))
+            .unwrap();
+        occurrence_tx
+            .send(Occurrence::Definition(def.clone()))
+            .unwrap();
+        occurrence_tx
+            .send(Occurrence::Reference(Reference {
+                location: location(2),
+                node_name: "bar".to_string(),
+                comment: None,
+                def: Definition {
+                    location: location(5),
+                    ..def.clone()
+                },
+            }))
+            .unwrap();
+        drop(occurrence_tx);
+
+        indexer.emit_defs_and_refs(occurrence_rx);
+
+        assert_eq!(indexer.stats.references, 1);
+        assert_eq!(indexer.stats.unresolved_references, 1);
+        let def_info = indexer.cache.get_definition_info(&def.location).unwrap();
+        assert_eq!(def_info.reference_range_ids[&1].len(), 1);
+
+        // Defined in the project, so the unresolved reference is not linked to an `import`
+        // moniker
+        let imports = indexer
+            .emitter
+            .vertices
+            .iter()
+            .filter(|v| matches!(v, Vertex::Moniker(moniker) if moniker.kind == "import"))
+            .count();
+        assert_eq!(imports, 0);
+        let document = indexer.cache.get_document("file:///src/main.rs").unwrap();
+        assert_eq!(document.reference_range_ids.len(), 1);
+    }
+
+    #[test]
+    fn test_references_in_unindexed_files_are_skipped() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+        let def = definition(0, "foo");
+        indexer.index_definition(def.clone());
+
+        let r = Reference {
+            location: Location {
+                file_path: "file:///vendor/lib.rs".to_string(),
+                ..location(1)
+            },
+            node_name: "foo".to_string(),
+            comment: None,
+            def: def.clone(),
+        };
+        let emitted = indexer.emitter.vertices.len();
+        assert!(indexer.index_reference(&r));
+        assert_eq!(indexer.emitter.vertices.len(), emitted);
+        assert_eq!(indexer.stats.references, 0);
+    }
+
+    #[test]
+    fn test_contains_edge_has_no_duplicate_ranges() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+
+        // A recursive function, whose name the server resolves to itself at the call too
+        let def = definition(0, "foo");
+        indexer.index_definition(def.clone());
+        for line in &[0, 2] {
+            indexer.index_reference(&Reference {
+                location: location(*line),
+                node_name: "foo".to_string(),
+                comment: None,
+                def: def.clone(),
+            });
+        }
+        indexer.emit_contains();
+
+        let in_vs = indexer
+            .emitter
+            .edges
+            .iter()
+            .find_map(|e| match e {
+                Edge::Contains(data) if data.out_v == NumberOrString::Number(1) => {
+                    Some(data.in_vs.clone())
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(in_vs.len(), 2);
+    }
+
+    #[test]
+    fn test_same_input_gives_the_same_output() {
+        let run = || {
+            let mut indexer = test_indexer();
+            let files = (0..20)
+                .map(|i| format!("file:///src/file{}.rs", i))
+                .collect::<Vec<_>>();
+            for (id, file) in files.iter().enumerate() {
+                indexer.cache.cache_document(file.clone(), id as ID + 1);
+            }
+            let location = |file: &String, line| Location {
+                file_path: file.clone(),
+                ..location(line)
+            };
+            for file in &files {
+                let def = Definition {
+                    location: location(file, 0),
+                    ..definition(0, "foo")
+                };
+                indexer.index_definition(def.clone());
+                for other in &files {
+                    indexer.index_reference(&Reference {
+                        location: location(other, 1),
+                        node_name: "foo".to_string(),
+                        comment: None,
+                        def: def.clone(),
+                    });
+                }
+            }
+            indexer.emit_contains();
+            indexer.link_reference_results_to_ranges();
+
+            let emitter = indexer.emitter.into_inner();
+            serde_json::to_string(&(emitter.vertices, emitter.edges)).unwrap()
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn test_declarations_are_linked_to_their_ranges() {
+        let mut indexer = test_indexer();
+        let (source, header) = ("file:///src/main.c", "file:///src/main.h");
+        indexer.cache.cache_document(source.to_string(), 1);
+        indexer.cache.cache_document(header.to_string(), 2);
+
+        let at = |file: &str, line| Location {
+            file_path: file.to_string(),
+            ..location(line)
+        };
+        // Two overloads declared at the same place, and one declared where it is defined
+        for (line, declaration) in &[
+            (0, Some(at(header, 5))),
+            (1, Some(at(header, 5))),
+            (2, None),
+        ] {
+            indexer.index_definition(Definition {
+                location: at(source, *line),
+                declaration: declaration.clone(),
+                ..definition(0, "foo")
+            });
+        }
+        indexer.emit_declarations();
+        indexer.emit_contains();
+
+        let count =
+            |f: fn(&Vertex) -> bool| indexer.emitter.vertices.iter().filter(|v| f(v)).count();
+        assert_eq!(count(|v| matches!(v, Vertex::DeclarationResult(_))), 2);
+        assert_eq!(count(|v| matches!(v, Vertex::Range(_))), 4);
+        assert_eq!(indexer.stats.declarations, 2);
+
+        let header_ranges = indexer.emitter.edges.iter().find_map(|e| match e {
+            Edge::Contains(data) if data.out_v == NumberOrString::Number(2) => {
+                Some(data.in_vs.len())
+            }
+            _ => None,
         });
+        assert_eq!(header_ranges, Some(1));
     }
 
-    /// Returns a `Vec` of of paths of all the files that have the same format as this
-    /// indexer's language.
-    fn file_paths(&mut self) -> Vec<PathBuf> {
-        if let Some(res) = &self.cached_file_paths {
-            return res.clone();
+    #[test]
+    fn test_diagnostics_are_linked_to_their_document() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+        let (extras_tx, extras_rx) = channel();
+        extras_tx
+            .send(DocumentExtras {
+                file_path: "file:///src/main.rs".to_string(),
+                diagnostics: Some(serde_json::json!([{"message": "unused variable"}])),
+                ..DocumentExtras::default()
+            })
+            .unwrap();
+        drop(extras_tx);
+        indexer.emit_document_extras(extras_rx);
+
+        let result_id = indexer.emitter.vertices.iter().position(|v| {
+            matches!(v, Vertex::DiagnosticResult(result) if result.result[0]["message"] == "unused variable")
+        });
+        assert!(result_id.is_some());
+        assert!(matches!(
+            &indexer.emitter.edges[..],
+            [Edge::Diagnostic(data)] if data.out_v == NumberOrString::Number(1)
+        ));
+    }
+
+    #[test]
+    fn test_references_to_the_whole_declaration_are_linked() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+
+        // `def foo(x)` up to its `end`, as some servers resolve `foo` to
+        let declaration = Location {
+            range: Range::new(LspRange::new(Position::new(0, 0), Position::new(3, 3))),
+            ..location(0)
+        };
+        let def = Definition {
+            resolved_range: Some(declaration.range.clone()),
+            ..definition(0, "foo")
+        };
+        let (occurrence_tx, occurrence_rx) = channel();
+        for line in 5..7 {
+            occurrence_tx
+                .send(Occurrence::Reference(Reference {
+                    location: location(line),
+                    node_name: "foo".to_string(),
+                    comment: None,
+                    def: Definition {
+                        location: declaration.clone(),
+                        ..definition(0, "foo")
+                    },
+                }))
+                .unwrap();
+        }
+        occurrence_tx
+            .send(Occurrence::Definition(def.clone()))
+            .unwrap();
+        drop(occurrence_tx);
+        indexer.emit_defs_and_refs(occurrence_rx);
+
+        assert_eq!(indexer.stats.references, 2);
+        assert_eq!(indexer.stats.unresolved_references, 0);
+        let def_info = indexer.cache.get_definition_info(&def.location).unwrap();
+        assert_eq!(def_info.reference_range_ids[&1].len(), 2);
+    }
+
+    #[test]
+    fn test_recursive_call_is_not_a_reference_at_its_definition() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+
+        // `fn foo() { foo() }`, where the name at the definition looks like a reference
+        let def = definition(0, "foo");
+        indexer.index_definition(def.clone());
+        let def_range_id = indexer
+            .cache
+            .get_definition_info(&def.location)
+            .unwrap()
+            .range_id;
+        for line in &[0, 1] {
+            assert!(indexer.index_reference(&Reference {
+                location: location(*line),
+                node_name: "foo".to_string(),
+                comment: None,
+                def: def.clone(),
+            }));
+        }
+
+        assert_eq!(indexer.stats.references, 1);
+        let def_info = indexer.cache.get_definition_info(&def.location).unwrap();
+        assert!(!def_info.reference_range_ids[&1].contains(&def_range_id));
+        let document = indexer.cache.get_document("file:///src/main.rs").unwrap();
+        assert!(!document.reference_range_ids.contains(&def_range_id));
+        let next_edges_from_def = indexer
+            .emitter
+            .edges
+            .iter()
+            .filter(|e| {
+                matches!(e, Edge::Next(data) if data.out_v == NumberOrString::Number(def_range_id))
+            })
+            .count();
+        assert_eq!(next_edges_from_def, 1);
+    }
+
+    #[test]
+    fn test_graph_answers_definition_and_references() {
+        let mut indexer = indexer_with(GraphEmitter::default());
+        let document_id = indexer.emitter.emit_vertex(Document {
+            uri: Url::parse("file:///src/main.rs").unwrap(),
+            language_id: "rust".to_string(),
+            content_hash: None,
+        });
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), document_id);
+
+        let def = definition(0, "foo");
+        indexer.index_definition(def.clone());
+        for line in 1..3 {
+            indexer.index_reference(&Reference {
+                location: location(line),
+                node_name: "foo".to_string(),
+                comment: None,
+                def: def.clone(),
+            });
+        }
+        indexer.link_reference_results_to_ranges();
+        indexer.emit_contains();
+        let graph = indexer.emitter.into_inner().into_graph();
+
+        let file = std::path::Path::new("/src/main.rs");
+        let definition = graph.definition_of(file, Position::new(2, 5)).unwrap();
+        assert_eq!(definition.uri.as_str(), "file:///src/main.rs");
+        assert_eq!(definition.range.start, Position::new(0, 4));
+        let lines = |position| {
+            graph
+                .references_to(file, position)
+                .iter()
+                .map(|location| location.range.start.line)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines(Position::new(0, 4)), vec![1, 2]);
+        assert_eq!(lines(Position::new(1, 6)), vec![1, 2]);
+        // Past the end of the range
+        assert!(graph.definition_of(file, Position::new(1, 7)).is_none());
+    }
+
+    #[test]
+    fn test_reference_result_items_in_the_same_document() {
+        let mut indexer = test_indexer();
+        indexer
+            .cache
+            .cache_document("file:///src/main.rs".to_string(), 1);
+
+        let def = definition(0, "foo");
+        indexer.index_definition(def.clone());
+        for line in 1..3 {
+            indexer.index_reference(&Reference {
+                location: location(line),
+                node_name: "foo".to_string(),
+                comment: None,
+                def: def.clone(),
+            });
         }
+        indexer.link_reference_results_to_ranges();
+
+        let def_range_id = indexer
+            .cache
+            .get_definition_info(&def.location)
+            .unwrap()
+            .range_id;
+        let reference_result_items = indexer
+            .emitter
+            .edges
+            .iter()
+            .filter_map(|e| match e {
+                Edge::Item(Item::Definition(_)) | Edge::Item(Item::Reference(_)) => {
+                    Some(serde_json::to_value(e).unwrap())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(reference_result_items.len(), 2);
 
-        let exs = self.config.extensions.clone();
-        let res = paths(&self.opt.project_root.clone().unwrap(), exs);
-        self.cached_file_paths = Some(res.clone());
-        res
+        let (definitions, references) = (&reference_result_items[0], &reference_result_items[1]);
+        assert_eq!(definitions["property"], "definitions");
+        assert_eq!(definitions["document"], 1);
+        assert_eq!(definitions["inVs"], serde_json::json!([def_range_id]));
+        assert_eq!(references["property"], "references");
+        assert_eq!(references["document"], 1);
+        let reference_range_ids = references["inVs"].as_array().unwrap();
+        assert_eq!(reference_range_ids.len(), 2);
+        assert!(!reference_range_ids.contains(&serde_json::json!(def_range_id)));
     }
 }
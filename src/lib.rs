@@ -0,0 +1,31 @@
+//! Generates LSIF dumps for any language with a language server, by asking the server for
+//! the definition of every word of every file.
+//!
+//! Besides the `universal-lsif` binary, the graph can be built in memory with
+//! `crawler::traverse_in_memory` and queried with `graph::Graph`.
+
+pub mod archive;
+pub mod check;
+pub mod checkpoint;
+pub mod cli;
+pub mod configs;
+pub mod crawler;
+pub mod dump;
+pub mod emitter;
+pub mod graph;
+pub mod indexer;
+pub mod logging;
+pub mod lsif_data_cache;
+pub mod lsp;
+pub mod overlay;
+pub mod package;
+pub mod protocol;
+pub mod resolution;
+#[cfg(feature = "tree-sitter")]
+pub mod syntax;
+#[cfg(test)]
+mod tests;
+pub mod uri;
+pub mod verify;
+
+pub use anyhow::{anyhow as error, bail as ret_error, Error, Result};
@@ -0,0 +1,99 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::Context;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::Result;
+
+/// Writes the messages of the `log` macros to stderr, or to the file given with `--log-file`.
+/// Lines are written to the file as they are logged, so that it is complete even if the run
+/// is interrupted.
+struct Logger {
+    file: Option<Mutex<LineWriter<File>>>,
+    start: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match &self.file {
+            Some(file) => {
+                let mut file = file.lock().unwrap();
+                let _ = writeln!(
+                    file,
+                    "[{:>9.3}s {:<5}] {}",
+                    self.start.elapsed().as_secs_f64(),
+                    record.level(),
+                    record.args()
+                );
+            }
+            None => match record.level() {
+                Level::Error => eprintln!("Error: {}", record.args()),
+                Level::Warn => eprintln!("Warning: {}", record.args()),
+                _ => eprintln!("{}", record.args()),
+            },
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Opens the given log file for appending, e.g. to redirect the stderr of the language
+/// server to it.
+pub fn open_log_file(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Could not open the log file `{}`", path.display()))
+}
+
+/// Sets up logging: everything down to debug messages to the given file, which is truncated,
+/// or only warnings and errors to stderr.
+pub fn init(log_file: Option<&Path>) -> Result<()> {
+    let (file, level) = match log_file {
+        Some(path) => {
+            File::create(path)
+                .with_context(|| format!("Could not create the log file `{}`", path.display()))?;
+            let file = LineWriter::new(open_log_file(path)?);
+            (Some(Mutex::new(file)), LevelFilter::Debug)
+        }
+        None => (None, LevelFilter::Warn),
+    };
+    log::set_boxed_logger(Box::new(Logger {
+        file,
+        start: Instant::now(),
+    }))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_log_file_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.log");
+        writeln!(open_log_file(&path).unwrap(), "first").unwrap();
+        writeln!(open_log_file(&path).unwrap(), "second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+    }
+}
@@ -1,18 +1,29 @@
 use std::{collections::HashMap, sync::Arc};
 
+use indexmap::IndexMap;
+
 use crate::{
     crawler::{Definition, Location, Range, Reference},
-    protocol::types::ID,
+    protocol::types::{Moniker, ID},
 };
 
+/// The maps that are iterated over to emit data keep their insertion order, so that the same
+/// input always gives the same dump.
 #[derive(Default)]
 pub struct LsifDataCache {
     /// Filename -> Info
-    documents: HashMap<String, DocumentInfo>,
+    documents: IndexMap<String, DocumentInfo>,
     /// Filename -> Range -> Range ID
     ranges: HashMap<String, HashMap<Range, ID>>,
     /// Definition Info Cache
-    def_infos: HashMap<Location, DefinitionInfo>,
+    def_infos: IndexMap<Location, DefinitionInfo>,
+    /// The range a definition resolves to, when it is not that of its name -> The location
+    /// of the definition in `def_infos`
+    resolved_ranges: HashMap<Location, Location>,
+    /// (Scheme, Identifier, Kind) -> Moniker ID
+    monikers: HashMap<(String, String, String), ID>,
+    /// Hover contents -> Hover Result ID
+    hover_results: HashMap<String, ID>,
 }
 
 /// Methods for caching and retrieving documents
@@ -58,7 +69,22 @@ impl LsifDataCache {
 
     pub fn get_definition_info(&self, location: &Location) -> Option<&DefinitionInfo> {
         //dbg!(&self.def_infos, &location);
-        self.def_infos.get(location)
+        self.def_infos.get(self.definition_location(location))
+    }
+
+    pub fn get_mut_definition_info(&mut self, location: &Location) -> Option<&mut DefinitionInfo> {
+        let location = self.definition_location(location).clone();
+        self.def_infos.get_mut(&location)
+    }
+
+    /// Returns the location of the definition that `location` resolves to, which is
+    /// `location` itself unless it is the whole declaration of a definition.
+    fn definition_location<'a>(&'a self, location: &'a Location) -> &'a Location {
+        if self.def_infos.contains_key(location) {
+            location
+        } else {
+            self.resolved_ranges.get(location).unwrap_or(location)
+        }
     }
 
     pub fn cache_definition(
@@ -67,6 +93,7 @@ impl LsifDataCache {
         document_id: ID,
         range_id: ID,
         result_set_id: ID,
+        has_hover: bool,
     ) {
         let file_ranges = self.ranges.get_mut(&def.location.file_path).unwrap();
         file_ranges.insert(def.location.range.clone(), range_id);
@@ -78,19 +105,59 @@ impl LsifDataCache {
             document_id,
             range_id,
             result_set_id,
+            has_hover,
             reference_range_ids: Default::default(),
         };
         self.def_infos
             .insert(def.location.clone(), def_info.clone());
+        if let Some(range) = &def.resolved_range {
+            let resolved = Location {
+                file_path: def.location.file_path.clone(),
+                range: range.clone(),
+            };
+            self.resolved_ranges
+                .entry(resolved)
+                .or_insert_with(|| def.location.clone());
+        }
+    }
+}
+
+/// Methods for caching and retrieving monikers
+impl LsifDataCache {
+    pub fn get_moniker_id(&self, moniker: &Moniker) -> Option<ID> {
+        self.monikers.get(&moniker_key(moniker)).copied()
+    }
+
+    pub fn cache_moniker(&mut self, moniker: &Moniker, moniker_id: ID) {
+        self.monikers.insert(moniker_key(moniker), moniker_id);
     }
 }
 
+/// Methods for caching and retrieving hover results
+impl LsifDataCache {
+    pub fn get_hover_result_id(&self, contents: &str) -> Option<ID> {
+        self.hover_results.get(contents).copied()
+    }
+
+    pub fn cache_hover_result(&mut self, contents: String, hover_result_id: ID) {
+        self.hover_results.insert(contents, hover_result_id);
+    }
+}
+
+fn moniker_key(moniker: &Moniker) -> (String, String, String) {
+    (
+        moniker.scheme.clone(),
+        moniker.identifier.clone(),
+        moniker.kind.clone(),
+    )
+}
+
 /// Methods for caching and retrieving references
 impl LsifDataCache {
     pub fn cache_reference(&mut self, def: &Definition, r: &Reference, range_id: ID) {
         {
             let id = self.get_mut_document(&def.location.file_path).unwrap().id;
-            let def_info = self.def_infos.get_mut(&def.location).unwrap();
+            let def_info = self.get_mut_definition_info(&def.location).unwrap();
             def_info.reference_range_ids.entry(id).or_default();
             let def_range_ids = def_info.reference_range_ids.get_mut(&id).unwrap();
             def_range_ids.push(range_id);
@@ -100,6 +167,17 @@ impl LsifDataCache {
         document_info.reference_range_ids.push(range_id);
     }
 
+    /// Caches the range of a declaration that is neither a definition nor a reference, so
+    /// that it is contained in its document.
+    pub fn cache_declaration_range(&mut self, location: &Location, range_id: ID) {
+        if let Some(file_ranges) = self.ranges.get_mut(&location.file_path) {
+            file_ranges.insert(location.range.clone(), range_id);
+        }
+        if let Some(document_info) = self.get_mut_document(&location.file_path) {
+            document_info.definition_range_ids.push(range_id);
+        }
+    }
+
     pub fn cache_reference_range(&mut self, r: &Reference, range_id: ID) {
         let file_ranges = match self.ranges.get_mut(&r.location.file_path) {
             Some(it) => it,
@@ -121,6 +199,8 @@ pub struct DefinitionInfo {
     pub document_id: ID,
     pub range_id: ID,
     pub result_set_id: ID,
+    /// Whether a hover result is linked to the result set
+    pub has_hover: bool,
     /// Document ID -> Range ID
-    pub reference_range_ids: HashMap<ID, Vec<ID>>,
+    pub reference_range_ids: IndexMap<ID, Vec<ID>>,
 }
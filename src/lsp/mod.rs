@@ -1,96 +1,353 @@
 use std::{
-    collections::HashSet,
-    io::{BufReader, BufWriter, Write},
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
-    sync::mpsc::{channel, Receiver},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError},
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
 use jsonrpc_lite::{Id, JsonRpc, Params};
 use languageserver_types::{
-    notification::{DidOpenTextDocument, Initialized, Notification},
-    request::GotoDefinitionResponse,
-    ClientCapabilities, DidOpenTextDocumentParams, Hover, InitializeParams, InitializeResult,
-    InitializedParams, TextDocumentItem, TextDocumentPositionParams, TraceOption, Url,
+    notification::{
+        DidCloseTextDocument, DidOpenTextDocument, Exit, Initialized, Notification,
+        PublishDiagnostics,
+    },
+    request::{
+        DocumentLinkRequest, DocumentSymbolRequest, FoldingRangeRequest, GotoDeclaration,
+        GotoDefinition, GotoDefinitionResponse, HoverRequest, References, Request, Shutdown,
+        WorkspaceSymbol,
+    },
+    ClientCapabilities, DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentLinkParams,
+    DocumentSymbolParams, DocumentSymbolResponse, FoldingRangeParams,
+    FoldingRangeProviderCapability, Hover, HoverContents, InitializeParams, InitializedParams,
+    Location, MarkedString, MarkupKind, ReferenceContext, ReferenceParams, ServerCapabilities,
+    SymbolInformation, TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams,
+    TraceOption, Url, WorkspaceClientCapabilities, WorkspaceFolder, WorkspaceSymbolParams,
 };
 use serde::{de::DeserializeOwned, Serialize};
 use serde_derive::*;
 use serde_json::{json, Value};
 
-use crate::{protocol::types::HoverResult, Result};
+use crate::{
+    cli::Args,
+    logging,
+    package::PackageManager,
+    protocol::types::HoverResult,
+    ret_error,
+    uri::{dir_to_uri, file_uri, normalize_uri, path_to_uri},
+    Error, Result,
+};
 
 use self::parse_helpers::read_message;
 
 mod parse_helpers;
 
+/// The notifications that are dropped as soon as they are read, since nothing is done with
+/// them and some servers send a lot of them. Others, such as `$/progress`, are kept.
+const IGNORED_NOTIFICATIONS: &[&str] = &["telemetry/event", "$/logTrace", "window/logMessage"];
+
+/// The keywords that can come between an export keyword and the name of the symbol it
+/// exports, e.g. `fn` in `pub fn`, or `default function` in `export default function`.
+const DECLARATION_KEYWORDS: &[&str] = &[
+    "abstract",
+    "async",
+    "class",
+    "const",
+    "declare",
+    "default",
+    "enum",
+    "extern",
+    "fn",
+    "function",
+    "interface",
+    "let",
+    "mod",
+    "namespace",
+    "static",
+    "struct",
+    "trait",
+    "type",
+    "union",
+    "unsafe",
+    "var",
+];
+
+/// The keywords that declare a symbol of a Go package.
+const GO_DECLARATION_KEYWORDS: &[&str] = &["const", "func", "type", "var"];
+
+/// The semantic token types defined by the LSP specification, which the client advertises so
+/// that servers use them in their legend.
+pub(crate) const SEMANTIC_TOKEN_TYPES: &[&str] = &[
+    "namespace",
+    "type",
+    "class",
+    "enum",
+    "interface",
+    "struct",
+    "typeParameter",
+    "parameter",
+    "variable",
+    "property",
+    "enumMember",
+    "event",
+    "function",
+    "method",
+    "macro",
+    "keyword",
+    "modifier",
+    "comment",
+    "string",
+    "number",
+    "regexp",
+    "operator",
+    "decorator",
+    "label",
+];
+
+/// The semantic token modifiers defined by the LSP specification.
+const SEMANTIC_TOKEN_MODIFIERS: &[&str] = &[
+    "declaration",
+    "definition",
+    "readonly",
+    "static",
+    "deprecated",
+    "abstract",
+    "async",
+    "modification",
+    "documentation",
+    "defaultLibrary",
+];
+
+/// How long to wait between two requests of `wait_until_ready`.
+const READY_PROBE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The error of a message that could not be written to the language server, usually
+/// because it exited.
+#[derive(Debug)]
+pub struct WriteError(pub std::io::Error);
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not write to the language server: {}", self.0)
+    }
+}
+
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
 /// A language-server client.
 pub struct LSClient {
     pub message_rx: Receiver<String>,
     writer: Box<dyn Write + Send>,
+    /// The process of the server, if it was spawned by `spawn_server`.
+    child: Option<Child>,
     next_id: u64,
+    /// The capabilities advertised by the server in its `InitializeResult`.
+    capabilities: ServerCapabilities,
+    /// Same as `capabilities`, for the capabilities that `languageserver_types` does not
+    /// know about.
+    raw_capabilities: Value,
+    /// The options the server was spawned with, kept to respawn it. `None` for clients
+    /// that were not spawned by `spawn_server`.
+    options: Option<ServerOptions>,
+    respawns: u64,
+    /// The number of times the server was shut down and spawned again by `restart`.
+    restarts: u64,
+    /// The encoding of the `character` offsets of positions, as negotiated with the server.
+    position_encoding: PositionEncoding,
+    /// Paces the definition requests, if `--max-requests-per-second` is given.
+    throttle: Option<Throttle>,
+    /// Whether to keep the diagnostics published by the server, for `wait_for_diagnostics`.
+    keep_diagnostics: bool,
+    /// The latest diagnostics published by the server for each file, keyed by `file_uri`,
+    /// if `keep_diagnostics` is set.
+    diagnostics: HashMap<String, Value>,
+    /// The file of the latest diagnostics published by the server, keyed by `file_uri`.
+    last_published: Option<String>,
+}
+
+/// How the `character` offset of a position counts the characters of a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    /// In bytes of the UTF-8 encoding.
+    Utf8,
+    /// In code units of the UTF-16 encoding, which is the default of the protocol.
+    #[default]
+    Utf16,
+}
+
+impl PositionEncoding {
+    /// Returns the name of the encoding in the protocol, e.g. `utf-16`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PositionEncoding::Utf8 => "utf-8",
+            PositionEncoding::Utf16 => "utf-16",
+        }
+    }
+
+    /// Returns the offset in this encoding of the given byte offset in `line`.
+    pub fn offset(self, line: &str, byte_offset: usize) -> u64 {
+        match self {
+            PositionEncoding::Utf8 => byte_offset as u64,
+            PositionEncoding::Utf16 => line[..byte_offset].encode_utf16().count() as u64,
+        }
+    }
+
+    /// Returns the byte offset in `line` of the given offset in this encoding, the inverse of
+    /// `offset`. Offsets past the end of the line are clamped to its length.
+    pub fn byte_offset(self, line: &str, offset: u64) -> usize {
+        match self {
+            PositionEncoding::Utf8 => (offset as usize).min(line.len()),
+            PositionEncoding::Utf16 => {
+                let mut units = 0;
+                line.char_indices()
+                    .find(|(_idx, c)| {
+                        units += c.len_utf16() as u64;
+                        units > offset
+                    })
+                    .map_or(line.len(), |(idx, _c)| idx)
+            }
+        }
+    }
+}
+
+/// How to spawn the language server.
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    pub command: String,
+    pub args: Option<String>,
+    /// If `workspace_folders` is empty, the server only receives `root_path` as its
+    /// `rootUri`.
+    pub root_path: PathBuf,
+    pub workspace_folders: Vec<PathBuf>,
+    pub env: Vec<(String, String)>,
+    pub cwd: Option<PathBuf>,
+    /// A file to append the stderr of the server to, instead of inheriting it.
+    pub stderr: Option<PathBuf>,
+    pub trace: TraceOption,
+    /// The `ClientCapabilities` to advertise instead of `default_client_capabilities`, as
+    /// JSON.
+    pub capabilities: Option<Value>,
+    /// The only hover format to advertise, if any, instead of those of the capabilities.
+    pub hover_format: Option<MarkupKind>,
+    pub initialization_options: Option<Value>,
+    /// How long to wait for the response to `initialize`, without a limit if `None`.
+    pub startup_timeout: Option<Duration>,
 }
 
 impl LSClient {
-    pub fn spawn_server(
-        start_command: String,
-        start_args: Option<String>,
-        root_path: PathBuf,
-    ) -> Result<(Self, JoinHandle<()>)> {
-        let args = start_args
-            .map(|it| {
-                let split = it.split(' ').map(|it| it.to_string()).collect::<Vec<_>>();
-                split
-            })
-            .unwrap_or(Vec::new());
-        let mut process = Command::new(start_command)
+    /// Spawns the language server and initializes it.
+    pub fn spawn_server(options: ServerOptions) -> Result<(Self, JoinHandle<()>)> {
+        let (program, args) = server_argv(&options.command, options.args.as_deref())?;
+        let mut command = Command::new(program);
+        if let Some(cwd) = &options.cwd {
+            command.current_dir(cwd);
+        }
+        if let Some(path) = &options.stderr {
+            command.stderr(logging::open_log_file(path)?);
+        }
+        log::debug!("Spawning the language server `{}`", options.command);
+        let mut process = command
             .args(args)
+            .envs(options.env.clone())
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
             .context(format!("Failed to spawn the language server with command"))?;
 
-        let mut stdout = process.stdout;
+        let stdout = process.stdout.take().unwrap();
+        let stdin = process.stdin.take().unwrap();
+        match Self::attach(stdout, stdin, &options) {
+            Ok((mut ls_client, lsp_proc)) => {
+                ls_client.options = Some(options);
+                ls_client.child = Some(process);
+                Ok((ls_client, lsp_proc))
+            }
+            Err(err) => {
+                // Rather than leaving it running in the background
+                let _ = process.kill();
+                Err(err)
+            }
+        }
+    }
 
+    /// Initializes a language server that is already running, e.g. over a socket, and that
+    /// communicates through `reader` and `writer`. The returned thread reads the messages of
+    /// the server until `reader` is closed. The server cannot be respawned.
+    pub fn attach<R, W>(
+        reader: R,
+        writer: W,
+        options: &ServerOptions,
+    ) -> Result<(Self, JoinHandle<()>)>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
         let (message_tx, message_rx) = channel();
 
         let lsp_proc = std::thread::Builder::new()
             .name("lsp-stdout-looper".into())
             .spawn(move || {
-                let mut reader = Box::new(BufReader::new(stdout.take().unwrap()));
-                loop {
-                    match read_message(&mut reader) {
-                        Ok(message_str) => {
-                            if message_tx.send(message_str).is_err() {
-                                // Receiver was dropped, end the loop
-                                break;
-                            };
-                        }
-                        Err(err) => panic!("Failed to read message: {}", err),
+                let mut reader = Box::new(BufReader::new(reader));
+                // Ends when the server exits, which the client finds out about when the
+                // channel closes
+                while let Ok(message_str) = read_message(&mut reader) {
+                    if is_ignored_notification(&message_str) {
+                        continue;
+                    }
+                    if message_tx.send(message_str).is_err() {
+                        // Receiver was dropped, end the loop
+                        break;
                     };
                 }
             })?;
 
-        let writer = Box::new(BufWriter::new(process.stdin.take().unwrap()));
+        let writer = Box::new(BufWriter::new(writer));
 
-        let mut ls_client = Self {
-            writer,
-            message_rx,
-            next_id: 0,
+        let mut ls_client = Self::new(message_rx, writer);
+        let mut capabilities: ClientCapabilities = match &options.capabilities {
+            Some(capabilities) => serde_json::from_value(capabilities.clone())
+                .context("Invalid client capabilities")?,
+            None => default_client_capabilities(),
         };
+        if let Some(format) = &options.hover_format {
+            capabilities
+                .text_document
+                .get_or_insert_with(Default::default)
+                .hover
+                .get_or_insert_with(Default::default)
+                .content_format = Some(vec![format.clone()]);
+        }
+        let mut init_params = initialize_params(
+            options.root_path.clone(),
+            options.workspace_folders.clone(),
+            options.trace.clone(),
+            capabilities,
+        )?;
+        init_params.initialization_options = options.initialization_options.clone();
 
-        let init_params = InitializeParams {
-            process_id: Some(u64::from(std::process::id())),
-            initialization_options: None,
-            capabilities: ClientCapabilities::default(),
-            trace: Some(TraceOption::Verbose),
-            workspace_folders: None,
-            root_uri: Some(Url::from_directory_path(root_path).unwrap()),
-            root_path: None,
-        };
+        let mut init_params = serde_json::to_value(init_params)?;
+        // `languageserver_types` does not know about semantic tokens
+        init_params["capabilities"]["textDocument"]["semanticTokens"] = json!({
+            "requests": {"full": true},
+            "tokenTypes": SEMANTIC_TOKEN_TYPES,
+            "tokenModifiers": SEMANTIC_TOKEN_MODIFIERS,
+            "formats": ["relative"],
+        });
+        // Nor about position encodings. UTF-8 offsets are byte offsets, which is what the
+        // crawler finds words at.
+        init_params["capabilities"]["general"] = json!({
+            "positionEncodings": ["utf-8", "utf-16"],
+        });
 
-        let rpc_params = Params::from(serde_json::to_value(init_params)?);
+        let rpc_params = Params::from(init_params);
         let request = JsonRpc::request_with_params(
             Id::Num(ls_client.next_id as i64),
             "initialize",
@@ -99,76 +356,543 @@ impl LSClient {
 
         ls_client.next_id += 1;
 
-        ls_client.send_rpc(&serde_json::to_value(&request)?);
+        ls_client.send_rpc(&serde_json::to_value(&request)?)?;
 
-        ls_client.await_response::<InitializeResult>()?;
-        ls_client.send_lsp_notification::<Initialized>(InitializedParams {});
+        let deadline = options
+            .startup_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let init_result = match ls_client.next_response_until(deadline) {
+            Ok((_id, result)) => result?,
+            Err(err) if err.is::<RecvTimeoutError>() => ret_error!(
+                "The language server did not respond to `initialize` within {} seconds",
+                options.startup_timeout.unwrap_or_default().as_secs()
+            ),
+            Err(err) => return Err(err),
+        };
+        ls_client.set_capabilities(init_result["capabilities"].clone())?;
+        ls_client.send_lsp_notification::<Initialized>(InitializedParams {})?;
 
         Ok((ls_client, lsp_proc))
     }
 
-    pub fn set_document<P: AsRef<Path>>(&mut self, path: P, text: String) {
+    /// Spawns and initializes the server again with the same options, e.g. after it
+    /// crashed. Documents need to be opened again.
+    pub fn respawn(&mut self) -> Result<()> {
+        self.spawn_again()?;
+        self.respawns += 1;
+        Ok(())
+    }
+
+    /// Shuts the server down and spawns it again, e.g. to free the memory it leaks. The
+    /// server is given `timeout` to accept the `shutdown` request and another to exit, and
+    /// is killed otherwise, before the new one is spawned. Documents need to be opened again.
+    pub fn restart(&mut self, timeout: Duration) -> Result<()> {
+        match self.shutdown(timeout) {
+            Ok(()) => self.wait_for_exit(timeout),
+            Err(err) => log::warn!("The language server did not shut down cleanly: {:#}", err),
+        }
+        self.spawn_again()?;
+        self.restarts += 1;
+        Ok(())
+    }
+
+    /// Sends the `shutdown` request, then the `exit` notification once the server accepted
+    /// it.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<()> {
+        let id = self.send_request(Shutdown::METHOD, ())?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let (resp_id, result) = self.next_response_until(Some(deadline))?;
+            if resp_id == id {
+                result?;
+                break;
+            }
+        }
+        self.send_lsp_notification::<Exit>(())
+    }
+
+    /// Waits for at most `timeout` for the spawned server to exit, e.g. after `exit`.
+    fn wait_for_exit(&mut self, timeout: Duration) {
+        let child = match &mut self.child {
+            Some(child) => child,
+            None => return,
+        };
+        let deadline = Instant::now() + timeout;
+        while let Ok(None) = child.try_wait() {
+            if Instant::now() >= deadline {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Replaces the server by a new one with the same options, keeping the state of the
+    /// client that outlives the server. The old server is killed if it is still running.
+    fn spawn_again(&mut self) -> Result<()> {
+        let options = match &self.options {
+            Some(it) => it.clone(),
+            None => ret_error!("The language server was not spawned by this client"),
+        };
+        if let Some(mut child) = self.child.take() {
+            // Fails if it already exited, which is fine
+            let _ = child.kill();
+            child
+                .wait()
+                .context("Could not wait for the language server to exit")?;
+        }
+        let (mut client, _lsp_proc) = Self::spawn_server(options)?;
+        client.next_id += self.next_id;
+        client.respawns = self.respawns;
+        client.restarts = self.restarts;
+        client.throttle = self.throttle.take();
+        client.keep_diagnostics = self.keep_diagnostics;
+        *self = client;
+        Ok(())
+    }
+
+    /// Keeps the diagnostics published by the server, to be returned by `wait_for_diagnostics`.
+    /// They are dropped otherwise, since servers publish them for every file they analyse.
+    pub fn set_keep_diagnostics(&mut self, keep: bool) {
+        self.keep_diagnostics = keep;
+    }
+
+    /// Sends at most the given number of definition requests per second, which is at least 1.
+    /// Unlimited if `None`.
+    pub fn set_max_requests_per_second(&mut self, max: Option<u64>) {
+        self.throttle = max.map(|max| Throttle::new(Duration::from_secs_f64(1.0 / max as f64)));
+    }
+
+    /// Returns the number of times the server was respawned.
+    pub fn respawns(&self) -> u64 {
+        self.respawns
+    }
+
+    /// Returns the number of times the server was restarted with `restart`.
+    pub fn restarts(&self) -> u64 {
+        self.restarts
+    }
+
+    fn new(message_rx: Receiver<String>, writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            message_rx,
+            writer,
+            child: None,
+            next_id: 0,
+            capabilities: ServerCapabilities::default(),
+            raw_capabilities: json!({}),
+            options: None,
+            respawns: 0,
+            restarts: 0,
+            position_encoding: PositionEncoding::default(),
+            throttle: None,
+            keep_diagnostics: false,
+            diagnostics: HashMap::new(),
+            last_published: None,
+        }
+    }
+
+    /// Returns a client that writes to `writer` and reads no messages, of a server with the
+    /// given capabilities, for the tests of other modules.
+    #[cfg(test)]
+    pub(crate) fn with_capabilities(writer: Box<dyn Write + Send>, capabilities: Value) -> Self {
+        let mut client = Self::new(channel().1, writer);
+        client.set_capabilities(capabilities).unwrap();
+        client
+    }
+
+    /// Sets the capabilities from the `capabilities` field of an `InitializeResult`.
+    fn set_capabilities(&mut self, capabilities: Value) -> Result<()> {
+        self.capabilities = serde_json::from_value(capabilities.clone())
+            .context("Failed to parse the server capabilities")?;
+        self.position_encoding = match capabilities["positionEncoding"].as_str() {
+            Some("utf-8") => PositionEncoding::Utf8,
+            // The server must use UTF-16 if it did not pick an encoding
+            _ => PositionEncoding::Utf16,
+        };
+        self.raw_capabilities = capabilities;
+        Ok(())
+    }
+
+    /// Returns true if the given provider is `true` or an options object in the raw
+    /// server capabilities.
+    fn has_raw_capability(&self, provider: &str) -> bool {
+        match self.raw_capabilities.get(provider) {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::Object(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the server advertised `semanticTokensProvider` with support for
+    /// requesting the tokens of a whole document.
+    pub fn supports_semantic_tokens(&self) -> bool {
+        match self.raw_capabilities["semanticTokensProvider"].get("full") {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::Object(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the server advertised `definitionProvider`.
+    pub fn supports_definition(&self) -> bool {
+        self.capabilities.definition_provider.unwrap_or(false)
+    }
+
+    /// Returns true if the server advertised `declarationProvider`.
+    pub fn supports_declaration(&self) -> bool {
+        self.has_raw_capability("declarationProvider")
+    }
+
+    /// Returns true if the server advertised `referencesProvider`.
+    pub fn supports_references(&self) -> bool {
+        self.has_raw_capability("referencesProvider")
+    }
+
+    /// Returns true if the server advertised `renameProvider` with `prepareProvider`.
+    pub fn supports_prepare_rename(&self) -> bool {
+        self.raw_capabilities["renameProvider"]["prepareProvider"].as_bool() == Some(true)
+    }
+
+    /// Returns true if the server advertised `hoverProvider`.
+    pub fn supports_hover(&self) -> bool {
+        self.capabilities.hover_provider.unwrap_or(false)
+    }
+
+    /// Returns true if the server advertised `documentSymbolProvider`.
+    pub fn supports_document_symbol(&self) -> bool {
+        self.capabilities.document_symbol_provider.unwrap_or(false)
+    }
+
+    /// Returns true if the server advertised `workspaceSymbolProvider`.
+    pub fn supports_workspace_symbol(&self) -> bool {
+        self.has_raw_capability("workspaceSymbolProvider")
+    }
+
+    /// Returns true if the server advertised `foldingRangeProvider`.
+    pub fn supports_folding_range(&self) -> bool {
+        match &self.capabilities.folding_range_provider {
+            Some(FoldingRangeProviderCapability::Simple(supported)) => *supported,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Returns true if the server advertised `documentLinkProvider`.
+    pub fn supports_document_link(&self) -> bool {
+        self.has_raw_capability("documentLinkProvider")
+    }
+
+    /// Opens the given document. The diagnostics published for it before are dropped, since
+    /// they are not those of this text.
+    pub fn set_document<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        language_id: String,
+        text: String,
+    ) -> Result<()> {
+        self.diagnostics.remove(&file_uri(path.as_ref()));
         let params = DidOpenTextDocumentParams {
             text_document: TextDocumentItem {
-                uri: Url::from_file_path(path).unwrap(),
-                language_id: String::new(),
+                uri: path_to_uri(path)?,
+                language_id,
                 version: 0,
                 text,
             },
         };
-        self.send_lsp_notification::<DidOpenTextDocument>(params);
+        self.send_lsp_notification::<DidOpenTextDocument>(params)
+    }
+
+    pub fn close_document<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let params = DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier {
+                uri: path_to_uri(path)?,
+            },
+        };
+        self.send_lsp_notification::<DidCloseTextDocument>(params)
     }
 
-    fn send_lsp_notification<N>(&mut self, params: N::Params)
+    /// Sends the given definition request until the server returns a location, for servers
+    /// that do not report when they are done indexing. The document must be open.
+    ///
+    /// Fails if the server returns an error or nothing until `timeout`, including when it
+    /// does not answer a request at all, or if it exits.
+    pub fn wait_until_ready(
+        &mut self,
+        params: TextDocumentPositionParams,
+        timeout: Duration,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let id = self.send_request(GotoDefinition::METHOD, params.clone())?;
+            match self.await_response_until::<GotoDefinitionResponse>(id, Some(deadline)) {
+                Ok(GotoDefinitionResponse::Array(locations)) if locations.is_empty() => {}
+                Ok(GotoDefinitionResponse::Link(links)) if links.is_empty() => {}
+                Ok(_) => return Ok(()),
+                Err(err) if err.is::<RecvError>() => return Err(err),
+                // Also `null` while the server is still indexing, or no response in time
+                Err(_) => {}
+            }
+            if Instant::now() >= deadline {
+                ret_error!(
+                    "The language server was not ready after {} seconds",
+                    timeout.as_secs()
+                );
+            }
+            std::thread::sleep(READY_PROBE_INTERVAL);
+        }
+    }
+
+    fn send_lsp_notification<N>(&mut self, params: N::Params) -> Result<()>
     where
         N: Notification,
         N::Params: Serialize,
     {
-        let json_params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_notification(N::METHOD, json_params);
+        let json_params = Params::from(serde_json::to_value(params)?);
+        self.send_notification(N::METHOD, json_params)
     }
 
-    fn send_notification(&mut self, method: &str, params: Params) {
+    fn send_notification(&mut self, method: &str, params: Params) -> Result<()> {
         let notification = JsonRpc::notification_with_params(method, params);
-        let res = serde_json::to_value(&notification).unwrap();
-        self.send_rpc(&res);
+        let res = serde_json::to_value(&notification)?;
+        self.send_rpc(&res)
     }
 
     pub fn get_definition(
         &mut self,
         lsp_params: TextDocumentPositionParams,
     ) -> Result<GotoDefinitionResponse> {
-        let rpc_params = Params::from(serde_json::to_value(lsp_params)?);
-        let request = JsonRpc::request_with_params(
-            Id::Num(self.next_id as i64),
-            "textDocument/definition",
-            rpc_params,
-        );
+        let id = self.send_request(GotoDefinition::METHOD, lsp_params)?;
+        self.await_response_for::<GotoDefinitionResponse>(id)
+    }
+
+    /// Sends a `textDocument/definition` request for each of the given positions, keeping
+    /// at most `concurrency_limit` requests in flight at once. Responses are correlated by
+    /// their id, so they may arrive in any order; the returned `Vec` is in the same order
+    /// as `lsp_params`.
+    pub fn get_definitions(
+        &mut self,
+        lsp_params: Vec<TextDocumentPositionParams>,
+        concurrency_limit: usize,
+    ) -> Result<Vec<Result<GotoDefinitionResponse>>> {
+        let mut results: Vec<Option<Result<GotoDefinitionResponse>>> =
+            lsp_params.iter().map(|_| None).collect();
+        // Request ID -> index into `results`
+        let mut in_flight: HashMap<u64, usize> = HashMap::new();
+        let mut pending = lsp_params.into_iter().enumerate();
+
+        loop {
+            while in_flight.len() < concurrency_limit.max(1) {
+                match pending.next() {
+                    Some((idx, params)) => {
+                        if let Some(throttle) = &mut self.throttle {
+                            throttle.wait();
+                        }
+                        let id = self.send_request(GotoDefinition::METHOD, params)?;
+                        in_flight.insert(id, idx);
+                    }
+                    None => break,
+                }
+            }
+            if in_flight.is_empty() {
+                break;
+            }
+
+            let (id, res) = self.next_response()?;
+            if let Some(idx) = in_flight.remove(&id) {
+                // A `null` result means that there is no definition, like an empty array
+                results[idx] = Some(res.and_then(|v| match v {
+                    Value::Null => Ok(GotoDefinitionResponse::Array(Vec::new())),
+                    v => serde_json::from_value(v).map_err(Into::into),
+                }));
+            }
+        }
+
+        Ok(results.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Sends a `textDocument/declaration` request. Callers should check
+    /// `supports_declaration` first.
+    pub fn get_declaration(
+        &mut self,
+        lsp_params: TextDocumentPositionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        self.send_lsp_request::<GotoDeclaration>(lsp_params)
+    }
+
+    /// Sends a `textDocument/references` request for the references to the symbol at the
+    /// given position, without its declaration. Callers should check `supports_references`
+    /// first.
+    pub fn get_references(
+        &mut self,
+        lsp_params: TextDocumentPositionParams,
+    ) -> Result<Option<Vec<Location>>> {
+        self.send_lsp_request::<References>(ReferenceParams {
+            text_document: lsp_params.text_document,
+            position: lsp_params.position,
+            context: ReferenceContext {
+                include_declaration: false,
+            },
+        })
+    }
+
+    /// Sends a `textDocument/prepareRename` request. The response is returned as is, since
+    /// `languageserver_types` does not know about `{"defaultBehavior": true}` responses.
+    /// Callers should check `supports_prepare_rename` first.
+    pub fn prepare_rename(&mut self, lsp_params: TextDocumentPositionParams) -> Result<Value> {
+        let id = self.send_request("textDocument/prepareRename", lsp_params)?;
+        self.await_response_for(id)
+    }
+
+    /// Sends a `textDocument/hover` request. Callers should check `supports_hover` first.
+    pub fn get_hover(&mut self, lsp_params: TextDocumentPositionParams) -> Result<Option<Hover>> {
+        self.send_lsp_request::<HoverRequest>(lsp_params)
+    }
+
+    /// Sends a `textDocument/documentSymbol` request. Callers should check
+    /// `supports_document_symbol` first.
+    pub fn get_document_symbols<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        self.send_lsp_request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: text_document_identifier(path)?,
+        })
+    }
+
+    /// Sends a `workspace/symbol` request for the symbols of the whole workspace that match
+    /// `query`. Callers should check `supports_workspace_symbol` first.
+    pub fn workspace_symbols(&mut self, query: &str) -> Result<Vec<SymbolInformation>> {
+        let symbols = self.send_lsp_request::<WorkspaceSymbol>(WorkspaceSymbolParams {
+            query: query.to_string(),
+        })?;
+        Ok(symbols.unwrap_or_default())
+    }
+
+    /// Sends a `textDocument/foldingRange` request and returns the raw result. Callers
+    /// should check `supports_folding_range` first.
+    pub fn get_folding_ranges<P: AsRef<Path>>(&mut self, path: P) -> Result<Value> {
+        let id = self.send_request(
+            FoldingRangeRequest::METHOD,
+            FoldingRangeParams {
+                text_document: text_document_identifier(path)?,
+            },
+        )?;
+        self.await_response_for(id)
+    }
+
+    /// Sends a `textDocument/documentLink` request and returns the raw result. Callers
+    /// should check `supports_document_link` first.
+    pub fn get_document_links<P: AsRef<Path>>(&mut self, path: P) -> Result<Value> {
+        let id = self.send_request(
+            DocumentLinkRequest::METHOD,
+            DocumentLinkParams {
+                text_document: text_document_identifier(path)?,
+            },
+        )?;
+        self.await_response_for(id)
+    }
+
+    /// Sends a `textDocument/semanticTokens/full` request and returns the decoded tokens.
+    /// Callers should check `supports_semantic_tokens` first.
+    pub fn get_semantic_tokens<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<SemanticToken>> {
+        let id = self.send_request(
+            "textDocument/semanticTokens/full",
+            json!({ "textDocument": text_document_identifier(path)? }),
+        )?;
+        let result: Value = self.await_response_for(id)?;
+        let data: Vec<u64> = match result.get("data") {
+            Some(data) => serde_json::from_value(data.clone())?,
+            None => Vec::new(),
+        };
+        let legend: Vec<String> = serde_json::from_value(
+            self.raw_capabilities["semanticTokensProvider"]["legend"]["tokenTypes"].clone(),
+        )
+        .unwrap_or_default();
+        Ok(decode_semantic_tokens(&data, &legend))
+    }
+
+    fn send_lsp_request<R>(&mut self, params: R::Params) -> Result<R::Result>
+    where
+        R: Request,
+        R::Params: Serialize,
+        R::Result: DeserializeOwned,
+    {
+        let id = self.send_request(R::METHOD, params)?;
+        self.await_response_for::<R::Result>(id)
+    }
+
+    /// Returns the position encoding negotiated with the server.
+    pub fn position_encoding(&self) -> PositionEncoding {
+        self.position_encoding
+    }
+
+    /// Returns the number of requests sent to the server so far.
+    pub fn requests_sent(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Sends a request without waiting for its response and returns the request's id.
+    fn send_request<P: Serialize>(&mut self, method: &str, params: P) -> Result<u64> {
+        let id = self.next_id;
+        let rpc_params = Params::from(serde_json::to_value(params)?);
+        let request = JsonRpc::request_with_params(Id::Num(id as i64), method, rpc_params);
 
         self.next_id += 1;
 
-        self.send_rpc(&serde_json::to_value(&request)?);
+        self.send_rpc(&serde_json::to_value(&request)?)?;
 
-        let resp = self.await_response::<GotoDefinitionResponse>()?;
-        Ok(resp)
+        Ok(id)
     }
 
-    fn await_response<T: DeserializeOwned>(&mut self) -> Result<T> {
-        let result;
+    /// Waits for the response to the request with the given id, dropping responses to
+    /// any other request.
+    fn await_response_for<T: DeserializeOwned>(&mut self, id: u64) -> Result<T> {
+        self.await_response_until(id, None)
+    }
+
+    /// Same as `await_response_for`, but fails with `RecvTimeoutError::Timeout` if the
+    /// response is not received before `deadline`, if given.
+    fn await_response_until<T: DeserializeOwned>(
+        &mut self,
+        id: u64,
+        deadline: Option<Instant>,
+    ) -> Result<T> {
         loop {
-            let message = self.message_rx.recv()?;
-            if let Some((_id, res)) = self.handle_message(&message) {
-                result = Some(res.with_context(|| {
-                    format!("Language server failed with message: `{}`", message)
-                })?);
-                break;
-            } else {
-                //dbg!(message);
+            let (resp_id, result) = self.next_response_until(deadline)?;
+            if resp_id == id {
+                let resp: T = serde_json::from_value(result?)?;
+                return Ok(resp);
+            }
+        }
+    }
+
+    /// Blocks until a response (as opposed to a request or a notification) is received
+    /// from the server. The outer `Result` fails only if the server is gone.
+    fn next_response(&mut self) -> Result<(u64, Result<Value>)> {
+        self.next_response_until(None)
+    }
+
+    /// Same as `next_response`, but fails with `RecvTimeoutError::Timeout` if no response is
+    /// received before `deadline`, if given.
+    fn next_response_until(&mut self, deadline: Option<Instant>) -> Result<(u64, Result<Value>)> {
+        loop {
+            let message = match deadline {
+                Some(deadline) => match self
+                    .message_rx
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => return Err(RecvTimeoutError::Timeout.into()),
+                    Err(RecvTimeoutError::Disconnected) => return Err(RecvError.into()),
+                },
+                None => self.message_rx.recv()?,
+            };
+            if let Some((id, res)) = self.handle_message(&message) {
+                let res = res
+                    .with_context(|| format!("Language server failed with message: `{}`", message));
+                return Ok((id, res));
             }
         }
-        let result = result.unwrap();
-        let resp: T = serde_json::from_value(result)?;
-        Ok(resp)
     }
 
     fn handle_message(
@@ -181,41 +905,115 @@ impl LSClient {
                 return None;
             }
             Ok(value @ JsonRpc::Notification(_)) => {
-                //dbg!(value);
+                if value.get_method() == Some(PublishDiagnostics::METHOD) {
+                    self.store_diagnostics(message);
+                }
                 return None;
             }
-            Ok(value @ JsonRpc::Success(_)) => {
-                let id = number_from_id(&value.get_id().unwrap());
-                let result = value.get_result().unwrap();
-                return Some((id, Ok(result.clone())));
+            Ok(value @ JsonRpc::Success(_)) | Ok(value @ JsonRpc::Error(_)) => {
+                // E.g. `null` for errors about messages whose id could not be read
+                let id = match value.get_id().as_ref().and_then(number_from_id) {
+                    Some(id) => id,
+                    None => {
+                        log::warn!(
+                            "Ignoring a response of the language server to no request: {}",
+                            message
+                        );
+                        return None;
+                    }
+                };
+                return match (value.get_result(), value.get_error()) {
+                    (_, Some(error)) => Some((id, Err(error.clone()))),
+                    (result, None) => Some((id, Ok(result.cloned().unwrap_or(Value::Null)))),
+                };
             }
-            Ok(value @ JsonRpc::Error(_)) => {
-                let id = number_from_id(&value.get_id().unwrap());
-                let error = value.get_error().unwrap();
-                return Some((id, Err(error.clone())));
-            }
-            Err(err) => panic!("Error in parsing incoming string: {}", err),
+            // Some servers print e.g. banners to stdout, which is no reason to stop
+            Err(err) => log::warn!(
+                "Ignoring a message of the language server that is not JSON-RPC ({}): {}",
+                err,
+                message
+            ),
         }
         None
     }
 
-    fn send_rpc(&mut self, value: &Value) {
-        let rpc = match prepare_lsp_json(value) {
-            Ok(r) => r,
-            Err(err) => panic!("Encoding Error {:?}", err),
+    /// Keeps the diagnostics of a `textDocument/publishDiagnostics` notification if
+    /// `keep_diagnostics` is set, replacing the ones previously published for the same file.
+    fn store_diagnostics(&mut self, message: &str) {
+        let params = match serde_json::from_str::<Value>(message) {
+            Ok(mut value) => value["params"].take(),
+            Err(_) => return,
+        };
+        let uri = match params["uri"].as_str().map(Url::parse) {
+            Some(Ok(uri)) => uri,
+            _ => return,
         };
+        let key = normalize_uri(&uri);
+        if self.keep_diagnostics {
+            self.diagnostics
+                .insert(key.clone(), params["diagnostics"].clone());
+        }
+        self.last_published = Some(key);
+    }
 
-        self.write(rpc.as_ref());
+    /// Waits until the server has not published diagnostics for the given file for `settle`,
+    /// then returns the latest ones, if any. Servers publish diagnostics whenever they are
+    /// done analysing a file, which can be well after it was opened.
+    ///
+    /// The responses received meanwhile are dropped, so no request must be pending.
+    pub fn wait_for_diagnostics(&mut self, path: &Path, settle: Duration) -> Option<Value> {
+        let key = file_uri(path);
+        let mut deadline = Instant::now() + settle;
+        while let Ok(message) = self
+            .message_rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            let published = self.diagnostics.get(&key).cloned();
+            self.handle_message(&message);
+            if self.diagnostics.get(&key) != published.as_ref() {
+                deadline = Instant::now() + settle;
+            }
+        }
+        self.diagnostics.remove(&key)
     }
 
-    fn write(&mut self, message: &str) {
-        self.writer.write_all(message.as_bytes()).expect(
-            "error writing to stdin for language server,
-        ",
-        );
+    /// Waits until the server has published diagnostics for the given file, e.g. after it
+    /// was opened, for at most `timeout`. Returns false if none were published in time.
+    ///
+    /// The responses received meanwhile are dropped, so no request must be pending.
+    pub fn wait_until_diagnostics_published(&mut self, path: &Path, timeout: Duration) -> bool {
+        let key = file_uri(path);
+        if self.diagnostics.contains_key(&key) {
+            return true;
+        }
+        let deadline = Instant::now() + timeout;
+        self.last_published = None;
+        while self.last_published.as_ref() != Some(&key) {
+            match self
+                .message_rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(message) => {
+                    self.handle_message(&message);
+                }
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    fn send_rpc(&mut self, value: &Value) -> Result<()> {
+        let rpc = prepare_lsp_json(value)?;
+        self.write(rpc.as_ref())
+    }
+
+    /// Writes the given message to the server. Fails with a `WriteError` if the server is
+    /// gone, e.g. with a broken pipe once it exited.
+    fn write(&mut self, message: &str) -> Result<()> {
         self.writer
-            .flush()
-            .expect("error flushing child stdin for language server")
+            .write_all(message.as_bytes())
+            .and_then(|()| self.writer.flush())
+            .map_err(|err| WriteError(err).into())
     }
 }
 
@@ -230,17 +1028,1125 @@ fn prepare_lsp_json(msg: &Value) -> Result<String, serde_json::error::Error> {
     ))
 }
 
+/// Spaces out events by at least a fixed interval, sleeping if they come in faster.
+struct Throttle {
+    interval: Duration,
+    next: Instant,
+}
+
+impl Throttle {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Instant::now(),
+        }
+    }
+
+    /// Sleeps until an interval has passed since the previous call.
+    fn wait(&mut self) {
+        let now = Instant::now();
+        if self.next > now {
+            std::thread::sleep(self.next - now);
+        }
+        self.next = self.next.max(now) + self.interval;
+    }
+}
+
+/// A token from a `textDocument/semanticTokens` response, with an absolute position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    pub line: u64,
+    pub start: u64,
+    pub length: u64,
+    /// The token type from the server's legend, e.g. `function` or `comment`.
+    pub token_type: String,
+}
+
+/// Decodes the relative `data` of a semantic tokens response, five integers per token,
+/// using the token types of the server's legend.
+fn decode_semantic_tokens(data: &[u64], legend: &[String]) -> Vec<SemanticToken> {
+    let (mut line, mut start) = (0, 0);
+    data.chunks_exact(5)
+        .map(|token| {
+            if token[0] > 0 {
+                line += token[0];
+                start = token[1];
+            } else {
+                start += token[1];
+            }
+            SemanticToken {
+                line,
+                start,
+                length: token[2],
+                token_type: legend.get(token[3] as usize).cloned().unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+fn text_document_identifier<P: AsRef<Path>>(path: P) -> Result<TextDocumentIdentifier> {
+    Ok(TextDocumentIdentifier {
+        uri: path_to_uri(path)?,
+    })
+}
+
+/// Splits the server command and arguments into the program and its arguments, the way a
+/// shell would. The command may contain arguments of its own, which come first.
+fn server_argv(command: &str, args: Option<&str>) -> Result<(String, Vec<String>)> {
+    let mut argv = shell_words::split(command)
+        .with_context(|| format!("Could not parse the server command `{}`", command))?;
+    if let Some(args) = args {
+        argv.extend(
+            shell_words::split(args)
+                .with_context(|| format!("Could not parse the server arguments `{}`", args))?,
+        );
+    }
+    if argv.is_empty() {
+        ret_error!("The server command is empty");
+    }
+    let program = argv.remove(0);
+    Ok((program, argv))
+}
+
+/// Returns whether a name after `line_before` is declared at the top level of a Go file,
+/// after a declaration keyword at the start of the line, or after the receiver of a method.
+fn is_top_level_declaration(line_before: &str) -> bool {
+    if line_before.starts_with(char::is_whitespace) {
+        return false;
+    }
+    let mut before = line_before.trim_end();
+    if let Some(receiver) = before.strip_suffix(')').and_then(|b| b.rfind('(')) {
+        before = before[..receiver].trim_end();
+    }
+    before.split_whitespace().count() == 1 && GO_DECLARATION_KEYWORDS.contains(&before)
+}
+
+/// Returns true if the given message is a notification in `IGNORED_NOTIFICATIONS`. Requests
+/// from the server have an `id` and are never ignored.
+fn is_ignored_notification(message: &str) -> bool {
+    #[derive(Deserialize)]
+    struct Header {
+        id: Option<Value>,
+        method: Option<String>,
+    }
+    match serde_json::from_str::<Header>(message) {
+        Ok(Header {
+            id: None,
+            method: Some(method),
+        }) => IGNORED_NOTIFICATIONS.contains(&method.as_str()),
+        _ => false,
+    }
+}
+
+/// Returns true if the given error is an error response of the server, as opposed to e.g. a
+/// result that could not be parsed.
+pub fn is_server_error(err: &Error) -> bool {
+    err.is::<jsonrpc_lite::Error>()
+}
+
+/// Returns the capabilities advertised when `--capabilities-file` is not given. Servers
+/// return less with the empty defaults, e.g. plain text hovers and flat document symbols.
+pub fn default_client_capabilities() -> ClientCapabilities {
+    let symbol_kinds = (1..=26).collect::<Vec<_>>();
+    serde_json::from_value(json!({
+        "textDocument": {
+            "hover": {"contentFormat": ["markdown", "plaintext"]},
+            "rename": {"prepareSupport": true},
+            "documentSymbol": {
+                "symbolKind": {"valueSet": symbol_kinds},
+                "hierarchicalDocumentSymbolSupport": true,
+            },
+            "definition": {"dynamicRegistration": false},
+            "declaration": {"dynamicRegistration": false},
+            "references": {"dynamicRegistration": false},
+            "foldingRange": {"lineFoldingOnly": false},
+            "documentLink": {"dynamicRegistration": false},
+        },
+        "workspace": {
+            "symbol": {"symbolKind": {"valueSet": symbol_kinds}},
+        },
+    }))
+    .expect("the default client capabilities are valid")
+}
+
+/// Returns the parameters of the `initialize` request.
+fn initialize_params(
+    root_path: PathBuf,
+    workspace_folders: Vec<PathBuf>,
+    trace: TraceOption,
+    mut capabilities: ClientCapabilities,
+) -> Result<InitializeParams> {
+    let workspace_folders = workspace_folders
+        .into_iter()
+        .map(|path| {
+            Ok(WorkspaceFolder {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                uri: dir_to_uri(path)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    capabilities
+        .workspace
+        .get_or_insert_with(WorkspaceClientCapabilities::default)
+        .workspace_folders = Some(!workspace_folders.is_empty());
+
+    Ok(InitializeParams {
+        process_id: Some(u64::from(std::process::id())),
+        initialization_options: None,
+        capabilities,
+        trace: Some(trace),
+        workspace_folders: if workspace_folders.is_empty() {
+            None
+        } else {
+            Some(workspace_folders)
+        },
+        root_uri: Some(dir_to_uri(root_path)?),
+        root_path: None,
+    })
+}
+
+/// Flattens the contents of a hover response into a single string.
+pub fn hover_to_string(hover: Hover) -> String {
+    fn marked_string_value(s: MarkedString) -> String {
+        match s {
+            MarkedString::String(s) => s,
+            MarkedString::LanguageString(s) => s.value,
+        }
+    }
+
+    match hover.contents {
+        HoverContents::Scalar(s) => marked_string_value(s),
+        HoverContents::Array(v) => v
+            .into_iter()
+            .map(marked_string_value)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        HoverContents::Markup(m) => m.value,
+    }
+}
+
 /// Configuration info for running a language server.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LSConfig {
     pub extensions: Vec<String>,
+    /// The extensions that are of this language rather than of the other languages that
+    /// have them too, e.g. `h` for C++ over C. See `configs::extension_languages`.
+    #[serde(default)]
+    pub preferred_extensions: Vec<String>,
     pub keywords: HashSet<String>,
+    /// The command used when `--server-command` is not given.
+    #[serde(default)]
+    pub default_server_command: Option<String>,
+    /// The arguments used when `--server-args` is not given.
+    #[serde(default)]
+    pub default_server_args: Option<String>,
+    /// The language ids of the extensions whose files are not in the language itself, e.g.
+    /// `tsx = "typescriptreact"`.
+    #[serde(default)]
+    pub language_ids: HashMap<String, String>,
+    /// The tree-sitter grammar used to find identifiers if the `tree-sitter` feature is
+    /// enabled, e.g. `rust`.
+    #[serde(default)]
+    pub grammar: Option<String>,
+    /// Whether to query the member of a member access (`c` in `a.b.c()` or `a::b::c`) again
+    /// at its end, next to the call, when nothing is found at its start. Some servers only
+    /// resolve methods there.
+    #[serde(default)]
+    pub requery_member_access: bool,
+    /// Named sets of `initializationOptions` for the default server, selected with
+    /// `--preset`.
+    #[serde(default)]
+    pub presets: HashMap<String, Value>,
+    /// Whether the symbols whose name starts with an uppercase letter are exported, as in Go.
+    #[serde(default)]
+    pub export_capitalized: bool,
+    /// The keyword that exports a symbol when it comes before its name on the same line,
+    /// e.g. `pub`. Restricted forms such as `pub(crate)` do not count.
+    #[serde(default)]
+    pub export_keyword: Option<String>,
+    /// The interpreters whose scripts are of this language, e.g. `python` for
+    /// `#!/usr/bin/env python3`. Only used with `--detect-shebangs`.
+    #[serde(default)]
+    pub shebangs: Vec<String>,
+    /// The package manager whose manifest at the project root names the package of the
+    /// exported symbols, e.g. `cargo`.
+    #[serde(default)]
+    pub package_manager: Option<PackageManager>,
+    /// The number of symbols at which the default server cuts a `workspace/symbol` response,
+    /// e.g. 128 for rust-analyzer. Used when `--workspace-symbol-limit` is not given.
+    #[serde(default)]
+    pub workspace_symbol_limit: Option<usize>,
 }
 
-fn number_from_id(id: &Id) -> u64 {
+impl LSConfig {
+    /// Returns the language id of the given file: the one of its extension, or `language`.
+    pub fn language_id(&self, path: &Path, language: &str) -> String {
+        path.extension()
+            .and_then(|ext| self.language_ids.get(ext.to_string_lossy().as_ref()))
+            .map_or(language, String::as_str)
+            .to_string()
+    }
+
+    /// Restricts the extensions of the language to the given ones, if any are given, and
+    /// returns those of the given extensions that are not extensions of the language.
+    pub fn restrict_extensions(&mut self, only: &[String]) -> Vec<String> {
+        if only.is_empty() {
+            return Vec::new();
+        }
+        let only = only
+            .iter()
+            .map(|ext| ext.trim_start_matches('.'))
+            .collect::<Vec<_>>();
+        let unknown = only
+            .iter()
+            .filter(|ext| !self.extensions.iter().any(|e| e == *ext))
+            .map(|ext| ext.to_string())
+            .collect();
+        self.extensions.retain(|ext| only.contains(&ext.as_str()));
+        unknown
+    }
+
+    /// Returns the command and arguments for starting the language server. The command-line
+    /// arguments take precedence over the defaults of the language.
+    pub fn server_command(&self, args: &Args) -> Result<(String, Option<String>)> {
+        let command = args
+            .init_server_command
+            .clone()
+            .or_else(|| self.default_server_command.clone())
+            .with_context(|| {
+                format!(
+                    "No server command was given and `{}` has no default command",
+                    args.language
+                )
+            })?;
+        let server_args = args
+            .server_args
+            .clone()
+            .or_else(|| self.default_server_args.clone());
+        Ok((command, server_args))
+    }
+
+    /// Returns whether the symbol with the given name is exported, given the part of its line
+    /// that comes before the name. Only the words right before the name count, so that the
+    /// parameters and locals on the line of an exported function are not exported.
+    ///
+    /// With `export_keyword`, the name follows the keyword and declaration keywords only,
+    /// e.g. `pub fn` or `pub` for a field. With `export_capitalized`, the name is declared at
+    /// the top level, e.g. `func Serve` or `func (s *Server) Serve`; the capitalized symbols
+    /// of indented `const` or `var` blocks and struct fields are not told apart from locals,
+    /// so they are not exported.
+    pub fn is_exported(&self, line_before: &str, name: &str) -> bool {
+        if self.export_capitalized
+            && name.starts_with(char::is_uppercase)
+            && is_top_level_declaration(line_before)
+        {
+            return true;
+        }
+        let keyword = match &self.export_keyword {
+            Some(keyword) => keyword,
+            None => return false,
+        };
+        let mut words = line_before.split_whitespace().rev().peekable();
+        match words.peek() {
+            Some(word) if word == keyword => return true,
+            Some(word) if DECLARATION_KEYWORDS.contains(word) => {}
+            _ => return false,
+        }
+        words
+            .find(|word| !DECLARATION_KEYWORDS.contains(word))
+            .is_some_and(|word| word == keyword)
+    }
+
+    /// Returns whether the given first line of a file is a shebang running one of the
+    /// interpreters of the language. A version after the interpreter is ignored, so that
+    /// `python3.11` is `python`.
+    pub fn matches_shebang(&self, first_line: &str) -> bool {
+        let mut words = match first_line.strip_prefix("#!") {
+            Some(command) => command.split_whitespace(),
+            None => return false,
+        };
+        let mut program = words.next().unwrap_or_default();
+        if program.rsplit('/').next() == Some("env") {
+            program = words
+                .find(|word| !word.starts_with('-'))
+                .unwrap_or_default();
+        }
+        let name = program
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        self.shebangs.iter().any(|shebang| shebang == name)
+    }
+
+    /// Returns the `initializationOptions` for the language server: those of `--preset`, with
+    /// `--initialization-options` merged over them.
+    pub fn initialization_options(&self, args: &Args) -> Result<Option<Value>> {
+        let preset = match &args.preset {
+            Some(name) => match self.presets.get(name) {
+                Some(preset) => Some(preset.clone()),
+                None => {
+                    let mut names = self.presets.keys().map(String::as_str).collect::<Vec<_>>();
+                    names.sort_unstable();
+                    ret_error!(
+                        "`{}` has no preset `{}`, expected one of: {}",
+                        args.language,
+                        name,
+                        names.join(", ")
+                    )
+                }
+            },
+            None => None,
+        };
+        Ok(match (preset, args.initialization_options.clone()) {
+            (Some(mut preset), Some(options)) => {
+                merge_json(&mut preset, options);
+                Some(preset)
+            }
+            (preset, options) => options.or(preset),
+        })
+    }
+}
+
+/// Merges `overlay` into `base`: objects are merged key by key, and any other value of
+/// `overlay` replaces the one in `base`.
+fn merge_json(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_json(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Returns the id of a response, or `None` if it is `null` or not one of the numeric ids of
+/// the requests.
+fn number_from_id(id: &Id) -> Option<u64> {
     match *id {
-        Id::Num(n) => n as u64,
-        Id::Str(ref s) => u64::from_str_radix(s, 10).expect("failed to convert string id to u64"),
-        _ => panic!("unexpected value for id: None"),
+        Id::Num(n) => Some(n as u64),
+        Id::Str(ref s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufReader, Write},
+        net::{TcpListener, TcpStream},
+        path::Path,
+        sync::mpsc::channel,
+        time::{Duration, Instant},
+    };
+
+    use languageserver_types::{
+        request::GotoDefinitionResponse, ClientCapabilities, MarkupKind, Position,
+        TextDocumentIdentifier, TextDocumentPositionParams, TraceOption, Url,
+    };
+    use serde_json::{json, Value};
+
+    use super::{
+        decode_semantic_tokens, default_client_capabilities, initialize_params,
+        is_ignored_notification, is_server_error, prepare_lsp_json, read_message, server_argv,
+        LSClient, PositionEncoding, SemanticToken, ServerOptions, Throttle, WriteError,
+    };
+
+    /// A writer to a server that exited, which fails like a closed pipe.
+    struct ClosedWriter;
+
+    impl Write for ClosedWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The options of a fake server that answers `initialize`, then reads its stdin until
+    /// it is closed.
+    #[cfg(unix)]
+    fn fake_server_options() -> ServerOptions {
+        let response =
+            r#"{"jsonrpc":"2.0","id":0,"result":{"capabilities":{"definitionProvider":true}}}"#;
+        let script = format!(
+            "printf 'Content-Length: {}\\r\\n\\r\\n%s' '{}'; cat > /dev/null",
+            response.len(),
+            response
+        );
+        ServerOptions {
+            command: "sh".into(),
+            args: Some(shell_words::join(["-c", &script])),
+            root_path: "/repo".into(),
+            startup_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether the process with the given id is still running.
+    #[cfg(unix)]
+    fn is_running(pid: u32) -> bool {
+        std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap()
+            .success()
+    }
+
+    #[test]
+    fn test_read_message_until_eof() {
+        let mut reader = std::io::Cursor::new("Content-Length: 2\r\n\r\n{}");
+        assert_eq!(read_message(&mut reader).unwrap(), "{}");
+        assert!(read_message(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_decode_semantic_tokens() {
+        let legend = vec!["keyword".to_string(), "function".to_string()];
+        let data = [1, 0, 2, 0, 0, 0, 3, 4, 1, 0, 2, 4, 1, 1, 0];
+        let token = |line, start, length, token_type: &str| SemanticToken {
+            line,
+            start,
+            length,
+            token_type: token_type.to_string(),
+        };
+        assert_eq!(
+            decode_semantic_tokens(&data, &legend),
+            vec![
+                token(1, 0, 2, "keyword"),
+                token(1, 3, 4, "function"),
+                token(3, 4, 1, "function"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_server_argv() {
+        let (program, args) = server_argv(
+            "dart language-server",
+            Some(r#"--log "/tmp/my logs/dart.log" --flag="a b" 'c d'"#),
+        )
+        .unwrap();
+        assert_eq!(program, "dart");
+        assert_eq!(
+            args,
+            vec![
+                "language-server",
+                "--log",
+                "/tmp/my logs/dart.log",
+                "--flag=a b",
+                "c d"
+            ]
+        );
+
+        assert!(server_argv("", None).is_err());
+        assert!(server_argv("gopls", Some("\"unterminated")).is_err());
+    }
+
+    #[test]
+    fn test_initialize_params_workspace_folders() {
+        let params = initialize_params(
+            "/repo".into(),
+            vec![],
+            TraceOption::Off,
+            ClientCapabilities::default(),
+        )
+        .unwrap();
+        assert_eq!(params.workspace_folders, None);
+        assert_eq!(params.root_uri.unwrap().as_str(), "file:///repo/");
+
+        let params = initialize_params(
+            "/repo".into(),
+            vec!["/repo/web".into(), "/repo/server".into()],
+            TraceOption::Off,
+            default_client_capabilities(),
+        )
+        .unwrap();
+        let folders = params.workspace_folders.unwrap();
+        assert_eq!(folders[1].name, "server");
+        assert_eq!(folders[1].uri.as_str(), "file:///repo/server/");
+        assert_eq!(
+            params.capabilities.workspace.unwrap().workspace_folders,
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_default_client_capabilities() {
+        let capabilities = default_client_capabilities().text_document.unwrap();
+        let document_symbol = capabilities.document_symbol.unwrap();
+        assert_eq!(
+            document_symbol.hierarchical_document_symbol_support,
+            Some(true)
+        );
+        assert_eq!(
+            document_symbol
+                .symbol_kind
+                .unwrap()
+                .value_set
+                .unwrap()
+                .len(),
+            26
+        );
+        assert_eq!(
+            capabilities.hover.unwrap().content_format.unwrap()[0],
+            MarkupKind::Markdown
+        );
+    }
+
+    #[test]
+    fn test_byte_offset() {
+        let line = "é𝄞x";
+        for encoding in &[PositionEncoding::Utf8, PositionEncoding::Utf16] {
+            for byte_offset in &[0, 2, 6, 7] {
+                let offset = encoding.offset(line, *byte_offset);
+                assert_eq!(encoding.byte_offset(line, offset), *byte_offset);
+            }
+        }
+        assert_eq!(PositionEncoding::Utf16.byte_offset(line, 10), line.len());
+    }
+
+    fn client_from_init_result(json: &str) -> LSClient {
+        let init_result: Value = serde_json::from_str(json).unwrap();
+        let mut client = LSClient::new(channel().1, Box::new(std::io::sink()));
+        client
+            .set_capabilities(init_result["capabilities"].clone())
+            .unwrap();
+        client
+    }
+
+    #[test]
+    fn test_capabilities_from_initialize_result() {
+        let client = client_from_init_result(
+            r#"{"capabilities": {"definitionProvider": true, "referencesProvider": false}}"#,
+        );
+
+        assert!(client.supports_definition());
+        assert!(!client.supports_references());
+        assert!(!client.supports_hover());
+        assert!(!client.supports_declaration());
+        assert!(!client.supports_document_symbol());
+        assert!(!client.supports_document_link());
+    }
+
+    #[test]
+    fn test_capabilities_with_options_objects() {
+        let client = client_from_init_result(
+            r#"{"capabilities": {"foldingRangeProvider": {}, "documentLinkProvider": {"resolveProvider": false}}}"#,
+        );
+
+        assert!(client.supports_folding_range());
+        assert!(client.supports_document_link());
+        assert!(!client.supports_semantic_tokens());
+
+        let client = client_from_init_result(
+            r#"{"capabilities": {"semanticTokensProvider": {"legend": {"tokenTypes": [], "tokenModifiers": []}, "full": {"delta": true}}}}"#,
+        );
+        assert!(client.supports_semantic_tokens());
+    }
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(Duration::from_millis(20));
+        let start = Instant::now();
+        for _ in 0..5 {
+            throttle.wait();
+        }
+        // The first call does not wait
+        assert!(start.elapsed() >= Duration::from_millis(80));
+    }
+
+    #[test]
+    fn test_negotiated_position_encoding() {
+        let client = client_from_init_result(r#"{"capabilities": {}}"#);
+        assert_eq!(client.position_encoding(), PositionEncoding::Utf16);
+
+        let client = client_from_init_result(r#"{"capabilities": {"positionEncoding": "utf-8"}}"#);
+        assert_eq!(client.position_encoding(), PositionEncoding::Utf8);
+
+        let line = "let é = \"😀\"; x";
+        let byte_offset = line.find('x').unwrap();
+        assert_eq!(PositionEncoding::Utf8.offset(line, byte_offset), 17);
+        assert_eq!(PositionEncoding::Utf16.offset(line, byte_offset), 14);
+    }
+
+    #[test]
+    fn test_prepare_rename() {
+        assert!(
+            !client_from_init_result(r#"{"capabilities": {"renameProvider": true}}"#)
+                .supports_prepare_rename()
+        );
+
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        client
+            .set_capabilities(json!({"renameProvider": {"prepareProvider": true}}))
+            .unwrap();
+        assert!(client.supports_prepare_rename());
+        for result in &["null", r#"{"defaultBehavior": true}"#] {
+            message_tx
+                .send(format!(
+                    r#"{{"jsonrpc": "2.0", "id": {}, "result": {}}}"#,
+                    client.requests_sent(),
+                    result
+                ))
+                .unwrap();
+            let params = TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::parse("file:///a.rs").unwrap(),
+                },
+                position: Position::new(0, 3),
+            };
+            let response = client.prepare_rename(params).unwrap();
+            assert_eq!(response, serde_json::from_str::<Value>(result).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_get_references() {
+        assert!(!client_from_init_result(r#"{"capabilities": {}}"#).supports_references());
+
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        client
+            .set_capabilities(json!({"referencesProvider": true}))
+            .unwrap();
+        assert!(client.supports_references());
+        message_tx
+            .send(
+                r#"{"jsonrpc": "2.0", "id": 0, "result": [{"uri": "file:///b.rs", "range": {"start": {"line": 2, "character": 4}, "end": {"line": 2, "character": 7}}}]}"#
+                    .to_string(),
+            )
+            .unwrap();
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::parse("file:///a.rs").unwrap(),
+            },
+            position: Position::new(0, 3),
+        };
+        let references = client.get_references(params).unwrap().unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].uri.as_str(), "file:///b.rs");
+        assert_eq!(references[0].range.start, Position::new(2, 4));
+    }
+
+    #[test]
+    fn test_workspace_symbols() {
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        client
+            .set_capabilities(json!({"workspaceSymbolProvider": true}))
+            .unwrap();
+        assert!(client.supports_workspace_symbol());
+        for (id, result) in [
+            r#"[{"name": "foo", "kind": 12, "location": {"uri": "file:///a.rs", "range": {"start": {"line": 1, "character": 0}, "end": {"line": 3, "character": 1}}}}]"#,
+            "null",
+        ]
+        .iter()
+        .enumerate()
+        {
+            message_tx
+                .send(format!(
+                    r#"{{"jsonrpc": "2.0", "id": {}, "result": {}}}"#,
+                    id, result
+                ))
+                .unwrap();
+        }
+
+        let symbols = client.workspace_symbols("").unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].name, "foo");
+        assert_eq!(symbols[0].location.range.start, Position::new(1, 0));
+        assert!(client.workspace_symbols("f").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_definitions_correlates_out_of_order_responses() {
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        for (id, line) in &[(1, 10), (0, 20)] {
+            message_tx
+                .send(format!(
+                    r#"{{"jsonrpc": "2.0", "id": {}, "result": {{"uri": "file:///a.rs", "range": {{"start": {{"line": {}, "character": 0}}, "end": {{"line": {}, "character": 1}}}}}}}}"#,
+                    id, line, line
+                ))
+                .unwrap();
+        }
+
+        let params = (0..2)
+            .map(|line| TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::parse("file:///a.rs").unwrap(),
+                },
+                position: Position::new(line, 0),
+            })
+            .collect();
+        let lines = client
+            .get_definitions(params, 2)
+            .unwrap()
+            .into_iter()
+            .map(|resp| match resp.unwrap() {
+                GotoDefinitionResponse::Scalar(loc) => loc.range.start.line,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines, vec![20, 10]);
+    }
+
+    #[test]
+    fn test_wait_for_diagnostics_keeps_the_latest() {
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        client.set_keep_diagnostics(true);
+        let publish = |uri: &str, message: &str| {
+            format!(
+                r#"{{"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {{"uri": "{}", "diagnostics": [{{"range": {{"start": {{"line": 0, "character": 0}}, "end": {{"line": 0, "character": 1}}}}, "message": "{}"}}]}}}}"#,
+                uri, message
+            )
+        };
+        message_tx
+            .send(publish("file:///src/main.rs", "first"))
+            .unwrap();
+        message_tx
+            .send(publish("file:///src/lib.rs", "other"))
+            .unwrap();
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            message_tx
+                .send(publish("file:///src/main.rs", "second"))
+                .unwrap();
+        });
+
+        let diagnostics = client
+            .wait_for_diagnostics(Path::new("/src/main.rs"), Duration::from_millis(200))
+            .unwrap();
+        sender.join().unwrap();
+        assert_eq!(diagnostics[0]["message"], "second");
+
+        let diagnostics = client
+            .wait_for_diagnostics(Path::new("/src/lib.rs"), Duration::from_millis(10))
+            .unwrap();
+        assert_eq!(diagnostics[0]["message"], "other");
+        assert!(client
+            .wait_for_diagnostics(Path::new("/src/lib.rs"), Duration::from_millis(10))
+            .is_none());
+    }
+
+    #[test]
+    fn test_wait_until_diagnostics_published() {
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        let publish = |uri: &str| {
+            format!(
+                r#"{{"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {{"uri": "{}", "diagnostics": []}}}}"#,
+                uri
+            )
+        };
+        message_tx.send(publish("file:///src/lib.rs")).unwrap();
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            message_tx.send(publish("file:///src/main.rs")).unwrap();
+        });
+
+        assert!(client
+            .wait_until_diagnostics_published(Path::new("/src/main.rs"), Duration::from_secs(10)));
+        sender.join().unwrap();
+        // Not kept without `set_keep_diagnostics`
+        assert!(client.diagnostics.is_empty());
+        assert!(!client
+            .wait_until_diagnostics_published(Path::new("/src/app.rs"), Duration::from_millis(10)));
+
+        // Those published before the document was opened are stale
+        client
+            .set_document("/src/main.rs", "rust".to_string(), String::new())
+            .unwrap();
+        assert!(!client.wait_until_diagnostics_published(
+            Path::new("/src/main.rs"),
+            Duration::from_millis(10)
+        ));
+    }
+
+    #[test]
+    fn test_is_ignored_notification() {
+        assert!(is_ignored_notification(
+            r#"{"jsonrpc": "2.0", "method": "telemetry/event", "params": {"name": "load"}}"#
+        ));
+        assert!(is_ignored_notification(
+            r#"{"jsonrpc": "2.0", "method": "window/logMessage", "params": {"type": 3, "message": "hi"}}"#
+        ));
+        assert!(!is_ignored_notification(
+            r#"{"jsonrpc": "2.0", "method": "$/progress", "params": {"token": 1, "value": {}}}"#
+        ));
+        assert!(!is_ignored_notification(
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "window/logMessage", "params": {}}"#
+        ));
+        assert!(!is_ignored_notification(
+            r#"{"jsonrpc": "2.0", "id": 3, "result": null}"#
+        ));
+    }
+
+    #[test]
+    fn test_invalid_messages_are_ignored() {
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        for message in &[
+            "Starting server v1.2.3",
+            r#"{"jsonrpc": "2.0", "id": 0, "result": []}"#,
+        ] {
+            message_tx.send(message.to_string()).unwrap();
+        }
+        let (id, result) = client.next_response().unwrap();
+        assert_eq!(id, 0);
+        assert_eq!(result.unwrap(), Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_responses_without_request_id_are_ignored() {
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        for message in &[
+            r#"{"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": "Parse error"}}"#,
+            r#"{"jsonrpc": "2.0", "id": "abc", "result": []}"#,
+            r#"{"jsonrpc": "2.0", "id": "1", "result": []}"#,
+        ] {
+            message_tx.send(message.to_string()).unwrap();
+        }
+        let (id, result) = client.next_response().unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(result.unwrap(), Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_is_server_error() {
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        for message in &[
+            r#"{"jsonrpc": "2.0", "id": 0, "error": {"code": -32603, "message": "no view"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 1, "result": null}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "result": 42}"#,
+        ] {
+            message_tx.send(message.to_string()).unwrap();
+        }
+        let params = (0..3)
+            .map(|line| TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::parse("file:///a.rs").unwrap(),
+                },
+                position: Position::new(line, 0),
+            })
+            .collect();
+
+        let responses = client.get_definitions(params, 1).unwrap();
+        let errors = responses
+            .iter()
+            .map(|resp| resp.as_ref().err().map(is_server_error))
+            .collect::<Vec<_>>();
+        assert_eq!(errors, vec![Some(true), None, Some(false)]);
+        // A `null` result is no definition
+        assert_eq!(
+            responses[1].as_ref().unwrap(),
+            &GotoDefinitionResponse::Array(vec![])
+        );
+    }
+
+    #[test]
+    fn test_attach_to_running_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let request: Value = serde_json::from_str(&read_message(&mut reader).unwrap()).unwrap();
+            assert_eq!(request["method"], "initialize");
+            assert_eq!(
+                request["params"]["capabilities"]["textDocument"]["hover"]["contentFormat"],
+                json!(["plaintext"])
+            );
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": {"capabilities": {"definitionProvider": true}},
+            });
+            (&stream)
+                .write_all(prepare_lsp_json(&response).unwrap().as_bytes())
+                .unwrap();
+            let notification: Value =
+                serde_json::from_str(&read_message(&mut reader).unwrap()).unwrap();
+            assert_eq!(notification["method"], "initialized");
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let (client, _reader) = LSClient::attach(
+            stream.try_clone().unwrap(),
+            stream,
+            &ServerOptions {
+                root_path: "/repo".into(),
+                hover_format: Some(MarkupKind::PlainText),
+                startup_timeout: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(client.supports_definition());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut methods = Vec::new();
+            loop {
+                let message: Value =
+                    serde_json::from_str(&read_message(&mut reader).unwrap()).unwrap();
+                methods.push(message["method"].clone());
+                let result = match message["method"].as_str() {
+                    Some("initialize") => json!({"capabilities": {}}),
+                    Some("shutdown") => Value::Null,
+                    Some("exit") => break,
+                    _ => continue,
+                };
+                let response = json!({"jsonrpc": "2.0", "id": message["id"], "result": result});
+                (&stream)
+                    .write_all(prepare_lsp_json(&response).unwrap().as_bytes())
+                    .unwrap();
+            }
+            methods
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let (mut client, _reader) = LSClient::attach(
+            stream.try_clone().unwrap(),
+            stream,
+            &ServerOptions {
+                root_path: "/repo".into(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        client.shutdown(Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["initialize", "initialized", "shutdown", "exit"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_respawn_after_a_failed_write() {
+        let (_message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(ClosedWriter));
+        client.options = Some(fake_server_options());
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::parse("file:///main.go").unwrap(),
+            },
+            position: Position::new(0, 0),
+        };
+        let err = client.get_definition(params).unwrap_err();
+        assert!(err.is::<WriteError>(), "{:#}", err);
+
+        client.respawn().unwrap();
+        assert!(client.supports_definition());
+        let pid = client.child.as_ref().unwrap().id();
+        assert!(is_running(pid));
+        client.respawn().unwrap();
+        assert_eq!(client.respawns(), 2);
+        // Killed and waited for, rather than left running next to the new one
+        assert!(!is_running(pid));
+        client
+            .set_document("/repo/main.go", "go".to_string(), String::new())
+            .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restart_kills_a_server_that_does_not_shut_down() {
+        let (mut client, _reader) = LSClient::spawn_server(fake_server_options()).unwrap();
+        let pid = client.child.as_ref().unwrap().id();
+        let requests_sent = client.requests_sent();
+
+        // The fake server never answers `shutdown`
+        client.restart(Duration::from_millis(100)).unwrap();
+        assert_eq!(client.restarts(), 1);
+        assert!(!is_running(pid));
+        let new_pid = client.child.as_ref().unwrap().id();
+        assert_ne!(new_pid, pid);
+        assert!(is_running(new_pid));
+        // The ids of the new server's requests follow those of the old one
+        assert!(client.requests_sent() > requests_sent);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_startup_timeout() {
+        let started = Instant::now();
+        let err = LSClient::spawn_server(ServerOptions {
+            command: "sleep".into(),
+            args: Some("30".into()),
+            root_path: "/repo".into(),
+            startup_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        })
+        .err()
+        .unwrap();
+        assert!(
+            err.to_string().contains("did not respond to `initialize`"),
+            "{:#}",
+            err
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_wait_until_ready_retries_until_a_location() {
+        let (message_tx, message_rx) = channel();
+        let mut client = LSClient::new(message_rx, Box::new(std::io::sink()));
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::parse("file:///main.go").unwrap(),
+            },
+            position: Position::new(0, 0),
+        };
+        for message in &[
+            r#"{"jsonrpc": "2.0", "id": 0, "error": {"code": -32801, "message": "indexing"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 1, "result": null}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "result": []}"#,
+            r#"{"jsonrpc": "2.0", "id": 3, "result": {"uri": "file:///main.go", "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 1}}}}"#,
+            r#"{"jsonrpc": "2.0", "id": 4, "result": null}"#,
+        ] {
+            message_tx.send(message.to_string()).unwrap();
+        }
+
+        client
+            .wait_until_ready(params.clone(), Duration::from_secs(60))
+            .unwrap();
+        assert!(client
+            .wait_until_ready(params.clone(), Duration::from_secs(0))
+            .is_err());
+
+        // A server that never answers
+        let started = Instant::now();
+        let err = client
+            .wait_until_ready(params, Duration::from_millis(200))
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("was not ready"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+        drop(message_tx);
     }
 }
@@ -33,7 +33,9 @@ pub fn read_message<T: BufRead>(reader: &mut T) -> Result<String> {
 
     loop {
         buffer.clear();
-        let _result = reader.read_line(&mut buffer);
+        if reader.read_line(&mut buffer)? == 0 {
+            ret_error!("The language server closed its output");
+        }
 
         match &buffer {
             s if s.trim().is_empty() => break,
@@ -1,56 +1,304 @@
-mod cli;
-mod configs;
-mod crawler;
-mod emitter;
-mod indexer;
-mod lsif_data_cache;
-mod lsp;
-mod protocol;
-mod tests;
-
 use core::panic;
 use std::{
     clone, env,
+    net::TcpStream,
     path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    thread::JoinHandle,
 };
 
-pub use anyhow::{anyhow as error, bail as ret_error, Error, Result};
-use cli::Args;
-use configs::language_configs;
-use crawler::traverse;
+use anyhow::Context;
 use ignore::{DirEntry, Walk};
 use indicatif::ProgressStyle;
 use languageserver_types::{Position, TextDocumentIdentifier, TextDocumentPositionParams, Url};
-use lsp::{LSClient, LSConfig};
 use structopt::{clap::crate_authors, StructOpt};
+use universal_lsif::{
+    archive::ExtractedArchive,
+    check,
+    cli::{self, Args, ReadyProbe, VerifyArgs},
+    configs::{extension_languages, language_configs},
+    crawler,
+    indexer::IndexStats,
+    logging,
+    lsp::{LSClient, LSConfig, ServerOptions},
+    uri, verify, Result,
+};
 
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("verify-dump") {
+        verify_dump(VerifyArgs::from_iter(std::env::args().skip(1)));
+        return;
+    }
+    // `exit` does not run destructors, e.g. of the extracted archive, so they run in `run`
+    std::process::exit(run());
+}
+
+/// Indexes the project with the command-line arguments, and returns the exit code.
+fn run() -> i32 {
     let mut args: Args = Args::from_args();
-    args.canonicalize_paths();
+    if let Err(err) = logging::init(args.log_file.as_deref()) {
+        eprintln!("Failed: {:#}", err);
+        return 1;
+    }
+    let archive = match &args.from_archive {
+        Some(path) => match ExtractedArchive::extract(path) {
+            Ok(archive) => Some(archive),
+            Err(err) => {
+                eprintln!("Failed: {}", err);
+                return 1;
+            }
+        },
+        None => None,
+    };
+    if let Some(archive) = &archive {
+        args.project_root = Some(archive.project_root());
+    }
+    if let Err(err) = args.canonicalize_paths() {
+        eprintln!("Failed: {:#}", err);
+        return 1;
+    }
 
-    let config = match language_configs().get(&args.language) {
+    let configs = language_configs();
+    let mut config = match configs.get(&args.language) {
         Some(c) => c.clone(),
         None => {
             eprintln!("Failed: Language not found.");
-            return;
+            return 1;
         }
     };
+    let (_, ambiguous) = extension_languages(&configs);
+    for (ext, languages) in ambiguous {
+        if languages.contains(&args.language) {
+            log::warn!(
+                "`.{}` is an extension of {}, none of which prefers it",
+                ext,
+                languages.join(", ")
+            );
+        }
+    }
+    config
+        .language_ids
+        .extend(args.language_id_map.iter().cloned());
+    for ext in config.restrict_extensions(&args.only_extensions) {
+        log::warn!(
+            "`{}` is not an extension of {}, ignoring it",
+            ext,
+            args.language
+        );
+    }
+    if config.extensions.is_empty() {
+        eprintln!("Failed: No extension of {} to index.", args.language);
+        return 1;
+    }
+    match args.skip_words() {
+        Ok(words) => config.keywords.extend(words),
+        Err(err) => {
+            eprintln!("Failed: {}", err);
+            return 1;
+        }
+    }
 
-    let (client, lsp_proc) = match LSClient::spawn_server(
-        args.init_server_command.clone(),
-        args.server_args.clone(),
-        args.project_root.clone().unwrap(),
-    ) {
+    let initialization_options = match config.initialization_options(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("Failed: {}", err);
+            return 1;
+        }
+    };
+    let capabilities = match args.client_capabilities() {
+        Ok(capabilities) => capabilities,
+        Err(err) => {
+            eprintln!("Failed: {:#}", err);
+            return 1;
+        }
+    };
+
+    let options = ServerOptions {
+        root_path: args.project_root.clone().unwrap(),
+        workspace_folders: args.workspace_folders.clone(),
+        env: args.server_env.clone(),
+        cwd: args.server_cwd.clone(),
+        stderr: args.log_file.clone(),
+        trace: args.server_trace.clone(),
+        capabilities,
+        hover_format: Some(args.hover_format.markup_kind()),
+        initialization_options,
+        startup_timeout: Some(std::time::Duration::from_secs(args.server_startup_timeout)),
+        ..Default::default()
+    };
+    let (mut client, lsp_proc) = match start_server(&args, &config, options) {
         Ok(c) => c,
         Err(err) => {
             eprintln!("Failed: {}", err);
-            return;
+            return 1;
         }
     };
 
-    // A hack to make sure the server is initialized
-    std::thread::sleep(std::time::Duration::from_millis(1500));
+    match &args.server_ready_command {
+        Some(probe) => {
+            if let Err(err) = wait_until_ready(&mut client, probe, &args, &config) {
+                eprintln!("Failed: {}", err);
+                return 1;
+            }
+        }
+        // A hack to make sure the server is initialized
+        None => std::thread::sleep(std::time::Duration::from_millis(1500)),
+    }
+
+    let check = args.check;
+    let output = args.output.clone().unwrap();
+    let shards = args.shards();
+    let stats_json = args.stats_json.clone();
+    let after = args.after.clone();
+    let pool = match rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()
+    {
+        Ok(pool) => pool,
+        Err(err) => {
+            eprintln!("Failed: Could not create the thread pool: {}", err);
+            return 1;
+        }
+    };
+    let stats = match pool.install(|| crawler::traverse(args, client, config)) {
+        Ok(stats) => stats,
+        Err(err) => {
+            eprintln!("Failed: {:#}", err);
+            return 1;
+        }
+    };
+    if let Some(lsp_proc) = lsp_proc {
+        lsp_proc.join().unwrap();
+    }
+
+    if let Some(path) = stats_json {
+        if let Err(err) = write_stats(&path, &stats) {
+            eprintln!("Failed: {}", err);
+            return 1;
+        }
+    }
+
+    let output_paths = match shards {
+        Some(shards) => shards.existing_paths(),
+        None => vec![output],
+    };
+    if check {
+        match check::check_dump(&output_paths) {
+            Ok(summary) => println!(
+                "Check passed: {} definitions, {} references, {} contains edges",
+                summary.definitions, summary.references, summary.contains
+            ),
+            Err(err) => {
+                eprintln!("Check failed: {}", err);
+                return 1;
+            }
+        }
+    }
+
+    if let Some(command) = after {
+        if stats.timed_out {
+            eprintln!("Not running the `--after` command, the dump is incomplete");
+            return 0;
+        }
+        match run_after(&command, &output_paths) {
+            Ok(status) if status.success() => {}
+            Ok(status) => return status.code().unwrap_or(1),
+            Err(err) => {
+                eprintln!("Failed: {}", err);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+/// Runs the `--after` command on the dump in the given files, with the same stdio as this
+/// process.
+fn run_after(command: &str, dump_paths: &[PathBuf]) -> Result<ExitStatus> {
+    let (program, args) = cli::after_argv(command, dump_paths)?;
+    Command::new(&program)
+        .args(&args)
+        .status()
+        .with_context(|| format!("Could not run `{}`", program))
+}
+
+/// Spawns the language server, or attaches to a running one with `--attach-stdio` or
+/// `--attach-socket`. Returns the thread that reads the messages of a spawned server, which
+/// ends once it exits. An attached server keeps running, so its thread is not returned.
+fn start_server(
+    args: &Args,
+    config: &LSConfig,
+    mut options: ServerOptions,
+) -> Result<(LSClient, Option<JoinHandle<()>>)> {
+    if args.attach_stdio {
+        let (client, _reader) = LSClient::attach(std::io::stdin(), std::io::stdout(), &options)?;
+        return Ok((client, None));
+    }
+    if let Some(address) = &args.attach_socket {
+        let stream = TcpStream::connect(address)
+            .with_context(|| format!("Could not connect to `{}`", address))?;
+        let (client, _reader) = LSClient::attach(stream.try_clone()?, stream, &options)?;
+        return Ok((client, None));
+    }
+    let (command, server_args) = config.server_command(args)?;
+    options.command = command;
+    options.args = server_args;
+    let (client, lsp_proc) = LSClient::spawn_server(options)?;
+    Ok((client, Some(lsp_proc)))
+}
+
+/// Waits until the language server resolves the definition at the position given with
+/// `--server-ready-command`.
+fn wait_until_ready(
+    client: &mut LSClient,
+    probe: &ReadyProbe,
+    args: &Args,
+    config: &LSConfig,
+) -> Result<()> {
+    let text = args
+        .overlay
+        .read_to_string(&probe.file)
+        .with_context(|| format!("Could not read `{}`", probe.file.display()))?;
+    client.set_document(
+        &probe.file,
+        config.language_id(&probe.file, &args.language),
+        text,
+    )?;
+    let params = TextDocumentPositionParams {
+        text_document: TextDocumentIdentifier {
+            uri: uri::path_to_uri(&probe.file)?,
+        },
+        position: probe.position,
+    };
+    client.wait_until_ready(
+        params,
+        std::time::Duration::from_secs(args.server_startup_timeout),
+    )?;
+    // It is opened again when it is crawled
+    client.close_document(&probe.file)
+}
+
+/// Runs `universal-lsif verify-dump`, exiting with an error if the dump is not valid.
+fn verify_dump(args: VerifyArgs) {
+    match verify::verify_dump(&args.dumps) {
+        Ok(report) => {
+            print!("{}", report);
+            if !report.is_ok() {
+                eprintln!("Verification failed");
+                std::process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("Failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
 
-    crawler::traverse(args, client, config).unwrap();
-    lsp_proc.join().unwrap();
+/// Writes the given stats to the given file as JSON.
+fn write_stats(path: &Path, stats: &IndexStats) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Could not create `{}`", path.display()))?;
+    serde_json::to_writer_pretty(file, stats)?;
+    Ok(())
 }
@@ -0,0 +1,56 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Contents of files that take precedence over the files on disk, e.g. the unsaved buffers
+/// of an editor. Both the language server and the indexer see the overlaid contents.
+///
+/// The paths must be absolute and canonical, as those returned by `crawler::paths`.
+#[derive(Debug, Clone, Default)]
+pub struct Overlay {
+    files: HashMap<PathBuf, String>,
+}
+
+impl Overlay {
+    /// Overlays the given contents on the file at the given path.
+    pub fn insert(&mut self, path: PathBuf, contents: String) {
+        self.files.insert(path, contents);
+    }
+
+    /// Returns the contents of the given file: those overlaid on it, or those on disk.
+    pub fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+        match self.files.get(path) {
+            Some(contents) => Ok(contents.clone()),
+            None => std::fs::read_to_string(path),
+        }
+    }
+
+    /// Same as `read_to_string`, for files that may not be UTF-8.
+    pub fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        match self.files.get(path) {
+            Some(contents) => Ok(contents.clone().into_bytes()),
+            None => std::fs::read(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Overlay;
+
+    #[test]
+    fn test_overlay_takes_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let (saved, unsaved) = (dir.path().join("saved.rs"), dir.path().join("unsaved.rs"));
+        std::fs::write(&saved, "fn saved() {}").unwrap();
+        std::fs::write(&unsaved, "fn stale() {}").unwrap();
+
+        let mut overlay = Overlay::default();
+        overlay.insert(unsaved.clone(), "fn fresh() {}".to_string());
+        assert_eq!(overlay.read_to_string(&saved).unwrap(), "fn saved() {}");
+        assert_eq!(overlay.read_to_string(&unsaved).unwrap(), "fn fresh() {}");
+        assert_eq!(overlay.read(&unsaved).unwrap(), b"fn fresh() {}");
+        assert!(overlay.read(&dir.path().join("missing.rs")).is_err());
+    }
+}
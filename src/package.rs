@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{protocol::types::PackageInformation, Result};
+
+/// The package managers whose manifests name the package of a project, so that its exported
+/// monikers can be resolved from other repositories.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    /// `Cargo.toml`
+    Cargo,
+    /// `go.mod`, which has no version
+    Gomod,
+    /// `package.json`
+    Npm,
+}
+
+impl PackageManager {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PackageManager::Cargo => "cargo",
+            PackageManager::Gomod => "gomod",
+            PackageManager::Npm => "npm",
+        }
+    }
+
+    /// The name of the manifest file at the root of a project.
+    pub fn manifest(self) -> &'static str {
+        match self {
+            PackageManager::Cargo => "Cargo.toml",
+            PackageManager::Gomod => "go.mod",
+            PackageManager::Npm => "package.json",
+        }
+    }
+}
+
+/// Returns the package of the project at `root` from its manifest, or `None` if it has no
+/// manifest or the manifest does not name a package, e.g. a Cargo workspace.
+pub fn package_information(
+    manager: PackageManager,
+    root: &Path,
+) -> Result<Option<PackageInformation>> {
+    let path = root.join(manager.manifest());
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let src = std::fs::read_to_string(&path)
+        .with_context(|| format!("Could not read `{}`", path.display()))?;
+    let name_and_version = match manager {
+        PackageManager::Cargo => {
+            let manifest: toml::Value = toml::from_str(&src)
+                .with_context(|| format!("Invalid manifest `{}`", path.display()))?;
+            manifest.get("package").and_then(|package| {
+                let name = package.get("name")?.as_str()?.to_string();
+                // Missing if it is inherited from the workspace
+                let version = package
+                    .get("version")
+                    .and_then(toml::Value::as_str)
+                    .map(str::to_string);
+                Some((name, version))
+            })
+        }
+        PackageManager::Gomod => src
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("module "))
+            .map(|module| (module.trim().trim_matches('"').to_string(), None))
+            .next(),
+        PackageManager::Npm => {
+            let manifest: Value = serde_json::from_str(&src)
+                .with_context(|| format!("Invalid manifest `{}`", path.display()))?;
+            manifest["name"].as_str().map(|name| {
+                let version = manifest["version"].as_str().map(str::to_string);
+                (name.to_string(), version)
+            })
+        }
+    };
+    Ok(name_and_version.map(|(name, version)| PackageInformation {
+        name,
+        manager: manager.as_str().to_string(),
+        version,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{package_information, PackageManager};
+
+    #[test]
+    fn test_package_information() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = |manager, manifest: &str| {
+            std::fs::write(dir.path().join(PackageManager::manifest(manager)), manifest).unwrap();
+            package_information(manager, dir.path())
+                .unwrap()
+                .map(|package| (package.name, package.manager, package.version))
+        };
+
+        assert_eq!(
+            package(
+                PackageManager::Cargo,
+                "[package]\nname = \"zas\"\nversion = \"0.3.1\"\n"
+            ),
+            Some((
+                "zas".to_string(),
+                "cargo".to_string(),
+                Some("0.3.1".to_string())
+            ))
+        );
+        assert_eq!(
+            package(PackageManager::Cargo, "[workspace]\nmembers = [\"zas\"]\n"),
+            None
+        );
+        assert_eq!(
+            package(
+                PackageManager::Gomod,
+                "// comment\nmodule github.com/alidn/zas\n\ngo 1.16\n"
+            ),
+            Some((
+                "github.com/alidn/zas".to_string(),
+                "gomod".to_string(),
+                None
+            ))
+        );
+        assert_eq!(
+            package(
+                PackageManager::Npm,
+                r#"{"name": "@zas/web", "version": "1.0.0"}"#
+            ),
+            Some((
+                "@zas/web".to_string(),
+                "npm".to_string(),
+                Some("1.0.0".to_string())
+            ))
+        );
+
+        assert!(
+            package_information(PackageManager::Npm, &dir.path().join("missing"))
+                .unwrap()
+                .is_none()
+        );
+        std::fs::write(dir.path().join("package.json"), "{").unwrap();
+        assert!(package_information(PackageManager::Npm, dir.path()).is_err());
+    }
+}
@@ -38,17 +38,21 @@ pub enum Element {
 pub enum Vertex {
     Project(Project),
     Document(Document),
-    Range(Range),
+    Range(RangeVertex),
     ResultSet(ResultSet),
     HoverResult(HoverResult),
     MetaData(MetaData),
     Moniker(Moniker),
+    PackageInformation(PackageInformation),
 
     // Method results
     DefinitionResult(DefinitionResult),
+    DeclarationResult(DeclarationResult),
+    FoldingRangeResult(FoldingRangeResult),
+    DocumentLinkResult(DocumentLinkResult),
 
     ReferenceResult(ReferenceResult),
-    DiagnosticResult,
+    DiagnosticResult(DiagnosticResult),
     ExportResult,
     ExternalImportResult,
 }
@@ -61,6 +65,7 @@ pub enum Edge {
     RefersTo(EdgeData),
     Next(EdgeData),
     Moniker(EdgeData),
+    PackageInformation(EdgeData),
 
     Item(Item),
 
@@ -116,16 +121,56 @@ pub enum DefinitionResultType {
     Array(LocationOrRangeId),
 }
 
+/// An `item` edge. Only the items of reference results have a `property`.
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-#[serde(tag = "property")]
+#[serde(from = "ItemData", into = "ItemData")]
 pub enum Item {
+    /// An item of a reference result that is a definition of the symbol.
     Definition(MultiEdgeDataWithDocument),
+    /// An item of a reference result that is a reference to the symbol.
     Reference(MultiEdgeDataWithDocument),
-    #[serde(rename = "")]
+    /// An item of another result, e.g. the ranges of a definition result.
     Neither(MultiEdgeDataWithDocument),
 }
 
+/// The wire format of an `item` edge, which has no `property` unless it is the item of a
+/// reference result.
+#[derive(Serialize, Deserialize)]
+struct ItemData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    property: Option<ItemProperty>,
+    #[serde(flatten)]
+    data: MultiEdgeDataWithDocument,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum ItemProperty {
+    Definitions,
+    References,
+}
+
+impl From<ItemData> for Item {
+    fn from(item: ItemData) -> Self {
+        match item.property {
+            Some(ItemProperty::Definitions) => Item::Definition(item.data),
+            Some(ItemProperty::References) => Item::Reference(item.data),
+            None => Item::Neither(item.data),
+        }
+    }
+}
+
+impl From<Item> for ItemData {
+    fn from(item: Item) -> Self {
+        let (property, data) = match item {
+            Item::Definition(data) => (Some(ItemProperty::Definitions), data),
+            Item::Reference(data) => (Some(ItemProperty::References), data),
+            Item::Neither(data) => (None, data),
+        };
+        ItemData { property, data }
+    }
+}
+
 impl Edge {
     pub fn item(out_v: ID, in_vs: Vec<ID>, doc_id: ID) -> Self {
         Self::Item(Item::Neither(MultiEdgeDataWithDocument {
@@ -157,6 +202,44 @@ impl Edge {
             out_v: NumberOrString::Number(out_v),
         })
     }
+
+    /// Returns the IDs of the vertices that this edge connects, as keys.
+    pub(crate) fn vertex_keys(&self) -> Vec<String> {
+        match self {
+            Edge::Contains(data) => std::iter::once(&data.out_v)
+                .chain(&data.in_vs)
+                .map(key)
+                .collect(),
+            Edge::Item(Item::Definition(data))
+            | Edge::Item(Item::Reference(data))
+            | Edge::Item(Item::Neither(data)) => std::iter::once(key(&data.out_v))
+                .chain(data.in_vs.iter().map(key))
+                .chain(std::iter::once(data.document.to_string()))
+                .collect(),
+            Edge::RefersTo(data)
+            | Edge::Next(data)
+            | Edge::Moniker(data)
+            | Edge::PackageInformation(data)
+            | Edge::Definition(data)
+            | Edge::Declaration(data)
+            | Edge::Hover(data)
+            | Edge::References(data)
+            | Edge::Implementation(data)
+            | Edge::TypeDefinition(data)
+            | Edge::FoldingRange(data)
+            | Edge::DocumentLink(data)
+            | Edge::DocumentSymbol(data)
+            | Edge::Diagnostic(data) => vec![key(&data.out_v), key(&data.in_v)],
+        }
+    }
+}
+
+/// Returns the given ID as a key, so that number and string IDs can be compared.
+pub(crate) fn key(id: &NumberOrString) -> String {
+    match id {
+        NumberOrString::Number(n) => n.to_string(),
+        NumberOrString::String(s) => s.clone(),
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -165,6 +248,62 @@ pub struct Document {
     #[serde(with = "url_serde")]
     pub uri: lsp::Url,
     pub language_id: String,
+    /// The hex-encoded SHA-256 hash of the document's contents.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub content_hash: Option<String>,
+}
+
+/// https://github.com/Microsoft/language-server-protocol/blob/master/indexFormat/specification.md#ranges
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeVertex {
+    #[serde(flatten)]
+    pub range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<RangeTag>,
+    /// Not part of the LSIF specification, written with `--emit-offsets`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offsets: Option<ByteOffsets>,
+    /// Not part of the LSIF specification, written for definitions with `--extras` if the
+    /// server supports `textDocument/prepareRename`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renameable: Option<bool>,
+}
+
+/// The byte offsets of a range from the start of its document. `end` is exclusive.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub struct ByteOffsets {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Describes what a range is, so that consumers can render it without having to follow
+/// its result set.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum RangeTag {
+    Definition(DefinitionTag),
+    Reference(ReferenceTag),
+    /// Used for definitions whose symbol information could not be determined.
+    Unknown(ReferenceTag),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DefinitionTag {
+    pub text: String,
+    pub kind: SymbolKind,
+    /// The range of the whole definition, e.g. the body of a function.
+    pub full_range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceTag {
+    pub text: String,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -195,10 +334,35 @@ pub struct LSIFMarkedString {
 #[serde(rename_all = "camelCase")]
 pub struct DefinitionResult {}
 
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeclarationResult {}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ReferenceResult {}
 
+/// The `textDocument/foldingRange` result of a document, as returned by the language server.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FoldingRangeResult {
+    pub result: serde_json::Value,
+}
+
+/// The `textDocument/documentLink` result of a document, as returned by the language server.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentLinkResult {
+    pub result: serde_json::Value,
+}
+
+/// The diagnostics of a document, as published by the language server.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticResult {
+    pub result: serde_json::Value,
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct MetaData {
@@ -208,6 +372,28 @@ pub struct MetaData {
     pub(crate) tool_info: Option<ToolInfo>,
     #[serde(with = "url_serde")]
     pub(crate) project_root: lsp::Url,
+    /// Not part of the LSIF specification, identifies the project in combined dumps.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) project_name: Option<String>,
+    /// Not part of the LSIF specification, recorded with `--record-invocation`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) invocation: Option<Box<Invocation>>,
+}
+
+/// How a dump was made, to reproduce it.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Invocation {
+    pub(crate) language: String,
+    /// `None` if the indexer attached to a running server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) server_command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) server_args: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub(crate) timestamp: u64,
+    pub(crate) os: String,
+    pub(crate) arch: String,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -216,6 +402,34 @@ pub struct Moniker {
     pub(crate) kind: String,
     pub(crate) scheme: String,
     pub(crate) identifier: String,
+    /// Missing in the dumps written before it was added
+    #[serde(default)]
+    pub(crate) unique: MonikerUnique,
+}
+
+/// The scope in which the identifier of a moniker is unique, which tells resolvers whether
+/// to match it across documents, projects or repositories.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MonikerUnique {
+    Document,
+    Project,
+    Group,
+    #[default]
+    Scheme,
+    Global,
+}
+
+/// The package of the symbols of the `export` monikers linked to it, see
+/// `package::package_information`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageInformation {
+    pub(crate) name: String,
+    /// e.g. `cargo`, `gomod` or `npm`
+    pub(crate) manager: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -309,8 +523,9 @@ impl ToString for Language {
 /// The following code defines a edge of type `Next` going from `a` to `b`
 ///
 /// ```
+/// # use universal_lsif::{edge, protocol::types::{Edge, EdgeData, NumberOrString}};
 /// let a = 3;
-/// let b = 3;
+/// let b = 4;
 /// let edge = edge!(Next, a -> b);
 /// ```
 #[macro_export]
@@ -343,16 +558,20 @@ macro_rules! edge {
 /// # Examples
 ///
 /// ```
-/// enum StringOrVec {
-///     String(String),
-///     Vec(Vec<String>)
+/// # use universal_lsif::impl_from_variant;
+/// struct Word(String);
+/// struct Number(u64);
+///
+/// enum Token {
+///     Word(Word),
+///     Number(Number),
 /// }
 ///
-/// impl_from_variant(String, StringOrVec);
-/// impl_from_variant(Vec<String>, StringOrVec);
+/// impl_from_variant!(Word, Token);
+/// impl_from_variant!(Number, Token);
 ///
-/// fn ex(s: String) -> StringOrVec {
-///     s.into()
+/// fn ex(w: Word) -> Token {
+///     w.into()
 /// }
 /// ```
 #[macro_export]
@@ -368,10 +587,20 @@ macro_rules! impl_from_variant {
 
 impl_from_variant!(Project, Vertex);
 impl_from_variant!(Document, Vertex);
-impl_from_variant!(Range, Vertex);
 impl_from_variant!(ResultSet, Vertex);
 impl_from_variant!(MetaData, Vertex);
 impl_from_variant!(ReferenceResult, Vertex);
 impl_from_variant!(DefinitionResult, Vertex);
+impl_from_variant!(DeclarationResult, Vertex);
+impl_from_variant!(FoldingRangeResult, Vertex);
+impl_from_variant!(DocumentLinkResult, Vertex);
+impl_from_variant!(DiagnosticResult, Vertex);
 impl_from_variant!(HoverResult, Vertex);
 impl_from_variant!(Moniker, Vertex);
+impl_from_variant!(PackageInformation, Vertex);
+
+impl From<RangeVertex> for Vertex {
+    fn from(v: RangeVertex) -> Vertex {
+        Vertex::Range(v)
+    }
+}
@@ -0,0 +1,152 @@
+use languageserver_types::{request::GotoDefinitionResponse, Range as LspRange};
+
+use crate::{
+    crawler::{Location, Range},
+    uri::{normalize_uri, same_file},
+};
+
+/// What a word in a file turned out to be.
+#[derive(Debug, PartialEq)]
+pub enum Classification {
+    /// The word declares a symbol. Holds the range that the server returned for the symbol
+    /// if it is not that of the word, e.g. its whole declaration, which references resolve
+    /// to as well.
+    Definition(Option<Range>),
+    /// The word refers to the symbol defined at the given location.
+    Reference(Location),
+    /// Nothing is known about the word.
+    Skip,
+}
+
+/// Decides whether a word is a definition or a reference, given the language server's
+/// response to a `textDocument/definition` request at the word.
+pub trait ResolutionStrategy {
+    /// Classifies `word`, found at `range` in the file with the given URI (as returned by
+    /// `uri::file_uri`).
+    fn classify(
+        &self,
+        word: &str,
+        file_uri: &str,
+        range: LspRange,
+        response: &GotoDefinitionResponse,
+    ) -> Classification;
+}
+
+/// Treats a word as a definition if the server says that it is defined at its own range, or
+/// at a range of the same file that contains the word and starts on its line, e.g. the whole
+/// declaration `def foo(x)` for `foo` as some servers return. Treats it as a reference to the
+/// first location the server returns otherwise, e.g. a shorter name at the word or a function
+/// that contains it.
+pub struct DefinitionProbeStrategy;
+
+impl ResolutionStrategy for DefinitionProbeStrategy {
+    fn classify(
+        &self,
+        _word: &str,
+        file_uri: &str,
+        range: LspRange,
+        response: &GotoDefinitionResponse,
+    ) -> Classification {
+        let def_location = match response {
+            GotoDefinitionResponse::Scalar(it) => it,
+            GotoDefinitionResponse::Array(it) => match it.first() {
+                Some(it) => it,
+                None => return Classification::Skip,
+            },
+            GotoDefinitionResponse::Link(_) => return Classification::Skip,
+        };
+        let def_range = def_location.range;
+        let declares_word = def_range.start.line == range.start.line
+            && def_range.start <= range.start
+            && range.end <= def_range.end;
+        if declares_word && same_file(&def_location.uri, file_uri) {
+            // it defines itself, so it's a declaration
+            Classification::Definition(Some(def_range).filter(|it| *it != range).map(Range::new))
+        } else {
+            Classification::Reference(Location {
+                file_path: normalize_uri(&def_location.uri),
+                range: Range::new(def_location.range),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use languageserver_types::{
+        request::GotoDefinitionResponse, Location as LspLocation, Position, Range as LspRange, Url,
+    };
+
+    use super::{Classification, DefinitionProbeStrategy, ResolutionStrategy};
+    use crate::crawler::{Location, Range};
+
+    fn range(line: u64) -> LspRange {
+        LspRange::new(Position::new(line, 4), Position::new(line, 7))
+    }
+
+    fn location(line: u64) -> LspLocation {
+        LspLocation::new(Url::parse("file:///src/main.rs").unwrap(), range(line))
+    }
+
+    #[test]
+    fn test_definition_probe_strategy() {
+        let strategy = DefinitionProbeStrategy;
+        let classify =
+            |response| strategy.classify("foo", "file:///src/main.rs", range(0), &response);
+
+        assert_eq!(
+            classify(GotoDefinitionResponse::Scalar(location(0))),
+            Classification::Definition(None)
+        );
+        assert_eq!(
+            classify(GotoDefinitionResponse::Array(vec![
+                location(2),
+                location(0)
+            ])),
+            Classification::Reference(Location {
+                file_path: "file:///src/main.rs".to_string(),
+                range: Range::new(range(2)),
+            })
+        );
+        assert_eq!(
+            classify(GotoDefinitionResponse::Array(vec![])),
+            Classification::Skip
+        );
+
+        let at = |start: (u64, u64), end: (u64, u64)| {
+            LspLocation::new(
+                Url::parse("file:///src/main.rs").unwrap(),
+                LspRange::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            )
+        };
+        // The whole declaration, e.g. `def foo(x)` up to its `end`
+        let declaration = at((0, 0), (3, 3));
+        assert_eq!(
+            classify(GotoDefinitionResponse::Scalar(declaration.clone())),
+            Classification::Definition(Some(Range::new(declaration.range)))
+        );
+        // Starts at the word, but ends inside of it
+        let shorter = at((0, 4), (0, 6));
+        assert_eq!(
+            classify(GotoDefinitionResponse::Scalar(shorter.clone())),
+            Classification::Reference(Location {
+                file_path: "file:///src/main.rs".to_string(),
+                range: Range::new(shorter.range),
+            })
+        );
+        // Contains the word, but starts on an earlier line, e.g. a recursive call
+        let function = at((0, 0), (2, 1));
+        assert_eq!(
+            strategy.classify(
+                "foo",
+                "file:///src/main.rs",
+                range(1),
+                &GotoDefinitionResponse::Scalar(function.clone())
+            ),
+            Classification::Reference(Location {
+                file_path: "file:///src/main.rs".to_string(),
+                range: Range::new(function.range),
+            })
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use languageserver_types::{Position, Range as LspRange};
+use tree_sitter::{Language, Node, Parser};
+
+use crate::lsp::PositionEncoding;
+
+/// Returns the tree-sitter grammar with the given name, as given by `grammar` in the
+/// language config.
+fn language(grammar: &str) -> Option<Language> {
+    match grammar {
+        "go" => Some(tree_sitter_go::LANGUAGE.into()),
+        "python" => Some(tree_sitter_python::LANGUAGE.into()),
+        "rust" => Some(tree_sitter_rust::LANGUAGE.into()),
+        _ => None,
+    }
+}
+
+/// Returns the identifiers in the given text with their ranges, with offsets in the given
+/// encoding. Unlike the regex tokenizer, this skips keywords, comments and strings.
+///
+/// Returns `None` if the grammar is not known or the text could not be parsed, in which case
+/// the caller should fall back to the regex tokenizer.
+pub fn identifier_words(
+    text: &str,
+    grammar: &str,
+    encoding: PositionEncoding,
+) -> Option<Vec<(String, LspRange)>> {
+    let mut parser = Parser::new();
+    parser.set_language(&language(grammar)?).ok()?;
+    let tree = parser.parse(text, None)?;
+
+    let lines = text.split('\n').collect::<Vec<_>>();
+    let mut words = Vec::new();
+    let mut cursor = tree.walk();
+    let mut stack = vec![tree.root_node()];
+    while let Some(node) = stack.pop() {
+        if is_identifier(&node) {
+            let (start, end) = (node.start_position(), node.end_position());
+            let line = lines[start.row];
+            words.push((
+                text[node.byte_range()].to_string(),
+                LspRange::new(
+                    Position::new(start.row as u64, encoding.offset(line, start.column)),
+                    Position::new(end.row as u64, encoding.offset(line, end.column)),
+                ),
+            ));
+        }
+        // Pushed in reverse to visit the nodes in the order of the text
+        let children = node.children(&mut cursor).collect::<Vec<_>>();
+        stack.extend(children.into_iter().rev());
+    }
+    Some(words)
+}
+
+/// Returns true if the node is an identifier, e.g. `identifier`, `type_identifier` or
+/// `field_identifier`, which all grammars name the same way.
+fn is_identifier(node: &Node) -> bool {
+    node.is_named() && node.child_count() == 0 && node.kind().ends_with("identifier")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::identifier_words;
+    use crate::lsp::PositionEncoding;
+
+    #[test]
+    fn test_identifier_words() {
+        let text = "fn foo(x: Bar) -> u8 {\n    // foo\n    let s = \"foo\"; x.baz\n}";
+        let words = identifier_words(text, "rust", PositionEncoding::Utf16).unwrap();
+
+        let names = words.iter().map(|(w, _)| w.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["foo", "x", "Bar", "s", "x", "baz"]);
+        let (_, range) = &words[5];
+        assert_eq!((range.start.line, range.start.character), (2, 21));
+    }
+
+    #[test]
+    fn test_unknown_grammar() {
+        assert!(identifier_words("", "cobol", PositionEncoding::Utf16).is_none());
+    }
+}
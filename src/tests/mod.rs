@@ -1,60 +1,81 @@
-mod rust {
-    use std::path::PathBuf;
+use std::{path::PathBuf, process::Command};
+
+/// Returns the path of the given file or directory in `src/tests/test_data`.
+fn test_data_path(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/tests/test_data")
+        .join(path)
+}
+
+/// Returns true if the given language server can be run with the given arguments. Tests
+/// that need a language server are skipped when it is not installed.
+fn server_available(command: &str, version_args: &[&str]) -> bool {
+    let available = Command::new(command)
+        .args(version_args)
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+    if !available {
+        eprintln!("skipping: `{}` is not available", command);
+    }
+    available
+}
 
+mod rust {
     use languageserver_types::{Position, TextDocumentIdentifier, TextDocumentPositionParams, Url};
 
-    use crate::Result;
+    use super::{server_available, test_data_path};
     use crate::{
-        configs::language_configs,
-        lsp::{LSClient, LSConfig},
+        lsp::{LSClient, ServerOptions},
+        Result,
     };
 
     fn get_client() -> Result<LSClient> {
-        let config = language_configs()["rust"].clone();
-        Ok(LSClient::spawn_server(
-            "rust-analyzer".into(),
-            None,
-            PathBuf::from("/Users/zas/space/universal-lsif/src/tests/test_data/rust"),
-        )
+        Ok(LSClient::spawn_server(ServerOptions {
+            command: "rust-analyzer".into(),
+            root_path: test_data_path("rust"),
+            ..Default::default()
+        })
         .unwrap()
         .0)
     }
 
     fn client_with_document(path: &str) -> Result<LSClient> {
         let mut client = get_client()?;
-        let src = std::fs::read_to_string(path)?;
-        client.set_document(path, src);
+        let path = test_data_path(path);
+        let src = std::fs::read_to_string(&path)?;
+        client.set_document(path, "rust".to_string(), src)?;
         Ok(client)
     }
 
     #[test]
     fn test_server_init() {
+        if !server_available("rust-analyzer", &["--version"]) {
+            return;
+        }
         get_client().unwrap();
     }
 
     #[test]
     fn test_set_document() {
-        client_with_document(
-            "/Users/zas/space/universal-lsif/src/tests/test_data/rust/src/main.rs",
-        )
-        .unwrap();
+        if !server_available("rust-analyzer", &["--version"]) {
+            return;
+        }
+        client_with_document("rust/src/main.rs").unwrap();
     }
 
     #[test]
     fn test_get_definition() {
-        let mut client = client_with_document(
-            "/Users/zas/space/universal-lsif/src/tests/test_data/rust/src/main.rs",
-        )
-        .unwrap();
+        if !server_available("rust-analyzer", &["--version"]) {
+            return;
+        }
+        let mut client = client_with_document("rust/src/main.rs").unwrap();
 
         std::thread::sleep(std::time::Duration::from_millis(3000));
-        let def = client
+        client
             .get_definition(TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(
-                        "/Users/zas/space/universal-lsif/src/tests/test_data/rust/src/main.rs",
-                    )
-                    .unwrap(),
+                    uri: Url::from_file_path(test_data_path("rust/src/main.rs")).unwrap(),
                 },
                 position: Position {
                     line: 2,
@@ -62,34 +83,31 @@ mod rust {
                 },
             })
             .unwrap();
-        //dbg!(def);
     }
 }
 
 mod go {
-    use std::path::PathBuf;
-
-    use languageserver_types::{Position, TextDocumentIdentifier, TextDocumentPositionParams, Url};
-
-    use crate::Result;
+    use super::{server_available, test_data_path};
     use crate::{
-        configs::language_configs,
-        lsp::{LSClient, LSConfig},
+        lsp::{LSClient, ServerOptions},
+        Result,
     };
 
     fn get_client() -> Result<LSClient> {
-        let config = language_configs()["go"].clone();
-        Ok(LSClient::spawn_server(
-            "gopls".into(),
-            None,
-            PathBuf::from("/Users/zas/space/universal-lsif/src/tests/test_data/go"),
-        )
+        Ok(LSClient::spawn_server(ServerOptions {
+            command: "gopls".into(),
+            root_path: test_data_path("go"),
+            ..Default::default()
+        })
         .unwrap()
         .0)
     }
 
     #[test]
     fn test_server_init() {
+        if !server_available("gopls", &["version"]) {
+            return;
+        }
         get_client().unwrap();
     }
 }
@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use languageserver_types::Url;
+
+use crate::{cli::normalize_path, error, Result};
+
+/// Returns the `file://` URI of the given file.
+pub fn path_to_uri<P: AsRef<Path>>(path: P) -> Result<Url> {
+    let path = strip_verbatim_prefix(path.as_ref());
+    Url::from_file_path(&path)
+        .map_err(|()| error!("Could not convert `{}` to a URI", path.display()))
+}
+
+/// Returns the `file://` URI of the given directory, with a trailing slash.
+pub fn dir_to_uri<P: AsRef<Path>>(path: P) -> Result<Url> {
+    let path = strip_verbatim_prefix(path.as_ref());
+    Url::from_directory_path(&path)
+        .map_err(|()| error!("Could not convert `{}` to a URI", path.display()))
+}
+
+/// Removes the `\\?\` prefix that `canonicalize` adds to paths on Windows, which servers
+/// do not expect. `\\?\C:\src` becomes `C:\src` and `\\?\UNC\server\share` becomes
+/// `\\server\share`.
+fn strip_verbatim_prefix(path: &Path) -> PathBuf {
+    let s = path.to_string_lossy();
+    if let Some(rest) = s.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", rest))
+    } else if let Some(rest) = s.strip_prefix(r"\\?\") {
+        PathBuf::from(rest)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Makes the drive letter of a Windows `file://` URI uppercase and decodes its colon, so
+/// that `file:///c%3A/src` and `file:///C:/src` have the same key.
+fn normalize_drive_letter(uri: &str) -> String {
+    let rest = match uri.strip_prefix("file:///") {
+        Some(rest) => rest,
+        None => return uri.to_string(),
+    };
+    let mut chars = rest.chars();
+    let drive = match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => c.to_ascii_uppercase(),
+        _ => return uri.to_string(),
+    };
+    let rest = chars.as_str();
+    let rest = match rest
+        .strip_prefix(':')
+        .or_else(|| rest.strip_prefix("%3A"))
+        .or_else(|| rest.strip_prefix("%3a"))
+    {
+        Some(rest) => rest,
+        None => return uri.to_string(),
+    };
+    if !(rest.is_empty() || rest.starts_with('/')) {
+        return uri.to_string();
+    }
+    format!("file:///{}:{}", drive, rest)
+}
+
+/// Returns the URI of the given file in the form that is used as the key for documents,
+/// ranges and definitions.
+///
+/// Symlinks and `.`/`..` components are resolved, so that the same file always has the same
+/// key no matter if it was found by walking the project or returned by the language server.
+pub fn file_uri(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| normalize_path(path));
+    match path_to_uri(&path) {
+        Ok(uri) => normalize_drive_letter(uri.as_str()),
+        Err(_) => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Same as `file_uri`, but for a URI returned by the language server. URIs that do not point
+/// to a file are returned unchanged.
+pub fn normalize_uri(uri: &Url) -> String {
+    let uri = match Url::parse(&normalize_drive_letter(uri.as_str())) {
+        Ok(it) => it,
+        Err(_) => uri.clone(),
+    };
+    match uri.to_file_path() {
+        Ok(path) => file_uri(&path),
+        Err(()) => uri.to_string(),
+    }
+}
+
+/// Returns true if the URI returned by the language server points to the file with the given
+/// key (as returned by `file_uri`). Percent-encoding, drive letters and a trailing slash, as
+/// added by `dir_to_uri`, make no difference.
+pub fn same_file(uri: &Url, file_uri: &str) -> bool {
+    normalize_uri(uri).trim_end_matches('/') == file_uri.trim_end_matches('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use languageserver_types::Url;
+
+    use super::{
+        dir_to_uri, file_uri, normalize_drive_letter, normalize_uri, same_file,
+        strip_verbatim_prefix,
+    };
+
+    #[test]
+    fn test_normalize_drive_letter() {
+        assert_eq!(
+            normalize_drive_letter("file:///c%3A/src/main.rs"),
+            "file:///C:/src/main.rs"
+        );
+        assert_eq!(
+            normalize_drive_letter("file:///c:/src/main.rs"),
+            "file:///C:/src/main.rs"
+        );
+        assert_eq!(
+            normalize_drive_letter("file:///src/main.rs"),
+            "file:///src/main.rs"
+        );
+        assert_eq!(
+            normalize_drive_letter("file://server/share/main.rs"),
+            "file://server/share/main.rs"
+        );
+    }
+
+    #[test]
+    fn test_strip_verbatim_prefix() {
+        assert_eq!(
+            strip_verbatim_prefix(Path::new(r"\\?\C:\src")),
+            Path::new(r"C:\src")
+        );
+        assert_eq!(
+            strip_verbatim_prefix(Path::new(r"\\?\UNC\server\share")),
+            Path::new(r"\\server\share")
+        );
+        assert_eq!(strip_verbatim_prefix(Path::new("/src")), Path::new("/src"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_drive_letter_path_round_trips() {
+        use crate::lsif_data_cache::LsifDataCache;
+
+        let mut cache = LsifDataCache::default();
+        cache.cache_document(file_uri(Path::new(r"c:\project\src\main.rs")), 1);
+
+        let server_uri = Url::parse("file:///C%3A/project/src/main.rs").unwrap();
+        assert_eq!(cache.get_document_id(&normalize_uri(&server_uri)), Some(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_file_has_the_same_uri() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let file = dir.join("src/my lib.rs");
+        std::fs::write(&file, "").unwrap();
+        std::os::unix::fs::symlink(dir.join("src"), dir.join("link")).unwrap();
+
+        let expected = file_uri(&file);
+        assert_eq!(file_uri(&dir.join("link/my lib.rs")), expected);
+        assert_eq!(file_uri(&dir.join("link/../src/./my lib.rs")), expected);
+
+        let server_uri = Url::from_file_path(dir.join("link/my lib.rs")).unwrap();
+        assert!(server_uri.as_str().contains("%20"));
+        assert_eq!(normalize_uri(&server_uri), expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_same_file_with_spaces_and_unicode() {
+        let key = file_uri(Path::new("/src/my lib/café.rs"));
+
+        for uri in &[
+            "file:///src/my%20lib/caf%C3%A9.rs",
+            "file:///src/my lib/café.rs",
+            "file:///src/my%20lib/./caf%c3%a9.rs",
+        ] {
+            assert!(same_file(&Url::parse(uri).unwrap(), &key), "{}", uri);
+        }
+        assert!(!same_file(
+            &Url::parse("file:///src/my%20lib/cafe.rs").unwrap(),
+            &key
+        ));
+
+        let dir = dir_to_uri(Path::new("/src/my lib")).unwrap();
+        assert!(dir.as_str().ends_with('/'));
+        assert!(same_file(&dir, &file_uri(Path::new("/src/my lib"))));
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+};
+
+use crate::{
+    check::for_each_entry,
+    protocol::types::{key, Edge, Element, Entry, Vertex},
+    Result,
+};
+
+/// The number of IDs listed for each kind of problem in the summary.
+const MAX_LISTED_IDS: usize = 10;
+
+/// The problems that `verify-dump` found in the graph of a dump. IDs are kept as strings
+/// because a dump may use string IDs.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub vertices: usize,
+    pub edges: usize,
+    /// Edges from or to an ID that is not a vertex, as `(edge, missing vertex)`.
+    pub dangling_edges: Vec<(String, String)>,
+    /// Ranges that are not contained in any document.
+    pub orphan_ranges: Vec<String>,
+    /// Documents that contain no range. This is not an error, a file can have no symbols.
+    pub empty_documents: Vec<String>,
+    /// Vertices whose chain of `next` edges comes back to them.
+    pub next_cycles: Vec<String>,
+}
+
+impl Report {
+    /// Returns true if the dump has no integrity errors.
+    pub fn is_ok(&self) -> bool {
+        self.dangling_edges.is_empty()
+            && self.orphan_ranges.is_empty()
+            && self.next_cycles.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} vertices, {} edges", self.vertices, self.edges)?;
+        let dangling = self
+            .dangling_edges
+            .iter()
+            .map(|(edge, vertex)| format!("{} -> {}", edge, vertex))
+            .collect::<Vec<_>>();
+        write_ids(f, "dangling edges", &dangling)?;
+        write_ids(f, "orphan ranges", &self.orphan_ranges)?;
+        write_ids(f, "empty documents", &self.empty_documents)?;
+        write_ids(f, "cycles of next edges", &self.next_cycles)
+    }
+}
+
+/// Writes the number of the given IDs and the first few of them.
+fn write_ids(f: &mut fmt::Formatter<'_>, what: &str, ids: &[String]) -> fmt::Result {
+    write!(f, "{} {}", ids.len(), what)?;
+    if !ids.is_empty() {
+        write!(f, ": {}", ids[..ids.len().min(MAX_LISTED_IDS)].join(", "))?;
+        if ids.len() > MAX_LISTED_IDS {
+            write!(f, ", ...")?;
+        }
+    }
+    writeln!(f)
+}
+
+/// Builds the graph of a dump from its entries.
+#[derive(Default)]
+struct Graph {
+    vertices: HashSet<String>,
+    ranges: Vec<String>,
+    documents: Vec<String>,
+    /// The IDs of the edges and the vertices they connect, in order.
+    edges: Vec<(String, Vec<String>)>,
+    contained: HashSet<String>,
+    containers: HashSet<String>,
+    next: HashMap<String, String>,
+}
+
+impl Graph {
+    fn add(&mut self, entry: &Entry) {
+        let id = key(&entry.id);
+        match &entry.data {
+            Element::Vertex(vertex) => {
+                match vertex {
+                    Vertex::Range(_) => self.ranges.push(id.clone()),
+                    Vertex::Document(_) => self.documents.push(id.clone()),
+                    _ => {}
+                }
+                self.vertices.insert(id);
+            }
+            Element::Edge(edge) => {
+                if let Edge::Contains(data) = edge {
+                    self.containers.insert(key(&data.out_v));
+                    self.contained.extend(data.in_vs.iter().map(key));
+                }
+                if let Edge::Next(data) = edge {
+                    self.next.insert(key(&data.out_v), key(&data.in_v));
+                }
+                self.edges.push((id, edge.vertex_keys()));
+            }
+        }
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report {
+            vertices: self.vertices.len(),
+            edges: self.edges.len(),
+            ..Report::default()
+        };
+        for (edge, vertices) in &self.edges {
+            for vertex in vertices {
+                if !self.vertices.contains(vertex) {
+                    report.dangling_edges.push((edge.clone(), vertex.clone()));
+                }
+            }
+        }
+        report.orphan_ranges = self
+            .ranges
+            .iter()
+            .filter(|range| !self.contained.contains(*range))
+            .cloned()
+            .collect();
+        report.empty_documents = self
+            .documents
+            .iter()
+            .filter(|document| !self.containers.contains(*document))
+            .cloned()
+            .collect();
+        report.next_cycles = self.next_cycles();
+        report
+    }
+
+    /// Returns a vertex of each cycle of `next` edges.
+    fn next_cycles(&self) -> Vec<String> {
+        let mut cycles = Vec::new();
+        let mut done = HashSet::new();
+        let mut starts = self.next.keys().collect::<Vec<_>>();
+        starts.sort();
+        for start in starts {
+            let mut path = HashSet::new();
+            let mut current = start;
+            while !done.contains(current) {
+                if !path.insert(current) {
+                    cycles.push(current.clone());
+                    break;
+                }
+                match self.next.get(current) {
+                    Some(next) => current = next,
+                    None => break,
+                }
+            }
+            done.extend(path);
+        }
+        cycles
+    }
+}
+
+/// Reads the dump in the given files, e.g. the shards of a dump in order, and checks the
+/// integrity of its graph. Returns an error if an entry is not valid.
+pub fn verify_dump<P: AsRef<Path>>(paths: &[P]) -> Result<Report> {
+    let mut graph = Graph::default();
+    for_each_entry(paths, |entry| graph.add(entry))?;
+    Ok(graph.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use languageserver_types::{Position, Range, Url};
+
+    use crate::{
+        edge,
+        protocol::types::{
+            Document, Edge, EdgeData, Element, Entry, NumberOrString, RangeVertex, ResultSet,
+        },
+    };
+
+    use super::Graph;
+
+    fn graph(elements: Vec<Element>) -> Graph {
+        let mut graph = Graph::default();
+        for (id, data) in elements.into_iter().enumerate() {
+            graph.add(&Entry {
+                id: NumberOrString::Number(id as u64 + 1),
+                data,
+            });
+        }
+        graph
+    }
+
+    fn document() -> Element {
+        Element::Vertex(
+            Document {
+                uri: Url::parse("file:///src/main.rs").unwrap(),
+                language_id: "rust".to_string(),
+                content_hash: None,
+            }
+            .into(),
+        )
+    }
+
+    fn range() -> Element {
+        Element::Vertex(
+            RangeVertex {
+                range: Range::new(Position::new(0, 0), Position::new(0, 3)),
+                tag: None,
+                offsets: None,
+                renameable: None,
+            }
+            .into(),
+        )
+    }
+
+    #[test]
+    fn test_valid_graph() {
+        let (document_id, range_id, result_set_id) = (1, 2, 3);
+        let report = graph(vec![
+            document(),
+            range(),
+            Element::Vertex(ResultSet {}.into()),
+            Element::Edge(edge!(Next, range_id -> result_set_id)),
+            Element::Edge(Edge::contains(document_id, vec![range_id])),
+        ])
+        .report();
+
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.vertices, 3);
+        assert_eq!(report.edges, 2);
+    }
+
+    #[test]
+    fn test_integrity_errors() {
+        let (range_id, first, second, missing) = (2, 3, 4, 99);
+        let report = graph(vec![
+            document(),
+            range(),
+            Element::Vertex(ResultSet {}.into()),
+            Element::Vertex(ResultSet {}.into()),
+            Element::Edge(edge!(Next, range_id -> first)),
+            Element::Edge(edge!(Next, first -> second)),
+            Element::Edge(edge!(Next, second -> first)),
+            Element::Edge(edge!(Hover, first -> missing)),
+        ])
+        .report();
+
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.dangling_edges,
+            vec![("8".to_string(), "99".to_string())]
+        );
+        assert_eq!(report.orphan_ranges, vec!["2"]);
+        assert_eq!(report.empty_documents, vec!["1"]);
+        assert_eq!(report.next_cycles.len(), 1);
+    }
+}